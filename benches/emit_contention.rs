@@ -0,0 +1,82 @@
+//! Emitting from several threads while others keep reading the global buffer, storing each
+//! event under the write lock versus staging it for the drainer thread.
+//!
+//! Run with `cargo bench --bench emit_contention`.
+
+use {
+    std::{
+        hint::black_box,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
+        thread,
+        time::{Duration, Instant},
+    },
+    tracing::Level,
+    tracing_spanner::{
+        capture, clear_global_events, flush_staged, get_event_summary, get_global_event_count,
+        init_global_event_manager_with_count, set_staged_emit,
+    },
+};
+
+const EMITTERS: usize = 4;
+const READERS: usize = 4;
+const EVENTS_PER_EMITTER: usize = 50_000;
+
+fn run(staged: bool) -> (Duration, usize) {
+    clear_global_events();
+    set_staged_emit(staged);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let readers: Vec<_> = (0..READERS)
+        .map(|_| {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut reads = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    black_box(get_global_event_count());
+                    black_box(get_event_summary());
+                    reads += 1;
+                }
+                reads
+            })
+        })
+        .collect();
+
+    let started = Instant::now();
+    let emitters: Vec<_> = (0..EMITTERS)
+        .map(|_| {
+            thread::spawn(|| {
+                for index in 0..EVENTS_PER_EMITTER {
+                    capture(Level::INFO, "bench", format!("event {}", index));
+                }
+            })
+        })
+        .collect();
+    emitters.into_iter().for_each(|emitter| emitter.join().unwrap());
+    let emitting = started.elapsed();
+
+    flush_staged();
+    stop.store(true, Ordering::Relaxed);
+    let reads = readers.into_iter().map(|reader| reader.join().unwrap()).sum();
+    set_staged_emit(false);
+    (emitting, reads)
+}
+
+fn main() {
+    init_global_event_manager_with_count(100_000);
+    let events = EMITTERS * EVENTS_PER_EMITTER;
+    println!("{} emitter threads ({} events) against {} reader threads", EMITTERS, events, READERS);
+
+    for (name, staged) in [("write lock", false), ("staged", true)] {
+        let (emitting, reads) = run(staged);
+        println!(
+            "  {:<12} {:>10.1?} emitting, {:>6.0?} per event, {} reads meanwhile",
+            name,
+            emitting,
+            emitting / events as u32,
+            reads
+        );
+    }
+}
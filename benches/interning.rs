@@ -0,0 +1,67 @@
+//! Memory used by a buffer full of events from the same few spans, with and without interning.
+//!
+//! Run with `cargo bench --bench interning`.
+
+use {
+    std::{
+        alloc::{GlobalAlloc, Layout, System},
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+    tracing::Level,
+    tracing_spanner::{Event, EventData, EventManager, SpanInfo},
+};
+
+struct Counting;
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+const EVENTS: usize = 10_000;
+
+/// An event as the layer captures it, with freshly allocated strings
+fn event(index: usize) -> Event {
+    let span = |name: &str| {
+        SpanInfo::new(index as u64, name.to_string(), "my_service::http::handlers".to_string(), Level::INFO)
+            .with_module_path("my_service::http::handlers::users".to_string())
+    };
+
+    let data = EventData::new(format!("request {}", index), Level::INFO, "my_service::db::queries".to_string())
+        .with_module_path("my_service::db::queries::users".to_string());
+    Event::new(data).with_span_stack(vec![span("handle_request"), span("db_query")]).with_current_span(span("db_query"))
+}
+
+fn live_bytes_after(fill: impl FnOnce() -> Box<dyn std::any::Any>) -> usize {
+    let before = LIVE_BYTES.load(Ordering::Relaxed);
+    let buffer = fill();
+    let used = LIVE_BYTES.load(Ordering::Relaxed) - before;
+    drop(buffer);
+    used
+}
+
+fn main() {
+    let plain = live_bytes_after(|| Box::new((0..EVENTS).map(event).collect::<Vec<_>>()));
+    let interned = live_bytes_after(|| {
+        let mut manager = EventManager::new(Some(EVENTS));
+        (0..EVENTS).for_each(|index| manager.push(event(index)));
+        Box::new(manager)
+    });
+
+    println!("{} events with 3 spans each", EVENTS);
+    println!("  without interning: {:>10} bytes", plain);
+    println!("  interned manager:  {:>10} bytes", interned);
+    println!("  reduction:         {:>9.1}%", 100.0 * (1.0 - interned as f64 / plain as f64));
+}
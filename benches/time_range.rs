@@ -0,0 +1,49 @@
+//! Time range queries on a full buffer, scanning every event versus binary searching the
+//! time-sorted default store.
+//!
+//! Run with `cargo bench --bench time_range`.
+
+use {
+    chrono::{TimeDelta, Utc},
+    std::{hint::black_box, time::Instant},
+    tracing::Level,
+    tracing_spanner::{Event, EventData, EventManager, SearchQuery},
+};
+
+const EVENTS: usize = 100_000;
+const QUERIES: usize = 200;
+
+fn main() {
+    let start = Utc::now() - TimeDelta::seconds(EVENTS as i64);
+    let mut manager = EventManager::new(Some(EVENTS));
+    for index in 0..EVENTS {
+        let mut data = EventData::new(format!("event {}", index), Level::INFO, "bench".to_string());
+        data.timestamp = start + TimeDelta::seconds(index as i64);
+        manager.push(Event::new(data));
+    }
+
+    // A one minute window at a different point of the buffer for each query
+    let windows: Vec<_> = (0..QUERIES)
+        .map(|query| {
+            let from = start + TimeDelta::seconds((query * EVENTS / QUERIES) as i64);
+            (from, from + TimeDelta::seconds(60))
+        })
+        .collect();
+
+    let timed = |name: &str, range: &dyn Fn(usize) -> usize| {
+        let started = Instant::now();
+        let found: usize = (0..QUERIES).map(|query| black_box(range(query))).sum();
+        let elapsed = started.elapsed();
+        println!("  {:<14} {:>10.1?} per query ({} events found)", name, elapsed / QUERIES as u32, found);
+    };
+
+    println!("{} one minute range queries over {} events", QUERIES, EVENTS);
+    timed("linear scan:", &|query| {
+        let (from, to) = windows[query];
+        manager.query(&SearchQuery::new().after(from).before(to)).len()
+    });
+    timed("binary search:", &|query| {
+        let (from, to) = windows[query];
+        manager.get_in_range(from, to).len()
+    });
+}
@@ -0,0 +1,218 @@
+use {
+    crate::event::Event,
+    std::{
+        cell::RefCell,
+        collections::BTreeMap,
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+    },
+    tracing::Span,
+};
+
+thread_local! {
+    static CORRELATION_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+    /// Values set with [`push_context`] on this thread, innermost last for each key
+    static DIAGNOSTIC_CONTEXT: RefCell<BTreeMap<String, Vec<String>>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Correlation ID and span captured on one thread, to be re-established on another.
+///
+/// See [`capture_context`] and [`with_captured_context`].
+#[derive(Debug, Clone)]
+pub struct SpanContext {
+    pub correlation_id: String,
+    /// The span that was current when captured, entered again wherever the context is used
+    pub span: Span,
+}
+
+impl SpanContext {
+    /// Run a future with this context re-established on every poll.
+    ///
+    /// Use it to carry a request's context into spawned tasks, e.g.
+    /// `tokio::spawn(capture_context().scope(async move { .. }))`, since thread-local state
+    /// doesn't follow a task between threads on its own.
+    pub fn scope<F: Future>(self, future: F) -> WithContext<F> { WithContext { context: self, future: Box::pin(future) } }
+}
+
+/// Future returned by [`SpanContext::scope`]
+pub struct WithContext<F> {
+    context: SpanContext,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for WithContext<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        with_captured_context(&this.context, || this.future.as_mut().poll(cx))
+    }
+}
+
+/// Snapshot the current correlation ID and span so work on another thread or task can share them.
+///
+/// Outside any [`with_captured_context`] scope a new correlation ID is generated; run the
+/// originating work inside that same context too if its own events should share the ID.
+///
+/// ```
+/// use tracing_spanner::{capture_context, with_captured_context};
+///
+/// let context = capture_context();
+/// let worker = context.clone();
+/// with_captured_context(&context, || {
+///     tracing::info!("handling request");
+///     std::thread::spawn(move || with_captured_context(&worker, || tracing::info!("in worker"))).join().unwrap();
+/// });
+/// ```
+pub fn capture_context() -> SpanContext {
+    SpanContext {
+        correlation_id: current_correlation_id().unwrap_or_else(|| format!("corr-{}", uuid::Uuid::new_v4())),
+        span: Span::current(),
+    }
+}
+
+/// Run `f` with a captured context: its span is entered and events captured on this thread
+/// use its correlation ID. The previous context is restored afterwards.
+pub fn with_captured_context<R>(context: &SpanContext, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<String>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) { CORRELATION_ID.with(|id| *id.borrow_mut() = self.0.take()); }
+    }
+
+    let _restore = Restore(CORRELATION_ID.with(|id| id.borrow_mut().replace(context.correlation_id.clone())));
+    context.span.in_scope(f)
+}
+
+/// The correlation ID established on this thread by [`with_captured_context`], if any
+pub fn current_correlation_id() -> Option<String> { CORRELATION_ID.with(|id| id.borrow().clone()) }
+
+/// Attach `key = value` to the `custom_metadata` of every event captured on this thread
+/// until the matching [`pop_context`], like a logging framework's mapped diagnostic context.
+///
+/// E.g. push a `request_id` once when a request starts and every event handling it carries
+/// the ID. Pushing a key again shadows its value until popped, so nested scopes each see their
+/// own. The context belongs to the thread and doesn't follow async tasks to other threads.
+pub fn push_context(key: impl Into<String>, value: impl Into<String>) {
+    DIAGNOSTIC_CONTEXT.with(|context| context.borrow_mut().entry(key.into()).or_default().push(value.into()));
+}
+
+/// Remove the innermost value pushed for `key` on this thread, restoring the one before it
+pub fn pop_context(key: &str) -> Option<String> {
+    DIAGNOSTIC_CONTEXT.with(|context| {
+        let mut context = context.borrow_mut();
+        let values = context.get_mut(key)?;
+        let value = values.pop();
+        if values.is_empty() {
+            context.remove(key);
+        }
+        value
+    })
+}
+
+/// The context set on this thread with [`push_context`], innermost value per key
+pub fn current_context() -> BTreeMap<String, String> {
+    DIAGNOSTIC_CONTEXT.with(|context| {
+        context.borrow().iter().filter_map(|(key, values)| Some((key.clone(), values.last()?.clone()))).collect()
+    })
+}
+
+/// Add this thread's diagnostic context to an event's metadata, keeping keys it already has
+pub(crate) fn apply_context(event: &mut Event) {
+    DIAGNOSTIC_CONTEXT.with(|context| {
+        for (key, values) in context.borrow().iter() {
+            if let Some(value) = values.last() {
+                event.custom_metadata.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            SpannerLayer,
+            manager::{get_global_events, global_test_guard, init_global_event_manager},
+        },
+        tracing::{Dispatch, dispatcher, info, info_span},
+        tracing_subscriber::{Registry, layer::SubscriberExt},
+    };
+
+    #[test]
+    fn test_diagnostic_context() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        let target = "spanner_test::diagnostic_context";
+        let metadata = || {
+            let events = get_global_events().unwrap_or_default();
+            let newest = events.into_iter().find(|e| e.event_data.target == target).unwrap();
+            let mut metadata: Vec<_> = newest.custom_metadata.into_iter().collect();
+            metadata.sort();
+            metadata
+        };
+        let pair = |key: &str, value: &str| (key.to_string(), value.to_string());
+
+        tracing::subscriber::with_default(Registry::default().with(SpannerLayer::new()), || {
+            push_context("request_id", "r-1");
+            info!(target: "spanner_test::diagnostic_context", "outer");
+            assert_eq!(metadata(), [pair("request_id", "r-1")]);
+
+            push_context("request_id", "r-2");
+            push_context("user", "ada");
+            info!(target: "spanner_test::diagnostic_context", "nested");
+            assert_eq!(metadata(), [pair("request_id", "r-2"), pair("user", "ada")]);
+
+            assert_eq!(pop_context("user").as_deref(), Some("ada"));
+            assert_eq!(pop_context("request_id").as_deref(), Some("r-2"));
+            info!(target: "spanner_test::diagnostic_context", "restored");
+            assert_eq!(metadata(), [pair("request_id", "r-1")]);
+
+            pop_context("request_id");
+            info!(target: "spanner_test::diagnostic_context", "cleared");
+            assert_eq!(metadata(), []);
+        });
+        assert!(current_context().is_empty());
+        assert_eq!(pop_context("request_id"), None);
+    }
+
+    #[test]
+    fn test_worker_inherits_captured_context() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        let dispatch = Dispatch::new(Registry::default().with(SpannerLayer::new()));
+
+        let context = dispatcher::with_default(&dispatch, || {
+            let request = info_span!("request");
+            let _entered = request.enter();
+            let context = capture_context();
+
+            with_captured_context(&context, || info!(target: "spanner_test::context", "parent"));
+
+            let (worker_context, worker_dispatch) = (context.clone(), dispatch.clone());
+            std::thread::spawn(move || {
+                dispatcher::with_default(&worker_dispatch, || {
+                    let task = worker_context.scope(async { info!(target: "spanner_test::context", "task") });
+                    futures::executor::block_on(task);
+                })
+            })
+            .join()
+            .unwrap();
+            context
+        });
+        assert_eq!(current_correlation_id(), None, "the previous context is restored");
+
+        let events: Vec<_> = get_global_events()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|e| e.event_data.target == "spanner_test::context")
+            .collect();
+        assert_eq!(events.len(), 2);
+        for event in &events {
+            assert_eq!(event.correlation_id.as_deref(), Some(context.correlation_id.as_str()));
+            assert_eq!(event.current_span.as_ref().map(|span| span.name.as_str()), Some("request"));
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::fmt;
+
+/// Errors surfaced by Spanner's public API
+#[derive(Debug)]
+pub enum SpannerError {
+    /// A global tracing subscriber was already installed, by Spanner or anyone else
+    AlreadyInitialized,
+    /// An event or export couldn't be serialized
+    Serialization(serde_json::Error),
+    /// A search pattern isn't a valid regular expression
+    InvalidRegex(Box<dyn std::error::Error + Send + Sync>),
+    /// The operation needs the named crate feature, which isn't enabled
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for SpannerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpannerError::AlreadyInitialized => write!(f, "a global tracing subscriber is already initialized"),
+            SpannerError::Serialization(e) => write!(f, "failed to serialize: {}", e),
+            SpannerError::InvalidRegex(e) => write!(f, "invalid search pattern: {}", e),
+            SpannerError::Unsupported(feature) => write!(f, "needs the `{}` feature", feature),
+        }
+    }
+}
+
+impl std::error::Error for SpannerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SpannerError::AlreadyInitialized => None,
+            SpannerError::Serialization(e) => Some(e),
+            SpannerError::InvalidRegex(e) => Some(e.as_ref()),
+            SpannerError::Unsupported(_) => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for SpannerError {
+    fn from(e: serde_json::Error) -> Self { SpannerError::Serialization(e) }
+}
+
+#[cfg(feature = "regex")]
+impl From<regex::Error> for SpannerError {
+    fn from(e: regex::Error) -> Self { SpannerError::InvalidRegex(Box::new(e)) }
+}
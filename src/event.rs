@@ -1,16 +1,198 @@
 use {
-    crate::{event_data::EventData, span::SpanInfo},
+    crate::{
+        event_data::EventData,
+        query::{Pattern, SearchQuery},
+        span::SpanInfo,
+    },
+    chrono::{DateTime, SecondsFormat, TimeDelta, Utc},
     serde::{Deserialize, Serialize},
     std::{
-        collections::HashMap,
-        sync::Arc,
-        time::{SystemTime, UNIX_EPOCH},
+        collections::{BTreeMap, HashMap},
+        fmt,
+        hash::{DefaultHasher, Hash, Hasher},
+        sync::{
+            Arc, RwLock,
+            atomic::{AtomicU64, Ordering},
+        },
+        time::Duration,
     },
     tracing::Level,
 };
 
+static NEXT_EVENT_ID: AtomicU64 = AtomicU64::new(1);
+static TARGET_ALIASES: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+static SESSION_LABELS: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+/// Set labels describing this process or session, such as `env=prod` or `service=checkout`,
+/// replacing any previous ones.
+///
+/// Labels apply to every live event without being stored on each one. Exports record them
+/// once in [`ExportMetadata::labels`](crate::ExportMetadata::labels), and imported events are
+/// stamped with their dump's labels so [`SearchQuery::label`] can tell merged dumps apart.
+pub fn set_session_labels(labels: BTreeMap<String, String>) {
+    *SESSION_LABELS.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = labels;
+}
+
+/// Get the labels set with [`set_session_labels`]
+pub fn session_labels() -> BTreeMap<String, String> {
+    SESSION_LABELS.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+/// Set display names for target prefixes, replacing any previous aliases.
+///
+/// A key matches a target equal to it or nested below it (`a::b` matches `a::b::c` but not
+/// `a::bc`), and the longest matching key wins. Aliases only affect display, such as
+/// [`Event::display_target`]; stored and exported events keep their original targets.
+pub fn set_target_aliases(aliases: BTreeMap<String, String>) {
+    *TARGET_ALIASES.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = aliases;
+}
+
+/// Reduce a message to a template so messages differing only in ids and numbers compare
+/// equal, as used by [`EventManager::top_error_signatures`](crate::EventManager::top_error_signatures).
+///
+/// Within runs of ASCII letters and digits, UUIDs (`8-4-4-4-12` hex digits) become `<uuid>`,
+/// `0x`-prefixed hex and hex runs of 8 or more characters with at least one digit become
+/// `<hex>`, and any other run of digits becomes `<n>`, so `retry 3 after 250ms` reads
+/// `retry <n> after <n>ms`. Everything else is kept as is.
+pub fn error_signature(message: &str) -> String {
+    let mut signature = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(c) = rest.chars().next() {
+        if !c.is_ascii_alphanumeric() {
+            signature.push(c);
+            rest = &rest[c.len_utf8()..];
+        } else if rest.get(..36).is_some_and(is_uuid) {
+            signature.push_str("<uuid>");
+            rest = &rest[36..];
+        } else {
+            let (word, tail) = rest.split_at(rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len()));
+            push_word_signature(&mut signature, word);
+            rest = tail;
+        }
+    }
+    signature
+}
+
+fn is_uuid(text: &str) -> bool {
+    text.bytes().enumerate().all(|(i, b)| if matches!(i, 8 | 13 | 18 | 23) { b == b'-' } else { b.is_ascii_hexdigit() })
+}
+
+fn push_word_signature(signature: &mut String, word: &str) {
+    let is_hex = |digits: &str| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_hexdigit());
+    let prefixed = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X"));
+    if prefixed.is_some_and(is_hex) || (word.len() >= 8 && is_hex(word) && word.bytes().any(|b| b.is_ascii_digit())) {
+        signature.push_str("<hex>");
+        return;
+    }
+
+    let mut in_number = false;
+    for c in word.chars() {
+        let digit = c.is_ascii_digit();
+        if !digit {
+            signature.push(c);
+        } else if !in_number {
+            signature.push_str("<n>");
+        }
+        in_number = digit;
+    }
+}
+
+/// How far in the future an event timestamp may be before [`Event::validate`] rejects it,
+/// allowing for clock skew between machines
+const MAX_CLOCK_SKEW: TimeDelta = TimeDelta::minutes(1);
+
+/// Single-line rendering of an event, see [`Event::to_line`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineFormat {
+    /// `2025-01-01T12:00:00.123Z INFO app::db: query done rows=5 table=users`
+    #[default]
+    Compact,
+    /// The whole event as one line of JSON, as in exports
+    Json,
+}
+
+/// What produced an event, so consumers can tell raw log events from synthetic ones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EventKind {
+    /// An event logged through `tracing` or captured directly
+    #[default]
+    Log,
+    /// A span was opened
+    SpanOpen,
+    /// A span was closed
+    SpanClose,
+    /// A point in time marked by the application
+    Marker,
+    /// A summary derived from a span, e.g. a [busy span warning](crate::SpannerLayer::with_busy_threshold)
+    SpanSummary,
+    /// A panic, see [`install_panic_capture`](crate::install_panic_capture)
+    Panic,
+}
+
+/// Which parts of an event make up its identity, see [`Event::content_key`].
+///
+/// Level, target and message always take part. The default leaves out what differs between
+/// repeats of the same log line (timestamp, correlation id, thread and event id), so repeats
+/// share a key. Including more makes events compare distinct more often, e.g. with
+/// `include_timestamp` only events captured at the same instant are the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdentityConfig {
+    pub include_fields: bool,
+    /// Source file and line
+    pub include_location: bool,
+    /// Names of the enclosing spans, outermost first
+    pub include_span_path: bool,
+    pub include_timestamp: bool,
+    pub include_correlation_id: bool,
+}
+
+impl Default for IdentityConfig {
+    fn default() -> Self {
+        Self {
+            include_fields: true,
+            include_location: true,
+            include_span_path: true,
+            include_timestamp: false,
+            include_correlation_id: false,
+        }
+    }
+}
+
+/// An inconsistency found by [`Event::validate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// `current_span` is set but isn't the innermost entry of `span_stack`
+    CurrentSpanNotInStack { span_id: u64 },
+    /// `span_stack` has entries but `current_span` is unset
+    MissingCurrentSpan,
+    /// Neither a message nor any fields
+    EmptyEvent,
+    /// The timestamp is more than a minute in the future
+    TimestampInFuture,
+    /// The event is timestamped before a span it fired in was entered
+    TimestampBeforeSpan { span_id: u64 },
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CurrentSpanNotInStack { span_id } => {
+                write!(f, "current span {} is not the innermost span of the stack", span_id)
+            }
+            Self::MissingCurrentSpan => write!(f, "span stack is set without a current span"),
+            Self::EmptyEvent => write!(f, "event has neither a message nor fields"),
+            Self::TimestampInFuture => write!(f, "timestamp is in the future"),
+            Self::TimestampBeforeSpan { span_id } => write!(f, "timestamp is before span {} was entered", span_id),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Event {
+    /// Identifier unique within this process, used to find an event's parent among others.
+    /// Events imported from dumps of other processes may share ids with local ones.
+    #[serde(default)]
+    pub id: u64,
     #[serde(skip)]
     pub parent: Option<Arc<Event>>,
     pub event_data: EventData,
@@ -20,12 +202,28 @@ pub struct Event {
     pub thread_name: Option<String>,
     pub process_id: Option<u32>,
     pub correlation_id: Option<String>,
+    /// Whether `correlation_id` was generated for this event alone rather than shared, see
+    /// [`Event::with_generated_correlation_id`]
+    #[serde(skip)]
+    pub correlation_id_generated: bool,
+    /// Name of the async task the event fired in, see [`SpanInfo::task_name`]
+    #[serde(default)]
+    pub task_name: Option<String>,
+    /// What produced the event; dumps that predate it load as [`EventKind::Log`]
+    #[serde(default)]
+    pub kind: EventKind,
+    #[serde(serialize_with = "sorted_map")]
     pub custom_metadata: HashMap<String, String>,
+    /// Session labels of the dump this event was imported from; empty for live events, which
+    /// carry the current [`session_labels`] implicitly, see [`Event::effective_labels`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
 }
 
 impl Event {
     pub fn new(event_data: EventData) -> Self {
         Self {
+            id: NEXT_EVENT_ID.fetch_add(1, Ordering::Relaxed),
             parent: None,
             event_data,
             span_stack: Vec::new(),
@@ -34,10 +232,18 @@ impl Event {
             thread_name: None,
             process_id: None,
             correlation_id: None,
+            correlation_id_generated: false,
+            task_name: None,
+            kind: EventKind::Log,
             custom_metadata: HashMap::new(),
+            labels: BTreeMap::new(),
         }
     }
 
+    /// Identifier assigned when the event was created. Clones share it, and it survives export
+    /// and import, so a UI can refer to a specific event across both.
+    pub fn id(&self) -> u64 { self.id }
+
     pub fn with_parent(mut self, parent: Arc<Event>) -> Self {
         self.parent = Some(parent);
         self
@@ -59,6 +265,11 @@ impl Event {
         self
     }
 
+    pub fn with_kind(mut self, kind: EventKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
     pub fn with_process_id(mut self, pid: u32) -> Self {
         self.process_id = Some(pid);
         self
@@ -66,17 +277,188 @@ impl Event {
 
     pub fn with_correlation_id(mut self, correlation_id: String) -> Self {
         self.correlation_id = Some(correlation_id);
+        self.correlation_id_generated = false;
+        self
+    }
+
+    /// Give the event a fresh correlation id of its own. No other event shares it, so
+    /// correlation retention doesn't open a trace for it.
+    pub fn with_generated_correlation_id(mut self) -> Self {
+        self.correlation_id = Some(format!("corr-{}", uuid::Uuid::new_v4()));
+        self.correlation_id_generated = true;
+        self
+    }
+
+    pub fn with_task_name(mut self, task_name: String) -> Self {
+        self.task_name = Some(task_name);
         self
     }
 
     pub fn add_metadata(&mut self, key: String, value: String) { self.custom_metadata.insert(key, value); }
 
+    /// The event target with the longest matching alias from [`set_target_aliases`] applied.
+    ///
+    /// Any path below the aliased prefix is kept, so with `my_crate::billing` aliased to
+    /// `Billing`, the target `my_crate::billing::stripe` displays as `Billing::stripe`.
+    pub fn display_target(&self) -> String {
+        let target = &self.event_data.target;
+        let aliases = TARGET_ALIASES.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        aliases
+            .iter()
+            .filter_map(|(prefix, alias)| {
+                let rest = target.strip_prefix(prefix.as_str())?;
+                (rest.is_empty() || rest.starts_with("::")).then(|| (prefix.len(), format!("{}{}", alias, rest)))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map_or_else(|| target.to_string(), |(_, display)| display)
+    }
+
+    /// Time since the event's timestamp, e.g. for "5s ago" displays. Zero for timestamps in
+    /// the future, such as events from a machine whose clock runs ahead.
+    pub fn age(&self) -> Duration { (Utc::now() - self.event_data.timestamp).to_std().unwrap_or_default() }
+
+    /// The message to show for this event, synthesized when it was logged without one.
+    ///
+    /// An empty message falls back to the first field by key (`user_id=5`), then to the
+    /// current span (`in span handler`), then to `(no message)`.
+    pub fn display_message(&self) -> String {
+        if !self.event_data.message.is_empty() {
+            return self.event_data.message.clone();
+        }
+        if let Some((key, value)) = self.event_data.fields.iter().min() {
+            return format!("{}={}", key, value.trim_matches('"'));
+        }
+        match &self.current_span {
+            Some(span) => format!("in span {}", span.display_name()),
+            None => "(no message)".to_string(),
+        }
+    }
+
+    /// Short single-line summary for outbound notifications, at most `max_len` characters.
+    ///
+    /// Formatted as `LEVEL target [corr=.. key=value ..]: message`, using the display target,
+    /// the correlation id and the first two fields by key. All whitespace, including newlines,
+    /// is collapsed to single spaces, and the summary is cut with `…` if it is too long.
+    pub fn notification_summary(&self, max_len: usize) -> String {
+        let mut identifiers: Vec<String> = self.correlation_id.iter().map(|id| format!("corr={}", id)).collect();
+        let mut fields: Vec<_> = self.event_data.fields.iter().collect();
+        fields.sort();
+        identifiers.extend(fields.into_iter().take(2).map(|(k, v)| format!("{}={}", k, v.trim_matches('"'))));
+
+        let mut summary = format!("{} {}", self.event_data.level(), self.display_target());
+        if !identifiers.is_empty() {
+            summary.push_str(&format!(" [{}]", identifiers.join(" ")));
+        }
+        summary.push_str(&format!(": {}", self.display_message()));
+
+        let summary = summary.split_whitespace().collect::<Vec<_>>().join(" ");
+        if summary.chars().count() <= max_len {
+            return summary;
+        }
+        let mut truncated: String = summary.chars().take(max_len.saturating_sub(1)).collect();
+        if max_len > 0 {
+            truncated.push('…');
+        }
+        truncated
+    }
+
+    /// Render the event as a single line, without a trailing newline.
+    ///
+    /// The compact format is the timestamp, level, display target, display message and fields
+    /// sorted by key, with newlines in the message escaped as `\n`.
+    pub fn to_line(&self, format: LineFormat) -> String {
+        match format {
+            LineFormat::Json => serde_json::to_string(self).unwrap_or_default(),
+            LineFormat::Compact => {
+                let mut line = format!(
+                    "{} {} {}: {}",
+                    self.event_data.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+                    self.event_data.level(),
+                    self.display_target(),
+                    self.display_message().replace('\n', "\\n")
+                );
+                let mut fields: Vec<_> = self.event_data.fields.iter().collect();
+                fields.sort();
+                for (key, value) in fields {
+                    line.push_str(&format!(" {}={}", key, value.trim_matches('"').replace('\n', "\\n")));
+                }
+                line
+            }
+        }
+    }
+
+    /// Render the event as a compact line prefixed with `indent` once per enclosing span, so a
+    /// live tail of nested events reads like a tree
+    pub fn to_indented_line(&self, indent: &str) -> String {
+        indent.repeat(self.span_stack.len()) + &self.to_line(LineFormat::Compact)
+    }
+
+    /// Check the invariants events captured by the layer always satisfy, e.g. after importing
+    /// or building events by hand.
+    ///
+    /// `current_span` must be the innermost (last) entry of `span_stack`, and both are set or
+    /// both empty. The event needs a message or at least one field, and its timestamp can't be
+    /// in the future (beyond a minute of clock skew) or before any of its spans were entered.
+    pub fn validate(&self) -> Result<(), Vec<ValidationIssue>> {
+        let mut issues = Vec::new();
+
+        match (&self.current_span, self.span_stack.last()) {
+            (Some(current), innermost) if innermost.is_none_or(|span| span.id != current.id) => {
+                issues.push(ValidationIssue::CurrentSpanNotInStack { span_id: current.id })
+            }
+            (None, Some(_)) => issues.push(ValidationIssue::MissingCurrentSpan),
+            _ => {}
+        }
+
+        if self.event_data.message.is_empty() && self.event_data.fields.is_empty() {
+            issues.push(ValidationIssue::EmptyEvent);
+        }
+
+        let timestamp = self.event_data.timestamp;
+        if timestamp > Utc::now() + MAX_CLOCK_SKEW {
+            issues.push(ValidationIssue::TimestampInFuture);
+        }
+        issues.extend(
+            self.span_stack
+                .iter()
+                .filter(|span| timestamp < span.entered_at)
+                .map(|span| ValidationIssue::TimestampBeforeSpan { span_id: span.id }),
+        );
+
+        if issues.is_empty() { Ok(()) } else { Err(issues) }
+    }
+
+    /// Approximate memory footprint of this event in bytes.
+    ///
+    /// Counts the inline struct size plus the length of every owned string, map entry and
+    /// captured span. Allocator overhead, spare capacity and the shared `parent` chain are
+    /// ignored, so treat it as a relative measure rather than exact usage.
+    pub fn estimated_size(&self) -> usize {
+        let optional = |value: &Option<String>| value.as_ref().map_or(0, String::len);
+        std::mem::size_of::<Self>()
+            + self.event_data.estimated_size()
+            + self.span_stack.iter().map(SpanInfo::estimated_size).sum::<usize>()
+            + self.current_span.as_ref().map_or(0, SpanInfo::estimated_size)
+            + optional(&self.thread_id)
+            + optional(&self.thread_name)
+            + optional(&self.correlation_id)
+            + optional(&self.task_name)
+            + map_size(&self.custom_metadata)
+            + self.labels.iter().map(|(k, v)| k.len() + v.len() + 2 * std::mem::size_of::<String>()).sum::<usize>()
+    }
+
+    /// The event's own labels if it was imported with some, otherwise the current session's
+    pub fn effective_labels(&self) -> BTreeMap<String, String> {
+        if self.labels.is_empty() { session_labels() } else { self.labels.clone() }
+    }
+
     /// Get the full span hierarchy as a formatted tree string
     pub fn get_span_tree(&self) -> String {
         let mut tree = String::new();
 
         if let Some(ref current) = self.current_span {
-            tree.push_str(&format!("Current Span: {} ({})\n", current.name, current.level()));
+            tree.push_str(&format!("Current Span: {} ({})\n", current.display_name(), current.level()));
         }
 
         if !self.span_stack.is_empty() {
@@ -86,7 +468,13 @@ impl Event {
                 let duration_str =
                     span.get_duration().map(|d| format!(" [{:.2?}]", d)).unwrap_or_else(|| " [active]".to_string());
 
-                tree.push_str(&format!("{}├─ {} ({}){}", indent, span.name, span.level(), duration_str));
+                tree.push_str(&format!("{}├─ {} ({}){}", indent, span.display_name(), span.level(), duration_str));
+                if span.event_count > 0 {
+                    tree.push_str(&format!(" events={}", span.event_count));
+                }
+                if span.resume_count > 0 {
+                    tree.push_str(&format!(" resumes={}", span.resume_count));
+                }
 
                 if !span.fields.is_empty() {
                     tree.push_str(" {");
@@ -111,7 +499,13 @@ impl Event {
         let indent = "  ".repeat(depth);
         let duration_str = span.get_duration().map(|d| format!(" [{:.2?}]", d)).unwrap_or_else(|| " [active]".to_string());
 
-        tree.push_str(&format!("{}├─ {} ({}){}", indent, span.name, span.level(), duration_str));
+        tree.push_str(&format!("{}├─ {} ({}){}", indent, span.display_name(), span.level(), duration_str));
+        if span.event_count > 0 {
+            tree.push_str(&format!(" events={}", span.event_count));
+        }
+        if span.resume_count > 0 {
+            tree.push_str(&format!(" resumes={}", span.resume_count));
+        }
 
         if !span.fields.is_empty() {
             tree.push_str(" {");
@@ -131,8 +525,13 @@ impl Event {
     pub fn get_full_context(&self) -> String {
         let mut context = String::new();
 
-        context.push_str(&format!("Event: {} ({})\n", self.event_data.message, self.event_data.level()));
-        context.push_str(&format!("Target: {}\n", self.event_data.target));
+        context.push_str(&format!("Event: {} ({})\n", self.display_message(), self.event_data.level()));
+        let display_target = self.display_target();
+        if self.event_data.target == display_target {
+            context.push_str(&format!("Target: {}\n", display_target));
+        } else {
+            context.push_str(&format!("Target: {} ({})\n", display_target, self.event_data.target));
+        }
         context.push_str(&format!("Timestamp: {:?}\n", self.event_data.timestamp));
 
         if let Some(ref file) = self.event_data.file {
@@ -155,6 +554,10 @@ impl Event {
             context.push_str(&format!("Correlation ID: {}\n", correlation_id));
         }
 
+        if let Some(ref task_name) = self.task_name {
+            context.push_str(&format!("Task: {}\n", task_name));
+        }
+
         if !self.event_data.fields.is_empty() {
             context.push_str("Event Fields:\n");
             for (k, v) in &self.event_data.fields {
@@ -188,33 +591,155 @@ impl Event {
         message_contains: Option<&str>,
         span_name_contains: Option<&str>,
     ) -> bool {
-        if let Some(level) = level_filter
-            && self.event_data.level() != level
+        self.matches_criteria_in_range(level_filter, target_filter, message_contains, span_name_contains, None, None)
+    }
+
+    /// Like [`Event::matches_criteria`], with the target and message given as compiled
+    /// regular expressions rather than substrings
+    pub fn matches_criteria_regex(
+        &self,
+        level_filter: Option<Level>,
+        target_pattern: Option<&Pattern>,
+        message_pattern: Option<&Pattern>,
+        span_name_contains: Option<&str>,
+    ) -> bool {
+        self.matches(&SearchQuery {
+            level: level_filter,
+            span_name: span_name_contains.map(String::from),
+            target_regex: target_pattern.cloned(),
+            message_regex: message_pattern.cloned(),
+            ..Default::default()
+        })
+    }
+
+    /// Like [`Event::matches_criteria`], also requiring the timestamp to be within `after`
+    /// and `before`, both inclusive
+    pub fn matches_criteria_in_range(
+        &self,
+        level_filter: Option<Level>,
+        target_filter: Option<&str>,
+        message_contains: Option<&str>,
+        span_name_contains: Option<&str>,
+        after: Option<DateTime<Utc>>,
+        before: Option<DateTime<Utc>>,
+    ) -> bool {
+        self.matches(&SearchQuery {
+            level: level_filter,
+            target: target_filter.map(String::from),
+            message: message_contains.map(String::from),
+            span_name: span_name_contains.map(String::from),
+            after,
+            before,
+            ..Default::default()
+        })
+    }
+
+    /// Hash of the parts of the event selected by `identity`, equal for events that count as
+    /// the same. Keys are only comparable within one build of the crate, so don't persist them.
+    pub fn content_key(&self, identity: &IdentityConfig) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let data = &self.event_data;
+        (data.level.0.as_str(), data.target.as_str(), data.message.as_str()).hash(&mut hasher);
+        if identity.include_fields {
+            data.fields.iter().collect::<BTreeMap<_, _>>().hash(&mut hasher);
+        }
+        if identity.include_location {
+            (&data.file, data.line).hash(&mut hasher);
+        }
+        if identity.include_span_path {
+            self.span_stack.iter().for_each(|span| span.display_name().hash(&mut hasher));
+        }
+        if identity.include_timestamp {
+            data.timestamp.hash(&mut hasher);
+        }
+        if identity.include_correlation_id {
+            self.correlation_id.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Check whether this event satisfies every criterion set on the query
+    pub fn matches(&self, query: &SearchQuery) -> bool {
+        let level = self.event_data.level();
+
+        if query.level.is_some_and(|expected| level != expected)
+            || query.exclude_levels.contains(&level)
+            || query.kind.is_some_and(|kind| self.kind != kind)
         {
             return false;
         }
 
-        if let Some(target) = target_filter
-            && !self.event_data.target.contains(target)
+        // `tracing` orders levels by verbosity, so "at least as severe" is `<=`
+        if query.min_level.is_some_and(|min| level > min) {
+            return false;
+        }
+
+        if let Some(target) = &query.target
+            && !self.event_data.target.contains(target.as_str())
         {
             return false;
         }
 
-        if let Some(message) = message_contains
-            && !self.event_data.message.contains(message)
+        if let Some(message) = &query.message
+            && !self.event_data.message.contains(message.as_str())
         {
             return false;
         }
 
-        if let Some(span_name) = span_name_contains {
+        if query.exclude_targets.iter().any(|target| self.event_data.target.contains(target.as_str()))
+            || query.exclude_messages.iter().any(|message| self.event_data.message.contains(message.as_str()))
+        {
+            return false;
+        }
+
+        if let Some(span_name) = &query.span_name {
             let has_matching_span =
-                self.span_stack.iter().chain(self.current_span.iter()).any(|span| span.name.contains(span_name));
+                self.span_stack.iter().chain(self.current_span.iter()).any(|span| span.name_contains(span_name));
             if !has_matching_span {
                 return false;
             }
         }
 
-        true
+        if !query.fields.iter().all(|filter| filter.matches(self.event_data.fields.get(&filter.key).map(String::as_str))) {
+            return false;
+        }
+
+        if !query.span_fields.iter().all(|filter| {
+            self.span_stack
+                .iter()
+                .chain(self.current_span.iter())
+                .any(|span| filter.matches(span.fields.get(&filter.key).map(String::as_str)))
+        }) {
+            return false;
+        }
+
+        if query.after.is_some_and(|after| self.event_data.timestamp < after)
+            || query.before.is_some_and(|before| self.event_data.timestamp > before)
+        {
+            return false;
+        }
+
+        if let Some(fuzzy) = &query.message_fuzzy
+            && !fuzzy.score(&self.event_data.message).is_ok_and(|score| score >= fuzzy.min_score)
+        {
+            return false;
+        }
+
+        if query.target_regex.as_ref().is_some_and(|pattern| !pattern.is_match(&self.event_data.target))
+            || query.message_regex.as_ref().is_some_and(|pattern| !pattern.is_match(&self.event_data.message))
+        {
+            return false;
+        }
+
+        if !query.labels.is_empty() {
+            let session = SESSION_LABELS.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let labels = if self.labels.is_empty() { &*session } else { &self.labels };
+            if !query.labels.iter().all(|(key, value)| labels.get(key) == Some(value)) {
+                return false;
+            }
+        }
+
+        query.metadata.iter().all(|(key, value)| self.custom_metadata.get(key) == Some(value))
     }
 
     /// Create an Event from tracing subscriber data
@@ -231,7 +756,25 @@ impl Event {
         if let Some((file, line, module_path)) = metadata {
             event_data.file = Some(file);
             event_data.line = Some(line);
-            event_data.module_path = Some(module_path);
+            event_data.module_path = Some(module_path.into());
+        }
+
+        Event::new(event_data)
+    }
+
+    /// Create an Event from an error, preserving its `source()` chain.
+    ///
+    /// The message is the error's `Display` output, and each link in the chain is recorded
+    /// as a field: `error.0` is the error itself, `error.1` its source, and so on.
+    pub fn from_error(err: &dyn std::error::Error, level: Level, target: String) -> Self {
+        let mut event_data = EventData::new(err.to_string(), level, target);
+
+        let mut current = Some(err);
+        let mut depth = 0;
+        while let Some(error) = current {
+            event_data.add_field(format!("error.{}", depth), error.to_string());
+            current = error.source();
+            depth += 1;
         }
 
         Event::new(event_data)
@@ -252,14 +795,268 @@ impl Event {
         event = event.with_process_id(std::process::id());
 
         // Add correlation ID (could be from context or generated)
-        event = event.with_correlation_id(format!("corr-{}", generate_uuid_like_string()));
+        event = event.with_generated_correlation_id();
 
         event
     }
 }
 
-/// Helper function to generate a simple UUID-like string
-fn generate_uuid_like_string() -> String {
-    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-    format!("{:x}-{:x}", now.as_secs(), now.subsec_nanos())
+/// An event and the events that name it as their [`parent`](Event::parent), see
+/// [`EventManager::build_event_forest`](crate::EventManager::build_event_forest)
+#[derive(Debug, Clone)]
+pub struct EventNode<'a> {
+    pub event: &'a Event,
+    pub children: Vec<EventNode<'a>>,
+    /// The event has a parent, but it isn't among the events the forest was built from
+    pub dangling_parent: bool,
+}
+
+/// Approximate heap size of a string map, see [`Event::estimated_size`]
+pub(crate) fn map_size(map: &HashMap<String, String>) -> usize {
+    map.iter().map(|(k, v)| k.len() + v.len() + 2 * std::mem::size_of::<String>()).sum()
+}
+
+/// Serialize a string map in key order, so identical events always produce identical output
+pub(crate) fn sorted_map<S: serde::Serializer>(map: &HashMap<String, String>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(map.iter().collect::<BTreeMap<_, _>>())
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::fmt};
+
+    #[derive(Debug)]
+    struct Layered(&'static str, Option<Box<Layered>>);
+
+    impl fmt::Display for Layered {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(self.0) }
+    }
+
+    impl std::error::Error for Layered {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            self.1.as_deref().map(|e| e as &(dyn std::error::Error + 'static))
+        }
+    }
+
+    fn chain() -> Layered {
+        Layered("request failed", Some(Box::new(Layered("query failed", Some(Box::new(Layered("connection reset", None)))))))
+    }
+
+    #[test]
+    fn test_from_error_records_source_chain() {
+        let event = Event::from_error(&chain(), Level::ERROR, "app".to_string());
+
+        assert_eq!(event.event_data.message, "request failed");
+        assert_eq!(event.event_data.level(), Level::ERROR);
+        assert_eq!(event.event_data.fields.len(), 3);
+        assert_eq!(event.event_data.fields["error.0"], "request failed");
+        assert_eq!(event.event_data.fields["error.1"], "query failed");
+        assert_eq!(event.event_data.fields["error.2"], "connection reset");
+    }
+
+    #[test]
+    fn test_spanner_error_macro() {
+        let _guard = crate::manager::global_test_guard();
+        crate::init_global_event_manager();
+
+        let err = chain();
+        crate::spanner_error!(err);
+
+        let captured = crate::get_global_events()
+            .unwrap_or_default()
+            .into_iter()
+            .find(|e| e.event_data.target == module_path!() && e.event_data.fields.contains_key("error.2"))
+            .expect("macro should capture into the global manager");
+        assert_eq!(captured.event_data.level(), Level::ERROR);
+        assert_eq!(captured.event_data.fields["error.1"], "query failed");
+    }
+
+    #[test]
+    fn test_display_target_aliases() {
+        let _guard = crate::manager::global_test_guard();
+        let event = |target: &str| Event::new(EventData::new("m".to_string(), Level::INFO, target.to_string()));
+        set_target_aliases(BTreeMap::from([
+            ("alias_test::services".to_string(), "Services".to_string()),
+            ("alias_test::services::billing".to_string(), "Billing".to_string()),
+        ]));
+
+        assert_eq!(event("alias_test::services::billing").display_target(), "Billing");
+        assert_eq!(event("alias_test::services::billing::stripe").display_target(), "Billing::stripe");
+        assert_eq!(event("alias_test::services::auth").display_target(), "Services::auth");
+        assert_eq!(event("alias_test::servicesx").display_target(), "alias_test::servicesx");
+
+        let billing = event("alias_test::services::billing");
+        assert!(billing.get_full_context().contains("Target: Billing (alias_test::services::billing)"));
+        assert_eq!(billing.event_data.target, "alias_test::services::billing", "stored target is untouched");
+
+        set_target_aliases(BTreeMap::new());
+    }
+
+    #[test]
+    fn test_notification_summary() {
+        let mut event = Event::new(EventData::new(
+            "payment failed:\n  card declined\r\n\tretry later".to_string(),
+            Level::ERROR,
+            "app::billing".to_string(),
+        ))
+        .with_correlation_id("abc".to_string());
+        event.event_data.add_field("order".to_string(), "42".to_string());
+        event.event_data.add_field("amount".to_string(), "9.99".to_string());
+        event.event_data.add_field("user".to_string(), "\"bob\"".to_string());
+
+        let summary = event.notification_summary(200);
+        assert_eq!(summary, "ERROR app::billing [corr=abc amount=9.99 order=42]: payment failed: card declined retry later");
+
+        for max_len in [0, 1, 10, 40] {
+            let short = event.notification_summary(max_len);
+            assert!(short.chars().count() <= max_len);
+            assert!(!short.contains('\n'));
+        }
+        assert_eq!(event.notification_summary(10), "ERROR app…");
+    }
+
+    #[test]
+    fn test_id() {
+        let new = || Event::new(EventData::new("m".to_string(), Level::INFO, "test".to_string()));
+        let (first, second) = (new(), new());
+        assert!(second.id() > first.id());
+        assert_eq!(first.clone().id(), first.id());
+
+        let decoded: Event = serde_json::from_str(&serde_json::to_string(&first).unwrap()).unwrap();
+        assert_eq!(decoded.id(), first.id());
+    }
+
+    #[test]
+    fn test_age() {
+        let at = |offset: TimeDelta| {
+            let mut data = EventData::new("aged".to_string(), Level::INFO, "test".to_string());
+            data.timestamp = Utc::now() + offset;
+            Event::new(data)
+        };
+
+        let backdated = at(TimeDelta::seconds(-90)).age();
+        assert!(backdated >= Duration::from_secs(90) && backdated < Duration::from_secs(95));
+        assert_eq!(at(TimeDelta::hours(1)).age(), Duration::ZERO, "future timestamps don't underflow");
+    }
+
+    #[test]
+    fn test_validate() {
+        let span = |id| {
+            let mut span = SpanInfo::new(id, "s".to_string(), "t".to_string(), Level::INFO);
+            span.entered_at -= TimeDelta::minutes(1);
+            span
+        };
+        let event = || Event::new(EventData::new("m".to_string(), Level::INFO, "t".to_string()));
+        let issues = |event: Event| event.validate().err().unwrap_or_default();
+
+        assert_eq!(event().validate(), Ok(()));
+        assert_eq!(event().with_span_stack(vec![span(1), span(2)]).with_current_span(span(2)).validate(), Ok(()));
+
+        assert_eq!(issues(event().with_span_stack(vec![span(1), span(2)]).with_current_span(span(1))), [
+            ValidationIssue::CurrentSpanNotInStack { span_id: 1 }
+        ]);
+        assert_eq!(issues(event().with_current_span(span(3))), [ValidationIssue::CurrentSpanNotInStack { span_id: 3 }]);
+        assert_eq!(issues(event().with_span_stack(vec![span(1)])), [ValidationIssue::MissingCurrentSpan]);
+
+        let mut empty = Event::new(EventData::new(String::new(), Level::INFO, "t".to_string()));
+        assert_eq!(issues(empty.clone()), [ValidationIssue::EmptyEvent]);
+        empty.event_data.add_field("user_id".to_string(), "5".to_string());
+        assert_eq!(empty.validate(), Ok(()));
+
+        let mut future = event();
+        future.event_data.timestamp = Utc::now() + TimeDelta::hours(1);
+        assert_eq!(issues(future), [ValidationIssue::TimestampInFuture]);
+
+        let mut early = event();
+        early.event_data.timestamp -= TimeDelta::minutes(2);
+        assert_eq!(issues(early.with_span_stack(vec![span(4)]).with_current_span(span(4))), [
+            ValidationIssue::TimestampBeforeSpan { span_id: 4 }
+        ]);
+    }
+
+    #[test]
+    fn test_serialization_is_deterministic() {
+        let build = |keys: &mut dyn Iterator<Item = u32>| {
+            let mut event = Event::new(EventData::new("m".to_string(), Level::INFO, "t".to_string()));
+            let mut span = SpanInfo::new(1, "s".to_string(), "t".to_string(), Level::INFO);
+            for key in keys {
+                event.event_data.add_field(format!("field{}", key), key.to_string());
+                event.add_metadata(format!("meta{}", key), key.to_string());
+                span.add_field(format!("span{}", key), key.to_string());
+            }
+            event.with_current_span(span)
+        };
+
+        let first = build(&mut (0..32));
+        let mut second = build(&mut (0..32).rev());
+        second.id = first.id;
+        second.event_data.timestamp = first.event_data.timestamp;
+        second.event_data.sequence = first.event_data.sequence;
+        second.current_span.as_mut().unwrap().entered_at = first.current_span.as_ref().unwrap().entered_at;
+
+        assert_eq!(serde_json::to_vec(&first).unwrap(), serde_json::to_vec(&second).unwrap());
+    }
+
+    #[test]
+    fn test_events_are_strictly_ordered() {
+        let events: Vec<_> =
+            (0..1000).map(|i| EventData::new(i.to_string(), Level::INFO, "spanner_test::order".to_string())).collect();
+        // Many of these share a wall-clock timestamp, the sequence still orders them
+        assert!(events.windows(2).all(|pair| pair[0].order_key() < pair[1].order_key()));
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    (0..500)
+                        .map(|_| EventData::new(String::new(), Level::INFO, "t".to_string()).sequence)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut sequences: Vec<_> = threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect();
+        sequences.sort();
+        sequences.dedup();
+        assert_eq!(sequences.len(), 2000, "sequences are unique across threads");
+    }
+
+    #[test]
+    fn test_indented_line_follows_span_depth() {
+        let event = Event::new(EventData::new("query done".to_string(), Level::INFO, "app::db".to_string()));
+        assert_eq!(event.to_indented_line("  "), event.to_line(LineFormat::Compact));
+
+        let spans: Vec<_> =
+            (1..=3).map(|id| SpanInfo::new(id, format!("span{}", id), "app".to_string(), Level::INFO)).collect();
+        let nested = event.with_current_span(spans[2].clone()).with_span_stack(spans);
+        let line = nested.to_indented_line("| ");
+        assert_eq!(line, format!("| | | {}", nested.to_line(LineFormat::Compact)));
+    }
+
+    #[test]
+    fn test_content_key_follows_identity() {
+        let event = |message: &str, correlation_id: &str| {
+            let mut event = Event::new(EventData::new(message.to_string(), Level::INFO, "app".to_string()));
+            event.event_data.add_field("user".to_string(), "7".to_string());
+            event.with_correlation_id(correlation_id.to_string())
+        };
+        let first = event("login", "corr-a");
+        let mut repeat = event("login", "corr-b");
+        repeat.event_data.timestamp += TimeDelta::seconds(5);
+
+        let default = IdentityConfig::default();
+        assert_eq!(first.content_key(&default), repeat.content_key(&default));
+        assert_ne!(first.content_key(&default), event("logout", "corr-a").content_key(&default));
+
+        for identity in [IdentityConfig { include_timestamp: true, ..default }, IdentityConfig {
+            include_correlation_id: true,
+            ..default
+        }] {
+            assert_ne!(first.content_key(&identity), repeat.content_key(&identity));
+        }
+
+        let mut other_user = event("login", "corr-a");
+        other_user.event_data.add_field("user".to_string(), "8".to_string());
+        assert_ne!(first.content_key(&default), other_user.content_key(&default));
+        let message_only = IdentityConfig { include_fields: false, ..default };
+        assert_eq!(first.content_key(&message_only), other_user.content_key(&message_only));
+    }
 }
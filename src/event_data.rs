@@ -1,37 +1,100 @@
 use {
-    crate::span::SerializableLevel,
+    crate::{
+        event::{map_size, sorted_map},
+        intern::SharedStr,
+        span::SerializableLevel,
+    },
     chrono::{DateTime, Utc},
     serde::{Deserialize, Serialize},
-    std::collections::HashMap,
+    std::{
+        collections::HashMap,
+        sync::{
+            OnceLock,
+            atomic::{AtomicU64, Ordering},
+        },
+        time::Instant,
+    },
     tracing::Level,
 };
 
+/// Monotonic clock start, shared by every sequence number in this process
+static CLOCK_START: OnceLock<Instant> = OnceLock::new();
+static LAST_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Nanoseconds since the process clock started, bumped so each call returns a larger value
+/// than the last even when the monotonic clock hasn't ticked in between
+fn next_sequence() -> u64 {
+    let elapsed = CLOCK_START.get_or_init(Instant::now).elapsed().as_nanos() as u64;
+    let mut last = LAST_SEQUENCE.load(Ordering::Relaxed);
+    loop {
+        let next = elapsed.max(last + 1);
+        match LAST_SEQUENCE.compare_exchange_weak(last, next, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return next,
+            Err(current) => last = current,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EventData {
     pub message: String,
     pub level: SerializableLevel,
-    pub target: String,
+    pub(crate) target: SharedStr,
     pub file: Option<String>,
     pub line: Option<u32>,
-    pub module_path: Option<String>,
+    pub(crate) module_path: Option<SharedStr>,
+    #[serde(serialize_with = "sorted_map")]
     pub fields: HashMap<String, String>,
     pub timestamp: DateTime<Utc>,
+    /// Monotonic capture order within the process that created the event.
+    ///
+    /// Wall-clock timestamps can repeat for events captured within one clock tick, or jump
+    /// when the system clock is adjusted. This value is nanoseconds on a monotonic clock and
+    /// strictly increases from one event to the next, so [`EventData::order_key`] is never
+    /// ambiguous for events from the same process. It is `0` in dumps that predate it.
+    #[serde(default)]
+    pub sequence: u64,
 }
 impl EventData {
     pub fn new(message: String, level: Level, target: String) -> Self {
         Self {
             message,
             level: level.into(),
-            target,
+            target: target.into(),
             file: None,
             line: None,
             module_path: None,
             fields: HashMap::new(),
             timestamp: Utc::now(),
+            sequence: next_sequence(),
         }
     }
 
+    /// Sort key ordering events by wall-clock time, then by capture order within a tick
+    pub fn order_key(&self) -> (DateTime<Utc>, u64) { (self.timestamp, self.sequence) }
+
     pub fn level(&self) -> Level { self.level.clone().into() }
 
+    pub fn target(&self) -> &str { &self.target }
+
+    pub fn module_path(&self) -> Option<&str> { self.module_path.as_deref() }
+
+    /// Set the module path the event was logged from
+    pub fn with_module_path(mut self, module_path: String) -> Self {
+        self.module_path = Some(module_path.into());
+        self
+    }
+
     pub fn add_field(&mut self, key: String, value: String) { self.fields.insert(key, value); }
+
+    /// Approximate heap size of the owned strings and fields, see [`Event::estimated_size`](crate::Event::estimated_size)
+    pub fn estimated_size(&self) -> usize {
+        let optional = |value: Option<&str>| value.map_or(0, str::len);
+        self.message.len()
+            + self.level.0.len()
+            + self.target.len()
+            + optional(self.file.as_deref())
+            + optional(self.module_path.as_deref())
+            + map_size(&self.fields)
+    }
 }
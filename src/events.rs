@@ -5,13 +5,16 @@ use {
         fmt::Debug,
         ops::Deref,
         pin::Pin,
-        sync::{Arc, RwLock},
+        sync::{
+            Arc, RwLock, Weak,
+            atomic::{AtomicU64, AtomicUsize, Ordering},
+            mpsc::{self as std_mpsc, RecvTimeoutError},
+        },
         task::{Context, Poll},
+        thread,
+        time::{Duration, Instant},
     },
-    tokio::sync::{
-        Mutex,
-        mpsc::{self, UnboundedReceiver, unbounded_channel},
-    },
+    tokio::sync::mpsc::{self, Receiver, UnboundedReceiver, error::TrySendError, unbounded_channel},
     tracing::instrument,
     uuid::Uuid,
 };
@@ -19,49 +22,169 @@ use {
 // Re-export from other modules for convenience
 pub use crate::{Event, EventData, SpanInfo};
 
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
+/// Poll interval used while a blocking bounded stream waits for space
+const BLOCKING_BACKOFF: Duration = Duration::from_millis(1);
+
+/// Values sent to stream channels and not yet received, across every stream in the process
+static BACKLOG: AtomicUsize = AtomicUsize::new(0);
+static BACKLOG_CAP: AtomicUsize = AtomicUsize::new(usize::MAX);
+static BACKLOG_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Number of values sitting in stream channels across all streams, waiting to be received
+pub fn current_backlog() -> usize { BACKLOG.load(Ordering::Relaxed) }
+
+/// Cap the combined stream backlog, or remove the cap with `None`.
+///
+/// While the backlog is at the cap, values aren't sent to any stream (see
+/// [`backlog_dropped_count`]) until consumers catch up. Listeners registered with
+/// [`EventTarget::on`] still run, since they don't queue anything.
+pub fn set_backlog_cap(cap: Option<usize>) { BACKLOG_CAP.store(cap.unwrap_or(usize::MAX), Ordering::Relaxed); }
+
+/// The cap set with [`set_backlog_cap`], if any
+pub(crate) fn backlog_cap() -> Option<usize> { Some(BACKLOG_CAP.load(Ordering::Relaxed)).filter(|&cap| cap != usize::MAX) }
+
+/// Number of values not sent to a stream because the backlog cap was reached
+pub fn backlog_dropped_count() -> u64 { BACKLOG_DROPPED.load(Ordering::Relaxed) }
+
+/// Reserve a backlog slot for a value about to be sent, or count it as dropped if at the cap
+fn reserve_backlog() -> bool {
+    let cap = BACKLOG_CAP.load(Ordering::Relaxed);
+    let reserved = BACKLOG.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| (n < cap).then_some(n + 1)).is_ok();
+    if !reserved {
+        BACKLOG_DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+    reserved
+}
+
+fn release_backlog(count: usize) { BACKLOG.fetch_sub(count, Ordering::Relaxed); }
+
+type Handler<T> = Arc<dyn Fn(Arc<T>) + Send + Sync>;
+type Listeners<T> = RwLock<HashMap<Uuid, Handler<T>>>;
+
+/// Fan-out of emitted values to subscribers and streams.
+///
+/// Nothing here requires a tokio runtime: listeners run inline on the emitting thread, and
+/// streams are backed by tokio's runtime-agnostic channels, so they can be polled from any
+/// executor or drained synchronously with `try_recv`. Only tokio's `sync` feature is enabled,
+/// so task-based dispatch brings its own threads, as [`EventTarget::on_batch`] does.
+#[derive(Clone)]
 pub struct EventTarget<T: Debug> {
-    listeners: Arc<RwLock<HashMap<Uuid, Arc<Subscription<T>>>>>,
-    sender: Arc<mpsc::UnboundedSender<Arc<T>>>,
-    receiver: Arc<Mutex<mpsc::UnboundedReceiver<Arc<T>>>>,
+    listeners: Arc<Listeners<T>>,
 }
 
-impl<T: Debug> EventTarget<T> {
-    pub fn new() -> Self {
-        let (sender, receiver) = mpsc::unbounded_channel();
-        Self {
-            listeners: Arc::new(RwLock::new(HashMap::new())),
-            sender: sender.into(),
-            receiver: Arc::new(Mutex::new(receiver)),
-        }
+impl<T: Debug> Debug for EventTarget<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let count = self.listeners.read().map(|listeners| listeners.len()).unwrap_or(0);
+        f.debug_struct("EventTarget").field("listeners", &count).finish()
     }
+}
+
+impl<T: Debug> EventTarget<T> {
+    pub fn new() -> Self { Self { listeners: Arc::new(RwLock::new(HashMap::new())) } }
 
     #[instrument(level = "trace")]
     pub fn emit(&self, v: impl Into<Arc<T>> + Debug) {
         let v = v.into();
 
-        // Notify all listeners
-        if let Ok(listeners) = self.listeners.read() {
-            listeners.values().for_each(|s| s.update(v.clone()));
-        }
+        // Snapshot the listeners so handlers can (un)subscribe without deadlocking
+        let handlers: Vec<Handler<T>> = match self.listeners.read() {
+            Ok(listeners) => listeners.values().cloned().collect(),
+            Err(_) => return,
+        };
 
-        // Send to stream (ignore error if receiver is dropped)
-        let _ = self.sender.send(v);
+        // Notify all listeners
+        handlers.iter().for_each(|handler| handler(v.clone()));
     }
 
+    /// Subscribe a handler for as long as the returned guard is alive.
+    ///
+    /// Dropping the last clone of the guard (or calling [`Subscription::off`]) unsubscribes.
     pub fn on(&self, handler: impl Fn(Arc<T>) + Send + Sync + 'static) -> Arc<Subscription<T>> {
-        let sub = Arc::new(Subscription::new(self, handler));
-        if let Ok(mut listeners) = self.listeners.write() {
-            listeners.insert(sub.id, sub.clone());
-        }
-        sub
+        Arc::new(Subscription::new(self, handler))
+    }
+
+    pub fn off(&self, sub: &Subscription<T>) { self.unsubscribe(sub.id); }
+
+    /// Like [`EventTarget::on`], only calling `handler` for values `predicate` accepts, e.g.
+    /// `|event| event.event_data.level() <= Level::WARN` for a WARN+ console.
+    pub fn on_filtered(
+        &self,
+        predicate: impl Fn(&T) -> bool + Send + Sync + 'static,
+        handler: impl Fn(Arc<T>) + Send + Sync + 'static,
+    ) -> Arc<Subscription<T>> {
+        self.on(move |v| {
+            if predicate(&v) {
+                handler(v)
+            }
+        })
+    }
+
+    /// Subscribe a handler that receives values in batches, e.g. for bulk inserts into a database.
+    ///
+    /// A batch is delivered once it holds `max_batch` values or `max_delay` after its first
+    /// value arrived, whichever comes first. Emitting only queues the value; batches are
+    /// assembled and handed to `handler` on a dedicated thread. Dropping the last clone of
+    /// the guard delivers what's queued, then stops the thread.
+    pub fn on_batch(
+        &self,
+        max_batch: usize,
+        max_delay: Duration,
+        handler: impl Fn(Vec<Arc<T>>) + Send + 'static,
+    ) -> Arc<Subscription<T>>
+    where
+        T: Send + Sync + 'static,
+    {
+        let (tx, rx) = std_mpsc::channel::<Arc<T>>();
+        let max_batch = max_batch.max(1);
+        thread::spawn(move || {
+            let mut batch = Vec::with_capacity(max_batch);
+            let mut deadline: Option<Instant> = None;
+            loop {
+                let received = match deadline {
+                    Some(deadline) => rx.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                    None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match received {
+                    Ok(value) => {
+                        deadline.get_or_insert_with(|| Instant::now() + max_delay);
+                        batch.push(value);
+                        if batch.len() < max_batch {
+                            continue;
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                handler(std::mem::replace(&mut batch, Vec::with_capacity(max_batch)));
+                deadline = None;
+            }
+            if !batch.is_empty() {
+                handler(batch);
+            }
+        });
+
+        self.on(move |value| {
+            let _ = tx.send(value);
+        })
     }
 
-    pub fn off(&self, sub: &Subscription<T>) {
+    /// Subscribe a handler without an RAII guard.
+    ///
+    /// The handler stays registered until [`EventTarget::unsubscribe`] is called with the
+    /// returned token. The caller owns that responsibility: losing the token leaks the
+    /// handler (and anything it captures) for as long as the target lives. Prefer
+    /// [`EventTarget::on`] unless the subscription is meant to live for the whole program.
+    pub fn subscribe_persistent(&self, handler: impl Fn(Arc<T>) + Send + Sync + 'static) -> Uuid {
+        let token = Uuid::new_v4();
         if let Ok(mut listeners) = self.listeners.write() {
-            listeners.remove(&sub.id);
+            listeners.insert(token, Arc::new(handler));
         }
+        token
+    }
+
+    /// Remove a subscription by token, returning whether it was still registered
+    pub fn unsubscribe(&self, token: Uuid) -> bool {
+        self.listeners.write().map(|mut listeners| listeners.remove(&token).is_some()).unwrap_or(false)
     }
 
     pub fn as_stream(&self) -> EventStream<T>
@@ -70,50 +193,78 @@ impl<T: Debug> EventTarget<T> {
     {
         EventStream::new(self)
     }
+
+    /// Like [`EventTarget::as_stream`], only receiving values `predicate` accepts. Rejected
+    /// values are discarded on the emitting thread, so they never wake the consumer or count
+    /// against the backlog cap.
+    pub fn as_filtered_stream(&self, predicate: impl Fn(&T) -> bool + Send + Sync + 'static) -> EventStream<T>
+    where
+        T: Send + Sync + 'static,
+    {
+        EventStream::filtered(self, predicate)
+    }
+
+    /// Get a stream backed by a bounded channel that applies backpressure to emitters.
+    ///
+    /// When the channel is full, `emit` blocks the emitting thread for up to `timeout`
+    /// waiting for the consumer to make room. If no room frees up in time the value is
+    /// dropped and counted in [`BoundedEventStream::dropped_count`].
+    ///
+    /// Blocking happens inline on whichever thread logs, so a slow consumer stalls every
+    /// emitter for up to `timeout` per value. Only use this when losing events is worse than
+    /// slowing the application down, e.g. in tests, batch jobs, or audit pipelines with a
+    /// dedicated consumer; avoid it on latency-sensitive or async executor threads.
+    pub fn as_bounded_stream_blocking(&self, capacity: usize, timeout: Duration) -> BoundedEventStream<T>
+    where
+        T: Send + Sync + 'static,
+    {
+        BoundedEventStream::new(self, capacity, timeout)
+    }
 }
 
 impl<T: Debug> Default for EventTarget<T> {
     fn default() -> Self { Self::new() }
 }
 
+/// RAII guard for a handler registered with [`EventTarget::on`]
 pub struct Subscription<T: Debug> {
     id: Uuid,
-    handler: Box<dyn Fn(Arc<T>) + Send + Sync>,
-    to: *const EventTarget<T>, // Using raw pointer to avoid lifetime issues
+    listeners: Weak<Listeners<T>>,
 }
 
 impl<T: Debug> Debug for Subscription<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Subscription").field("id", &self.id).field("handler", &"<function>").field("to", &self.to).finish()
+        f.debug_struct("Subscription").field("id", &self.id).field("active", &self.is_active()).finish()
     }
 }
 
-unsafe impl<T: Debug> Send for Subscription<T> {}
-unsafe impl<T: Debug> Sync for Subscription<T> {}
-
 impl<T: Debug> Subscription<T> {
+    /// Register a handler on the target, unsubscribing when the returned guard is dropped
     pub fn new(to: &EventTarget<T>, handler: impl Fn(Arc<T>) + Send + Sync + 'static) -> Self {
-        Self { id: Uuid::new_v4(), handler: Box::new(handler), to: to as *const _ }
+        Self { id: to.subscribe_persistent(handler), listeners: Arc::downgrade(&to.listeners) }
+    }
+
+    pub fn id(&self) -> Uuid { self.id }
+
+    /// Whether the handler is still registered on a live target
+    pub fn is_active(&self) -> bool {
+        self.listeners
+            .upgrade()
+            .and_then(|listeners| listeners.read().ok().map(|l| l.contains_key(&self.id)))
+            .unwrap_or(false)
     }
 
     pub fn off(&self) {
-        unsafe {
-            if let Some(target) = self.to.as_ref() {
-                target.off(self);
-            }
+        if let Some(listeners) = self.listeners.upgrade()
+            && let Ok(mut listeners) = listeners.write()
+        {
+            listeners.remove(&self.id);
         }
     }
-
-    #[instrument(level = "trace")]
-    pub(crate) fn update(&self, v: Arc<T>) { (self.handler)(v) }
 }
 
 impl<T: Debug> Drop for Subscription<T> {
-    fn drop(&mut self) {
-        unsafe {
-            self.to.read().off(self);
-        }
-    }
+    fn drop(&mut self) { self.off(); }
 }
 
 #[allow(dead_code)]
@@ -126,17 +277,31 @@ impl<T: Debug> EventStream<T>
 where
     T: Send + Sync + 'static,
 {
-    pub fn new(et: &EventTarget<T>) -> Self {
+    pub fn new(et: &EventTarget<T>) -> Self { Self::filtered(et, |_| true) }
+
+    /// Stream of the values `predicate` accepts, see [`EventTarget::as_filtered_stream`]
+    pub fn filtered(et: &EventTarget<T>, predicate: impl Fn(&T) -> bool + Send + Sync + 'static) -> Self {
         let (tx, rx) = unbounded_channel();
         Self {
             ch: rx,
-            sub: et.on(move |v| {
-                let _ = tx.send(v);
+            sub: et.on_filtered(predicate, move |v| {
+                if reserve_backlog() && tx.send(v).is_err() {
+                    release_backlog(1);
+                }
             }),
         }
     }
 }
 
+impl<T: Debug> Drop for EventStream<T> {
+    fn drop(&mut self) {
+        self.ch.close();
+        while self.ch.try_recv().is_ok() {
+            release_backlog(1);
+        }
+    }
+}
+
 impl<T: Debug> Deref for EventStream<T> {
     type Target = UnboundedReceiver<Arc<T>>;
 
@@ -146,7 +311,93 @@ impl<T: Debug> Deref for EventStream<T> {
 impl<T: Debug> Stream for EventStream<T> {
     type Item = Arc<T>;
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> { self.ch.poll_recv(cx) }
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let polled = self.ch.poll_recv(cx);
+        if let Poll::Ready(Some(_)) = polled {
+            release_backlog(1);
+        }
+        polled
+    }
+}
+
+/// Stream fed by a bounded channel, see [`EventTarget::as_bounded_stream_blocking`]
+pub struct BoundedEventStream<T: Debug> {
+    _sub: Arc<Subscription<T>>,
+    ch: Receiver<Arc<T>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<T: Debug> BoundedEventStream<T>
+where
+    T: Send + Sync + 'static,
+{
+    pub fn new(et: &EventTarget<T>, capacity: usize, timeout: Duration) -> Self {
+        let (tx, rx) = mpsc::channel(capacity.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let counter = dropped.clone();
+
+        Self {
+            ch: rx,
+            dropped,
+            _sub: et.on(move |v| {
+                if !reserve_backlog() {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+
+                let deadline = Instant::now() + timeout;
+                let mut pending = v;
+                loop {
+                    match tx.try_send(pending) {
+                        Ok(()) => return,
+                        Err(TrySendError::Closed(_)) => return release_backlog(1),
+                        Err(TrySendError::Full(v)) => {
+                            let now = Instant::now();
+                            if now >= deadline {
+                                counter.fetch_add(1, Ordering::Relaxed);
+                                return release_backlog(1);
+                            }
+                            pending = v;
+                            thread::sleep(BLOCKING_BACKOFF.min(deadline - now));
+                        }
+                    }
+                }
+            }),
+        }
+    }
+}
+
+impl<T: Debug> BoundedEventStream<T> {
+    /// Number of values dropped because the channel stayed full past the timeout, or the
+    /// global backlog cap (see [`set_backlog_cap`]) was reached
+    pub fn dropped_count(&self) -> u64 { self.dropped.load(Ordering::Relaxed) }
+}
+
+impl<T: Debug> Drop for BoundedEventStream<T> {
+    fn drop(&mut self) {
+        self.ch.close();
+        while self.ch.try_recv().is_ok() {
+            release_backlog(1);
+        }
+    }
+}
+
+impl<T: Debug> Deref for BoundedEventStream<T> {
+    type Target = Receiver<Arc<T>>;
+
+    fn deref(&self) -> &Self::Target { &self.ch }
+}
+
+impl<T: Debug> Stream for BoundedEventStream<T> {
+    type Item = Arc<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let polled = self.ch.poll_recv(cx);
+        if let Poll::Ready(Some(_)) = polled {
+            release_backlog(1);
+        }
+        polled
+    }
 }
 
 /// Bridge between the async event system and the tracing event system
@@ -172,3 +423,161 @@ impl TracingEventBridge {
 impl Default for TracingEventBridge {
     fn default() -> Self { Self::new() }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{EventManager, manager::global_test_guard},
+        futures::StreamExt,
+        tracing::Level,
+    };
+
+    #[test]
+    fn test_emit_without_tokio_runtime() {
+        let _guard = global_test_guard();
+        // Plain #[test]: no tokio runtime exists on this thread
+        let mut manager = EventManager::new(None);
+        let stream = manager.as_stream();
+        let mut bounded = manager.as_bounded_stream_blocking(4, Duration::from_millis(1));
+
+        manager.emit(Event::new(EventData::new("no runtime".to_string(), Level::INFO, "test".to_string())));
+
+        assert_eq!(manager.len(), 1);
+        assert_eq!(manager.get_recent(1)[0].event_data.message, "no runtime");
+        let received = futures::executor::block_on(bounded.next()).unwrap();
+        assert_eq!(received.event_data.message, "no runtime");
+        drop(stream);
+    }
+
+    #[test]
+    fn test_filtered_subscriptions() {
+        // Streams share the global backlog cap
+        let _guard = global_test_guard();
+        let target = EventTarget::<Event>::new();
+        let warn_and_above = |event: &Event| event.event_data.level() <= Level::WARN;
+        let mut stream = target.as_filtered_stream(warn_and_above);
+        let handled = Arc::new(RwLock::new(Vec::new()));
+        let sink = handled.clone();
+        let _sub = target.on_filtered(warn_and_above, move |event| {
+            sink.write().unwrap().push(event.event_data.message.clone());
+        });
+
+        for (message, level) in
+            [("debug", Level::DEBUG), ("warn", Level::WARN), ("info", Level::INFO), ("error", Level::ERROR)]
+        {
+            target.emit(Event::new(EventData::new(message.to_string(), level, "test".to_string())));
+        }
+
+        assert_eq!(*handled.read().unwrap(), ["warn", "error"]);
+        let received: Vec<String> =
+            (0..2).map(|_| futures::executor::block_on(stream.next()).unwrap().event_data.message.clone()).collect();
+        assert_eq!(received, ["warn", "error"]);
+        assert!(stream.is_empty(), "rejected events must not be queued");
+    }
+
+    #[test]
+    fn test_subscription_guard_and_persistent_token() {
+        use std::sync::atomic::AtomicUsize;
+
+        let target = EventTarget::<u32>::new();
+        let guarded = Arc::new(AtomicUsize::new(0));
+        let persistent = Arc::new(AtomicUsize::new(0));
+
+        let counter = guarded.clone();
+        let sub = target.on(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        let counter = persistent.clone();
+        let token = target.subscribe_persistent(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+
+        target.emit(1);
+        assert!(sub.is_active());
+        drop(sub);
+        target.emit(2);
+
+        assert_eq!(guarded.load(Ordering::Relaxed), 1, "dropping the guard unsubscribes");
+        assert_eq!(persistent.load(Ordering::Relaxed), 2, "persistent handlers need no guard");
+
+        assert!(target.unsubscribe(token));
+        assert!(!target.unsubscribe(token));
+        target.emit(3);
+        assert_eq!(persistent.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_on_batch() {
+        let target = EventTarget::<u32>::new();
+        let batches = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let received = batches.clone();
+        let sub = target.on_batch(10, Duration::from_millis(20), move |batch| received.lock().unwrap().push(batch.len()));
+
+        (0..100).for_each(|v| target.emit(v));
+        let wait_for = |total: usize| {
+            let started = Instant::now();
+            while batches.lock().unwrap().iter().sum::<usize>() < total {
+                assert!(started.elapsed() < Duration::from_secs(5), "batches never arrived");
+                thread::sleep(Duration::from_millis(1));
+            }
+        };
+        wait_for(100);
+        assert!(batches.lock().unwrap().iter().all(|&len| len <= 10));
+
+        // A partial batch goes out once `max_delay` has passed
+        (0..3).for_each(|v| target.emit(v));
+        wait_for(103);
+        assert_eq!(batches.lock().unwrap().last(), Some(&3));
+        drop(sub);
+    }
+
+    #[test]
+    fn test_bounded_blocking_stream_drops_after_timeout() {
+        let _guard = global_test_guard();
+        let target = EventTarget::<u32>::new();
+        let mut stream = target.as_bounded_stream_blocking(1, Duration::from_millis(20));
+
+        let started = Instant::now();
+        target.emit(1);
+        target.emit(2);
+        target.emit(3);
+
+        assert!(started.elapsed() >= Duration::from_millis(40), "emitter should block until the timeout");
+        assert_eq!(stream.dropped_count(), 2);
+        assert_eq!(futures::executor::block_on(stream.next()).as_deref(), Some(&1));
+
+        // Space is available again, so the next emit goes straight through
+        target.emit(4);
+        assert_eq!(futures::executor::block_on(stream.next()).as_deref(), Some(&4));
+        assert_eq!(stream.dropped_count(), 2);
+    }
+
+    #[test]
+    fn test_backlog_cap_across_streams() {
+        // Streams share the global backlog, so keep other stream tests out while it's capped
+        let _guard = global_test_guard();
+        let target = EventTarget::<u32>::new();
+        let mut first = target.as_stream();
+        let second = target.as_bounded_stream_blocking(100, Duration::from_millis(1));
+        let dropped = backlog_dropped_count();
+
+        set_backlog_cap(Some(10));
+        (0..100).for_each(|v| target.emit(v));
+        assert_eq!(current_backlog(), 10);
+        assert_eq!(first.len() + second.len(), 10);
+        assert_eq!(backlog_dropped_count() - dropped, 190);
+
+        // Receiving frees room for more
+        for _ in 0..first.len() {
+            futures::executor::block_on(first.next());
+        }
+        assert!(current_backlog() < 10);
+        (100..110).for_each(|v| target.emit(v));
+        assert_eq!(current_backlog(), 10);
+
+        set_backlog_cap(None);
+        drop((first, second));
+        assert_eq!(current_backlog(), 0, "dropped streams release their backlog");
+    }
+}
@@ -0,0 +1,91 @@
+use {
+    crate::event::Event,
+    serde_json::{Map, Value},
+    std::collections::BTreeMap,
+};
+
+/// Output names for exported event keys, so dumps match what a downstream index expects.
+///
+/// Keys are dotted paths into an event as serialized, e.g. `event_data.timestamp` or
+/// `thread_name`. A renamed value moves to a top-level key with the new name, taken
+/// verbatim, so `log.level` stays one dotted key as Elasticsearch expects. Unmapped keys are
+/// kept where they were, and paths missing from an event are skipped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldNames {
+    pub renames: BTreeMap<String, String>,
+}
+
+impl FieldNames {
+    pub fn new() -> Self { Self::default() }
+
+    /// Output the value at `path` under `name` instead
+    pub fn rename(mut self, path: impl Into<String>, name: impl Into<String>) -> Self {
+        self.renames.insert(path.into(), name.into());
+        self
+    }
+
+    /// Elastic Common Schema names: `@timestamp`, `message`, `log.level`, `log.logger`,
+    /// `log.origin.*`, `process.*`, `trace.id` and `labels` for the event's fields
+    pub fn ecs() -> Self {
+        [
+            ("event_data.timestamp", "@timestamp"),
+            ("event_data.message", "message"),
+            ("event_data.level", "log.level"),
+            ("event_data.target", "log.logger"),
+            ("event_data.file", "log.origin.file.name"),
+            ("event_data.line", "log.origin.file.line"),
+            ("event_data.module_path", "log.origin.function"),
+            ("event_data.fields", "labels"),
+            ("thread_id", "process.thread.id"),
+            ("thread_name", "process.thread.name"),
+            ("process_id", "process.pid"),
+            ("correlation_id", "trace.id"),
+        ]
+        .into_iter()
+        .fold(Self::new(), |names, (path, name)| names.rename(path, name))
+    }
+
+    /// Serialize an event with the renames applied
+    pub fn to_json(&self, event: &Event) -> serde_json::Result<Value> {
+        let mut value = serde_json::to_value(event)?;
+        if let Value::Object(object) = &mut value {
+            let renamed: Vec<(&String, Value)> =
+                self.renames.iter().filter_map(|(path, name)| Some((name, take_path(object, path)?))).collect();
+            renamed.into_iter().for_each(|(name, value)| {
+                object.insert(name.clone(), value);
+            });
+        }
+        Ok(value)
+    }
+}
+
+/// Remove the value at a dotted path, if there is one
+fn take_path(object: &mut Map<String, Value>, path: &str) -> Option<Value> {
+    match path.split_once('.') {
+        Some((head, rest)) => take_path(object.get_mut(head)?.as_object_mut()?, rest),
+        None => object.remove(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::EventData, tracing::Level};
+
+    #[test]
+    fn test_ecs_names() {
+        let mut data = EventData::new("payment declined".to_string(), Level::WARN, "shop::checkout".to_string());
+        data.add_field("order_id".to_string(), "42".to_string());
+        let event = Event::new(data).with_correlation_id("corr-1".to_string());
+
+        let json = FieldNames::ecs().rename("kind", "event.kind").to_json(&event).unwrap();
+        assert_eq!(json["message"], "payment declined");
+        assert_eq!(json["log.level"], "WARN");
+        assert_eq!(json["log.logger"], "shop::checkout");
+        assert_eq!(json["labels"]["order_id"], "42");
+        assert_eq!(json["trace.id"], "corr-1");
+        assert_eq!(json["event.kind"], "Log");
+        assert!(json["@timestamp"].is_string());
+        assert!(json.get("kind").is_none() && json["event_data"].get("timestamp").is_none());
+        assert!(json["event_data"].get("sequence").is_some(), "unmapped keys stay where they were");
+    }
+}
@@ -0,0 +1,104 @@
+use {
+    serde::{Deserialize, Deserializer, Serialize, Serializer},
+    std::{borrow::Borrow, collections::HashSet, fmt, ops::Deref, sync::Arc},
+};
+
+/// Immutable string that clones cheaply, used for targets, span names and module paths.
+///
+/// Values repeat across most buffered events, so [`EventManager`](crate::EventManager) interns
+/// them and every copy shares one allocation. Derefs to `str` and serializes as a plain string.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct SharedStr(Arc<str>);
+
+impl SharedStr {
+    pub fn as_str(&self) -> &str { &self.0 }
+
+    /// Whether two values share the same allocation
+    #[cfg(test)]
+    pub fn ptr_eq(&self, other: &Self) -> bool { Arc::ptr_eq(&self.0, &other.0) }
+}
+
+impl Deref for SharedStr {
+    type Target = str;
+
+    fn deref(&self) -> &str { &self.0 }
+}
+
+impl Borrow<str> for SharedStr {
+    fn borrow(&self) -> &str { &self.0 }
+}
+
+impl AsRef<str> for SharedStr {
+    fn as_ref(&self) -> &str { &self.0 }
+}
+
+impl fmt::Debug for SharedStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { fmt::Debug::fmt(&*self.0, f) }
+}
+
+impl fmt::Display for SharedStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(&self.0) }
+}
+
+impl From<String> for SharedStr {
+    fn from(value: String) -> Self { Self(value.into()) }
+}
+
+impl From<&str> for SharedStr {
+    fn from(value: &str) -> Self { Self(value.into()) }
+}
+
+impl From<SharedStr> for String {
+    fn from(value: SharedStr) -> Self { value.0.to_string() }
+}
+
+impl PartialEq<str> for SharedStr {
+    fn eq(&self, other: &str) -> bool { &*self.0 == other }
+}
+
+impl PartialEq<&str> for SharedStr {
+    fn eq(&self, other: &&str) -> bool { &*self.0 == *other }
+}
+
+impl PartialEq<String> for SharedStr {
+    fn eq(&self, other: &String) -> bool { *self.0 == **other }
+}
+
+impl Serialize for SharedStr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> { serializer.serialize_str(&self.0) }
+}
+
+impl<'de> Deserialize<'de> for SharedStr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
+
+/// Deduplicates [`SharedStr`] values so equal strings share one allocation
+#[derive(Default)]
+pub(crate) struct Interner {
+    strings: HashSet<SharedStr>,
+    /// Size after the last prune, so pruning cost stays proportional to growth
+    pruned_len: usize,
+}
+
+impl Interner {
+    /// Replace `value` with the shared copy of an equal string, adding it if new
+    pub(crate) fn intern(&mut self, value: &mut SharedStr) {
+        match self.strings.get(value.as_str()) {
+            Some(shared) => *value = shared.clone(),
+            None => {
+                self.strings.insert(value.clone());
+                if self.strings.len() > 2 * self.pruned_len.max(512) {
+                    self.prune();
+                }
+            }
+        }
+    }
+
+    /// Forget strings no longer used outside the interner
+    pub(crate) fn prune(&mut self) {
+        self.strings.retain(|shared| Arc::strong_count(&shared.0) > 1);
+        self.pruned_len = self.strings.len();
+    }
+}
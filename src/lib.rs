@@ -1,29 +1,147 @@
 // Modular structure for better code organization
+mod context;
+mod error;
 mod event;
 mod event_data;
+mod field_names;
+mod intern;
 mod manager;
+#[cfg(feature = "mmap")]
+mod mmap_store;
+mod query;
+mod recording;
 mod span;
+mod store;
 mod tracing;
 
 // Keep the existing async event system
 pub mod events;
 
 // Re-export main types and functions for public API
+#[cfg(feature = "mmap")]
+pub use mmap_store::MmapStore;
 pub use {
-    event::Event,
+    context::{
+        SpanContext, WithContext, capture_context, current_context, current_correlation_id, pop_context, push_context,
+        with_captured_context,
+    },
+    error::SpannerError,
+    event::{
+        Event, EventKind, EventNode, IdentityConfig, LineFormat, ValidationIssue, error_signature, session_labels,
+        set_session_labels, set_target_aliases,
+    },
     event_data::EventData,
+    field_names::FieldNames,
     manager::{
-        EventManager, ExportData, ExportMetadata, clear_global_events, events, export_filtered_to_bin_file,
-        export_to_bin_data, export_to_bin_file, get_event_summary, get_global_event_count, get_global_events,
-        import_and_merge_from_bin_file, import_from_bin_file, init_global_event_manager,
-        init_global_event_manager_with_count,
+        BuildInfo, Compression, CorrelationAnomaly, CorrelationSummary, EXPORT_HEADER_LEN, EventManager, ExportData,
+        ExportHeader, ExportMetadata, HealthStatus, ImportMergePolicy, ManagerSnapshot, MergeStats, ShutdownReport,
+        SpannerHandle, attach_line_writer, build_info, clear_global_events, events, export_and_clear_to_bin_file,
+        export_byte_stream, export_description, export_filtered_to_bin_file, export_projected, export_query_to_bin_file,
+        export_renamed, export_to_bin_data, export_to_bin_file, export_to_bin_file_compressed, export_to_ndjson_file,
+        flush_staged, get_all_spans, get_event_summary, get_global_correlation_trace, get_global_event_count,
+        get_global_events, get_span_duration_percentiles, global_dropped_count, health, import_and_merge_from_bin_file,
+        import_and_merge_with_policy, import_and_merge_with_progress, import_from_bin_file, import_from_ndjson_file,
+        init_global_event_manager, init_global_event_manager_with_count, init_global_event_manager_with_max_bytes,
+        init_with_per_level_buffers, on_global_overflow, on_initialized, print_shutdown_report, read_export_header,
+        read_metadata_only, report_on_shutdown, set_build_info, set_export_description, set_global_correlation_retention,
+        set_staged_emit,
     },
+    query::{FieldFilter, FieldOp, FuzzyMatch, MatchMode, Pattern, SearchQuery},
+    recording::{REPLAY_TARGET, Recording},
     span::SpanInfo,
-    tracing::{SpannerLayer, init_tracing_capture, init_layer_only, add_to_subscriber, init_with_subscriber},
+    store::{EventStore, InMemoryStore},
+    tracing::{
+        FieldMatch, OverheadStats, SpannerLayer, TraceRetention, UNKNOWN_TARGET, add_to_subscriber, capture_overhead_stats,
+        get_span_trace, init_layer_only, init_tracing_capture, init_with_subscriber, install_panic_capture,
+        set_level_remapper,
+    },
 };
 
 /// Main initialization function - sets up the complete tracing system
-pub fn init() -> Result<(), Box<dyn std::error::Error>> { tracing::init_tracing_capture() }
+pub fn init() -> Result<(), SpannerError> { tracing::init_tracing_capture() }
+
+/// Initialize like [`init`], tolerating an existing global subscriber.
+///
+/// Returns `true` if this call performed initialization and `false` if a global subscriber
+/// was already installed, which makes it safe to call from library code and test harnesses.
+pub fn try_init() -> bool { init().is_ok() }
+
+/// Capture an event into the global event manager without going through `tracing`.
+///
+/// The event gets the same thread, process and correlation context as events captured by
+/// [`SpannerLayer`], but no span context. Its file and line are those of the call site.
+/// Nothing is captured before the manager is initialized.
+///
+/// ```
+/// tracing_spanner::init_global_event_manager();
+/// tracing_spanner::capture(tracing::Level::WARN, "billing", "invoice retried");
+/// ```
+#[track_caller]
+pub fn capture(level: ::tracing::Level, target: &str, message: impl Into<String>) {
+    capture_with_fields(level, target, message, std::iter::empty::<(String, String)>())
+}
+
+/// Like [`capture`], with fields attached to the event
+#[track_caller]
+pub fn capture_with_fields<K: Into<String>, V: Into<String>>(
+    level: ::tracing::Level,
+    target: &str,
+    message: impl Into<String>,
+    fields: impl IntoIterator<Item = (K, V)>,
+) {
+    let location = std::panic::Location::caller();
+    let mut event_data = EventData::new(message.into(), level, target.to_string());
+    event_data.file = Some(location.file().to_string());
+    event_data.line = Some(location.line());
+    for (key, value) in fields {
+        event_data.add_field(key.into(), value.into());
+    }
+    manager::emit(tracing::with_thread_context(Event::new(event_data), true, true));
+}
+
+/// Capture an error and its `source()` chain into the global event manager.
+///
+/// Takes the error value (anything implementing `std::error::Error`) and an optional level,
+/// defaulting to `ERROR`. The event target is the calling module. See [`Event::from_error`].
+///
+/// ```
+/// let err = std::io::Error::other("disk full");
+/// tracing_spanner::spanner_error!(err);
+/// tracing_spanner::spanner_error!(err, tracing::Level::WARN);
+/// ```
+#[macro_export]
+macro_rules! spanner_error {
+    ($err:expr) => {
+        $crate::spanner_error!($err, $crate::__private::Level::ERROR)
+    };
+    ($err:expr, $level:expr) => {{
+        let err: &dyn ::std::error::Error = &$err;
+        $crate::__private::emit($crate::Event::from_error(err, $level, module_path!().to_string()));
+    }};
+}
+
+/// Capture the calling crate's build information at compile time.
+///
+/// Expands to a [`BuildInfo`] holding `CARGO_PKG_VERSION` and, if the `GIT_HASH` environment
+/// variable was set while compiling (e.g. from a build script), the git commit.
+///
+/// ```
+/// tracing_spanner::set_build_info(tracing_spanner::spanner_build_info!());
+/// ```
+#[macro_export]
+macro_rules! spanner_build_info {
+    () => {
+        $crate::BuildInfo {
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            git_hash: option_env!("GIT_HASH").map(String::from),
+        }
+    };
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use {crate::manager::emit, tracing::Level};
+}
 
 /// Example usage functions for testing the binary export functionality
 #[cfg(test)]
@@ -32,6 +150,8 @@ mod tests {
 
     #[test]
     fn test_binary_export() {
+        let _guard = manager::global_test_guard();
+
         // Initialize the tracing system
         init_global_event_manager();
         clear_global_events();
@@ -65,6 +185,8 @@ mod tests {
     fn test_filtered_export() {
         use std::{fs, path::Path};
 
+        let _guard = manager::global_test_guard();
+
         // Initialize the tracing system
         init_global_event_manager();
         clear_global_events();
@@ -122,32 +244,67 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_capture_without_tracing() {
+        let _guard = manager::global_test_guard();
+        init_global_event_manager();
+
+        let line = line!();
+        capture(::tracing::Level::WARN, "spanner_test::capture", "invoice retried");
+        capture_with_fields(::tracing::Level::INFO, "spanner_test::capture", "invoice paid", [("invoice", "42")]);
+
+        let events: Vec<_> = get_global_events()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|event| event.event_data.target == "spanner_test::capture")
+            .collect();
+        assert_eq!(events.len(), 2);
+        let (paid, retried) = (&events[0], &events[1]);
+        assert_eq!(retried.event_data.message, "invoice retried");
+        assert_eq!(retried.event_data.level(), ::tracing::Level::WARN);
+        assert_eq!(retried.event_data.file.as_deref(), Some(file!()));
+        assert_eq!(retried.event_data.line, Some(line + 1), "capture forwards its caller's location");
+        assert_eq!(paid.event_data.line, Some(line + 2));
+        assert_eq!(paid.event_data.fields.get("invoice").map(String::as_str), Some("42"));
+        assert!(paid.thread_id.is_some() && paid.process_id.is_some() && paid.correlation_id.is_some());
+    }
+
+    #[test]
+    fn test_try_init_is_idempotent() {
+        let _guard = manager::global_test_guard();
+        // Whichever call gets there first performs initialization; every later one reports it was already done
+        let _ = try_init();
+        assert!(!try_init());
+        assert!(matches!(init(), Err(SpannerError::AlreadyInitialized)));
+        assert!(matches!(
+            init_with_subscriber(tracing_subscriber::Registry::default()),
+            Err(SpannerError::AlreadyInitialized)
+        ));
+    }
+
     #[test]
     fn test_subscriber_integration() {
+        let _guard = manager::global_test_guard();
+
         // This test verifies that the layer integration works correctly
         init_global_event_manager();
         clear_global_events();
 
         // Set up the exact pattern from the user's code
-        let sub = tracing_subscriber::fmt()
-            .without_time()
-            .with_line_number(true)
-            .with_target(true)
-            .with_file(true)
-            .finish();
+        let sub = tracing_subscriber::fmt().without_time().with_line_number(true).with_target(true).with_file(true).finish();
 
         let sub = add_to_subscriber(sub);
-        
+
         // In the test we can't use set_global_default because it can only be called once
         // So we'll use a different approach
-        use ::tracing::{info, warn, error, subscriber};
-        
+        use ::tracing::{error, info, subscriber, warn};
+
         subscriber::with_default(sub, || {
             // Generate test events
             info!("Test info message from subscriber");
             warn!("Test warning from subscriber");
             error!("Test error from subscriber");
-            
+
             // Give a moment for processing
             std::thread::sleep(std::time::Duration::from_millis(50));
         });
@@ -155,7 +312,7 @@ mod tests {
         // Check what was captured
         let summary = get_event_summary();
         println!("Subscriber integration test - captured events summary:\n{}", summary);
-        
+
         // Verify we captured some events
         let count = get_global_event_count();
         println!("Total events captured: {}", count);
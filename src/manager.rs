@@ -1,83 +1,595 @@
 use {
-    crate::{event::Event, events::EventTarget},
+    crate::{
+        error::SpannerError,
+        event::{Event, EventKind, EventNode, IdentityConfig, LineFormat, error_signature, session_labels},
+        events::{EventTarget, Subscription},
+        field_names::FieldNames,
+        intern::Interner,
+        query::{MatchMode, Pattern, SearchQuery, cutoff},
+        span::SpanInfo,
+        store::{EventStore, InMemoryStore},
+    },
     chrono::{DateTime, Utc},
-    serde::{Deserialize, Serialize},
+    futures::{Stream, StreamExt, future, stream},
+    serde::{
+        Deserialize, Deserializer as _, Serialize,
+        de::{IgnoredAny, MapAccess, SeqAccess, Visitor},
+    },
     std::{
-        collections::{BTreeMap, VecDeque},
+        collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+        fmt,
         fs::File,
-        io::{self, Write},
+        hash::{DefaultHasher, Hash, Hasher},
+        io::{self, BufRead, BufReader, Read, Write},
         ops::Deref,
         path::Path,
-        sync::{Arc, OnceLock, RwLock},
+        sync::{
+            Arc, Mutex, OnceLock, RwLock, Weak,
+            atomic::{AtomicBool, Ordering},
+            mpsc::{self, Receiver, Sender},
+        },
+        thread,
+        time::Duration,
     },
     tracing::Level,
 };
 
 static GLOBAL_EVENT_MANAGER: OnceLock<Arc<RwLock<EventManager>>> = OnceLock::new();
+static BUILD_INFO: OnceLock<BuildInfo> = OnceLock::new();
+static EXPORT_DESCRIPTION: RwLock<Option<String>> = RwLock::new(None);
+static INIT_CALLBACKS: Mutex<Vec<InitCallback>> = Mutex::new(Vec::new());
+
+type InitCallback = Box<dyn FnOnce() + Send>;
+
+/// Whether [`emit`] hands events to the drainer thread, see [`set_staged_emit`]
+static STAGED_EMIT: AtomicBool = AtomicBool::new(false);
+static STAGING: OnceLock<Sender<Staged>> = OnceLock::new();
+
+/// Most staged items stored under one write lock
+const MAX_STAGED_BATCH: usize = 1024;
+
+/// Work queued for the drainer thread
+enum Staged {
+    Event(Box<Event>),
+    ClosedSpan(Box<SpanInfo>),
+    /// Acknowledged once everything queued before it has been stored
+    Flush(Sender<()>),
+}
+
+/// Rows per section of [`EventManager::report`]
+const REPORT_ROWS: usize = 5;
+
+/// Most events a manager reserves room for up front, see [`EventManager::push`]
+const MAX_PREALLOCATED_EVENTS: usize = 65_536;
+
+type OverflowHook = Arc<dyn Fn(&Event) + Send + Sync>;
 
 #[derive(Default)]
-pub struct EventManager {
-    inner: VecDeque<Event>,
+pub struct EventManager<S: EventStore = InMemoryStore> {
+    inner: S,
     target: EventTarget<Event>,
     max_events: usize,
+    max_bytes: Option<usize>,
+    size_bytes: usize,
+    /// Spans that have closed, newest first, capped at `max_events`
+    closed_spans: VecDeque<SpanInfo>,
+    /// Shares targets, span names and module paths between buffered events
+    interner: Interner,
+    /// Per-correlation copies of events, see [`EventManager::with_correlation_retention`]
+    correlations: Option<CorrelationRetention>,
+    /// Called with each event evicted to respect the limits, see [`EventManager::on_overflow`]
+    overflow_hook: Option<OverflowHook>,
+    /// Evicted events waiting for `overflow_hook`
+    overflowed: Vec<Event>,
+    /// Events evicted to respect the limits since the manager was created
+    dropped_count: u64,
+    /// `dropped_count` when [`export_and_clear_to_bin_file`] last drained the buffer
+    dropped_at_drain: u64,
+}
+
+/// Events of in-flight correlations, kept apart from the ring buffer so eviction can't split a trace
+#[derive(Debug)]
+struct CorrelationRetention {
+    max_in_flight: usize,
+    idle_timeout: Duration,
+    in_flight: HashMap<String, CorrelationTrace>,
+    /// The in-flight correlations by when their last event fired, least recently seen first
+    by_last_seen: BTreeSet<(DateTime<Utc>, String)>,
+    /// Traces whose root spans closed or that went idle, newest first, capped at `max_in_flight`
+    completed: VecDeque<(String, Vec<Event>)>,
+}
+
+#[derive(Debug)]
+struct CorrelationTrace {
+    events: VecDeque<Event>,
+    /// Root spans the correlation's events fired under that haven't closed yet
+    open_roots: HashSet<(u64, DateTime<Utc>)>,
+    last_seen: DateTime<Utc>,
+}
+
+impl CorrelationRetention {
+    fn record(&mut self, correlation_id: &str, event: &Event, max_events: usize) {
+        let now = event.event_data.timestamp;
+        if !self.in_flight.contains_key(correlation_id) {
+            self.complete_idle(now);
+            if self.in_flight.len() >= self.max_in_flight
+                && let Some((_, stalest)) = self.by_last_seen.first().cloned()
+            {
+                self.complete(&stalest);
+            }
+            if self.max_in_flight == 0 {
+                return;
+            }
+        }
+
+        let trace = self.in_flight.entry(correlation_id.to_string()).or_insert_with(|| {
+            self.by_last_seen.insert((now, correlation_id.to_string()));
+            CorrelationTrace { events: VecDeque::new(), open_roots: HashSet::new(), last_seen: now }
+        });
+        if now > trace.last_seen {
+            self.by_last_seen.remove(&(trace.last_seen, correlation_id.to_string()));
+            self.by_last_seen.insert((now, correlation_id.to_string()));
+            trace.last_seen = now;
+        }
+        if let Some(root) = event.span_stack.first() {
+            trace.open_roots.insert((root.id, root.entered_at));
+        }
+        trace.events.push_back(event.clone());
+        if trace.events.len() > max_events {
+            trace.events.pop_front();
+        }
+    }
+
+    /// Complete the correlations with no events for `idle_timeout` before `now`
+    fn complete_idle(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - chrono::Duration::from_std(self.idle_timeout).unwrap_or(chrono::Duration::MAX);
+        while let Some((last_seen, id)) = self.by_last_seen.first().cloned()
+            && last_seen < cutoff
+        {
+            self.complete(&id);
+        }
+    }
+
+    /// Complete the correlations whose last open root span is `span`
+    fn close_root(&mut self, span: &SpanInfo) {
+        let key = (span.id, span.entered_at);
+        let closed: Vec<String> = self
+            .in_flight
+            .iter_mut()
+            .filter_map(|(id, trace)| (trace.open_roots.remove(&key) && trace.open_roots.is_empty()).then(|| id.clone()))
+            .collect();
+        closed.iter().for_each(|id| self.complete(id));
+    }
+
+    fn complete(&mut self, correlation_id: &str) {
+        if let Some(trace) = self.in_flight.remove(correlation_id) {
+            self.by_last_seen.remove(&(trace.last_seen, correlation_id.to_string()));
+            self.completed.push_front((correlation_id.to_string(), trace.events.into()));
+            self.completed.truncate(self.max_in_flight);
+        }
+    }
+
+    fn get(&self, correlation_id: &str) -> Option<Vec<&Event>> {
+        match self.in_flight.get(correlation_id) {
+            Some(trace) => Some(trace.events.iter().collect()),
+            None => self.completed.iter().find(|(id, _)| id == correlation_id).map(|(_, events)| events.iter().collect()),
+        }
+    }
+}
+
+/// Which events to keep when merging leaves more than a manager's capacity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMergePolicy {
+    /// Keep the imported events, evicting live ones to make room for them
+    PreferImported,
+    /// Keep the live events, only filling spare capacity with imported ones
+    PreferLive,
+    /// Keep the newest events overall, whichever side they came from
+    InterleaveByTime,
+}
+
+/// Outcome of merging an import into the global manager
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeStats {
+    /// Imported events pushed into the manager
+    pub added: usize,
+    /// Imported events skipped because the manager, or the import itself, already had them
+    pub skipped_duplicates: usize,
+}
+
+/// Per-request overview of buffered events, see [`EventManager::correlation_summary`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorrelationSummary {
+    /// Number of distinct correlation IDs
+    pub distinct: usize,
+    /// The IDs with the most events and their counts, most events first
+    pub top: Vec<(String, usize)>,
+    /// IDs seen on exactly one event
+    pub singletons: usize,
+}
+
+/// A correlation ID that looks shared by unrelated work, see [`EventManager::detect_correlation_anomalies`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorrelationAnomaly {
+    /// The ID's events are spread over longer than any single request should take
+    LongWindow { correlation_id: String, first: DateTime<Utc>, last: DateTime<Utc>, events: usize },
+    /// The ID's events fired under different root spans, i.e. in separate requests.
+    /// Holds each root span's id and display name.
+    DisjointRoots { correlation_id: String, roots: Vec<(u64, String)> },
+}
+
+/// Complete state of an [`EventManager`], see [`EventManager::snapshot_state`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManagerSnapshot {
+    pub max_events: usize,
+    pub max_bytes: Option<usize>,
+    /// Buffered events, oldest first
+    pub events: Vec<Event>,
+    /// Closed spans, newest first
+    pub closed_spans: Vec<SpanInfo>,
+    /// See [`EventManager::dropped_count`]
+    #[serde(default)]
+    pub dropped_count: u64,
+}
+
+/// Split off the newest `count` events, in no particular order, leaving the older ones behind
+fn split_newest(events: &mut Vec<Event>, count: usize) -> Vec<Event> {
+    events.sort_by_key(|event| event.event_data.order_key());
+    events.split_off(events.len().saturating_sub(count))
+}
+
+/// Run an overflow hook taken with [`EventManager::take_overflow`], once the lock is released
+fn run_overflow(overflow: Option<(OverflowHook, Vec<Event>)>) {
+    if let Some((hook, events)) = overflow {
+        events.iter().for_each(|event| hook(event));
+    }
 }
 
-impl Deref for EventManager {
+impl<S: EventStore> Deref for EventManager<S> {
     type Target = EventTarget<Event>;
 
     fn deref(&self) -> &Self::Target { &self.target }
 }
 
 impl EventManager {
-    pub fn new(max_events: Option<usize>) -> Self {
-        Self { inner: Default::default(), target: Default::default(), max_events: max_events.unwrap_or(12_000) }
+    pub fn new(max_events: Option<usize>) -> Self { Self::with_store(InMemoryStore::new(), max_events) }
+
+    /// Rebuild a manager from [`EventManager::snapshot_state`], e.g. to resume a saved
+    /// debugging session in a new process. Listeners aren't part of the state and start empty.
+    pub fn restore_state(snapshot: ManagerSnapshot) -> Self {
+        let mut manager = Self::new(Some(snapshot.max_events));
+        manager.max_bytes = snapshot.max_bytes;
+        snapshot.events.into_iter().for_each(|event| manager.push(event));
+        snapshot.closed_spans.into_iter().rev().for_each(|span| manager.push_closed_span(span));
+        manager.dropped_count += snapshot.dropped_count;
+        manager
+    }
+}
+
+impl<S: EventStore> EventManager<S> {
+    /// Create a manager backed by a custom storage backend
+    pub fn with_store(store: S, max_events: Option<usize>) -> Self {
+        Self {
+            inner: store,
+            target: Default::default(),
+            max_events: max_events.unwrap_or(12_000),
+            max_bytes: None,
+            size_bytes: 0,
+            closed_spans: VecDeque::new(),
+            interner: Interner::default(),
+            correlations: None,
+            overflow_hook: None,
+            overflowed: Vec::new(),
+            dropped_count: 0,
+            dropped_at_drain: 0,
+        }
+    }
+
+    /// Cap the buffer by estimated memory use as well as by event count
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.set_max_bytes(Some(max_bytes));
+        self
+    }
+
+    /// Set or remove the memory budget, evicting the oldest events if now over budget.
+    ///
+    /// Sizes come from [`Event::estimated_size`], an approximation rather than exact
+    /// allocator usage. The newest event is always kept, even if it alone exceeds the budget.
+    pub fn set_max_bytes(&mut self, max_bytes: Option<usize>) {
+        self.max_bytes = max_bytes;
+        self.evict();
+        self.run_overflow_hook();
+    }
+
+    /// Call `hook` with every event evicted from now on because the buffer is over its count
+    /// or memory limit, e.g. to count lost events in a metric or spill them to disk. Replaces
+    /// any previous hook.
+    ///
+    /// For events stored through the global manager (by [`emit`], the tracing layer and the
+    /// `import_and_merge_*` functions) the hook runs after the buffer lock is released, so it
+    /// may query the global manager.
+    pub fn on_overflow(&mut self, hook: impl Fn(&Event) + Send + Sync + 'static) {
+        self.overflow_hook = Some(Arc::new(hook));
+    }
+
+    /// Number of events evicted because the buffer was over its count or memory limit, or
+    /// left out of a merge for lack of room. Clearing the manager doesn't reset it.
+    pub fn dropped_count(&self) -> u64 { self.dropped_count }
+
+    /// Keep a copy of each correlation's events until its trace completes, so a request's
+    /// early events survive the buffer evicting them, see [`EventManager::set_correlation_retention`]
+    pub fn with_correlation_retention(mut self, max_in_flight: usize, idle_timeout: Duration) -> Self {
+        self.set_correlation_retention(Some((max_in_flight, idle_timeout)));
+        self
+    }
+
+    /// Enable or disable retaining events per correlation id, independent of eviction.
+    ///
+    /// A trace completes once every root span its events fired under has closed, or when no
+    /// event for it arrives within `idle_timeout` (in event time, checked as new correlations
+    /// start). Completed traces stay queryable with [`EventManager::get_correlation_trace`].
+    /// Beyond `max_in_flight` open correlations the least recently seen one is completed early,
+    /// and the same number of completed traces is kept. Each trace keeps at most `max_events`
+    /// events, and retained events are copies, so memory use is on top of the buffer's.
+    /// Events whose id was generated for them alone, outside
+    /// [`with_captured_context`](crate::with_captured_context), aren't retained, and neither are
+    /// events merged or imported from elsewhere.
+    pub fn set_correlation_retention(&mut self, retention: Option<(usize, Duration)>) {
+        self.correlations = retention.map(|(max_in_flight, idle_timeout)| CorrelationRetention {
+            max_in_flight,
+            idle_timeout,
+            in_flight: HashMap::new(),
+            by_last_seen: BTreeSet::new(),
+            completed: VecDeque::new(),
+        });
+    }
+
+    /// Every retained event with this correlation id, oldest first, whether or not the trace
+    /// has completed. `None` without correlation retention or once the trace has been dropped.
+    pub fn get_correlation_trace(&self, correlation_id: &str) -> Option<Vec<&Event>> {
+        self.correlations.as_ref()?.get(correlation_id)
+    }
+
+    /// Age of the newest buffered event, see [`Event::age`]. In a normally busy service, a
+    /// value that keeps growing means capture has stopped.
+    pub fn newest_event_age(&self) -> Option<Duration> { self.inner.iter().next().map(Event::age) }
+
+    /// Approximate heap and inline size of all buffered events, in bytes
+    pub fn estimated_size(&self) -> usize { self.size_bytes }
+
+    /// Capture the buffered events, closed spans and limits, serializable so a session can be
+    /// saved and restored later with [`EventManager::restore_state`].
+    ///
+    /// Unlike an export this keeps the manager's configuration and span history. Counts such
+    /// as [`EventManager::summary`] come back as well: most are derived from the events, and
+    /// the [`dropped_count`](EventManager::dropped_count) is saved alongside them.
+    pub fn snapshot_state(&self) -> ManagerSnapshot {
+        ManagerSnapshot {
+            max_events: self.max_events,
+            max_bytes: self.max_bytes,
+            events: self.inner.iter().rev().cloned().collect(),
+            closed_spans: self.closed_spans.iter().cloned().collect(),
+            dropped_count: self.dropped_count,
+        }
     }
 
+    /// Access the underlying storage backend
+    pub fn store(&self) -> &S { &self.inner }
+
+    /// Store an event, evicting the oldest ones once over the count or memory limit.
+    ///
+    /// The first push into an empty manager reserves room for `max_events` events (up to
+    /// 65,536), so filling the buffer doesn't reallocate repeatedly and a full buffer never
+    /// reallocates again. The reservation is one pointer per event, not the events themselves;
+    /// call [`EventManager::shrink_to_fit`] after clearing to give it back.
     pub fn push(&mut self, event: Event) {
-        self.inner.push_front(event);
-        if self.inner.len() > self.max_events {
-            let _ = self.inner.pop_back();
+        self.push_deferring_overflow(event);
+        self.run_overflow_hook();
+    }
+
+    /// Like [`EventManager::push`], leaving evicted events for [`EventManager::take_overflow`]
+    /// so the overflow hook can run once the caller has released its lock. Returns the stored
+    /// event, for handing to listeners without copying it.
+    fn push_deferring_overflow(&mut self, event: Event) -> Arc<Event> {
+        if let (Some(retention), Some(correlation_id)) = (&mut self.correlations, &event.correlation_id)
+            && !event.correlation_id_generated
+        {
+            retention.record(correlation_id, &event, self.max_events);
+        }
+        self.insert_deferring_overflow(event)
+    }
+
+    /// Like [`EventManager::push_deferring_overflow`], without recording the event for
+    /// correlation retention. For events re-inserted by a merge, which were recorded when first
+    /// pushed, and imported ones, whose generated-id flag doesn't survive a dump.
+    fn insert_deferring_overflow(&mut self, mut event: Event) -> Arc<Event> {
+        if self.inner.is_empty() {
+            // One extra slot, since the newest event is stored before the oldest is evicted
+            self.inner.reserve(self.max_events.saturating_add(1).min(MAX_PREALLOCATED_EVENTS));
+        }
+        self.intern_event(&mut event);
+        self.size_bytes += event.estimated_size();
+        let event = Arc::new(event);
+        self.inner.push(event.clone());
+        self.evict();
+        event
+    }
+
+    /// Point the event's repeated strings at the shared copies held by the interner
+    fn intern_event(&mut self, event: &mut Event) {
+        let data = &mut event.event_data;
+        self.interner.intern(&mut data.target);
+        if let Some(module_path) = &mut data.module_path {
+            self.interner.intern(module_path);
+        }
+        event.span_stack.iter_mut().chain(event.current_span.as_mut()).for_each(|span| self.intern_span(span));
+    }
+
+    fn intern_span(&mut self, span: &mut SpanInfo) {
+        self.interner.intern(&mut span.name);
+        self.interner.intern(&mut span.target);
+        if let Some(module_path) = &mut span.module_path {
+            self.interner.intern(module_path);
+        }
+        span.children.iter_mut().for_each(|child| self.intern_span(child));
+    }
+
+    /// The overflow hook and the events evicted since it last ran, if any
+    fn take_overflow(&mut self) -> Option<(OverflowHook, Vec<Event>)> {
+        if self.overflowed.is_empty() {
+            return None;
+        }
+        Some((self.overflow_hook.clone()?, std::mem::take(&mut self.overflowed)))
+    }
+
+    fn run_overflow_hook(&mut self) { run_overflow(self.take_overflow()); }
+
+    fn record_overflow(&mut self, event: Event) {
+        self.size_bytes = self.size_bytes.saturating_sub(event.estimated_size());
+        self.record_dropped(event);
+    }
+
+    /// Count an event that didn't fit in the buffer and queue it for the overflow hook
+    fn record_dropped(&mut self, event: Event) {
+        self.dropped_count += 1;
+        if self.overflow_hook.is_some() {
+            self.overflowed.push(event);
         }
     }
 
+    /// Drop the oldest events until both the count and byte limits are respected
+    fn evict(&mut self) {
+        while let Some(event) = self.inner.pop_over_capacity() {
+            self.record_overflow(event);
+        }
+
+        let over_budget =
+            |manager: &Self| manager.max_bytes.is_some_and(|max| manager.size_bytes > max && manager.len() > 1);
+        while self.inner.len() > self.max_events || over_budget(self) {
+            match self.inner.pop_oldest() {
+                Some(event) => self.record_overflow(event),
+                None => break,
+            }
+        }
+    }
+
+    /// Remove all events and closed spans
+    pub fn clear(&mut self) {
+        self.inner.clear();
+        self.size_bytes = 0;
+        self.closed_spans.clear();
+        if let Some(retention) = &mut self.correlations {
+            retention.in_flight.clear();
+            retention.by_last_seen.clear();
+            retention.completed.clear();
+        }
+        self.interner.prune();
+    }
+
+    /// Release memory reserved beyond what the buffered events and closed spans need, e.g.
+    /// after clearing a large buffer. The next push into an empty manager reserves again.
+    pub fn shrink_to_fit(&mut self) {
+        self.inner.shrink_to_fit();
+        self.closed_spans.shrink_to_fit();
+    }
+
+    /// Record a span that has closed, dropping the oldest once over capacity
+    pub fn push_closed_span(&mut self, mut span: SpanInfo) {
+        self.intern_span(&mut span);
+        if let Some(retention) = &mut self.correlations {
+            retention.close_root(&span);
+        }
+        self.closed_spans.push_front(span);
+        self.closed_spans.truncate(self.max_events);
+    }
+
+    /// Iterate over closed spans, newest first
+    pub fn closed_spans(&self) -> impl DoubleEndedIterator<Item = &SpanInfo> { self.closed_spans.iter() }
+
+    /// Duration percentiles across closed spans with the given name or display name.
+    ///
+    /// Percentiles range from `0.0` to `100.0` and use the nearest-rank method, so every
+    /// result is an observed duration and few samples simply repeat the nearest one. Returns
+    /// `(percentile, duration)` pairs in the requested order, or nothing without samples.
+    pub fn span_duration_percentiles(&self, span_name: &str, percentiles: &[f64]) -> Vec<(f64, Duration)> {
+        let mut durations: Vec<Duration> = self
+            .closed_spans
+            .iter()
+            .filter(|span| span.name == span_name || span.display_name() == span_name)
+            .filter_map(|span| span.duration)
+            .collect();
+        if durations.is_empty() {
+            return Vec::new();
+        }
+        durations.sort();
+
+        let last = durations.len() - 1;
+        percentiles
+            .iter()
+            .map(|&percentile| {
+                let rank = (percentile.clamp(0.0, 100.0) / 100.0 * durations.len() as f64).ceil() as usize;
+                (percentile, durations[rank.saturating_sub(1).min(last)])
+            })
+            .collect()
+    }
+
     pub fn len(&self) -> usize { self.inner.len() }
 
     pub fn is_empty(&self) -> bool { self.inner.is_empty() }
 
     /// Emit an event (both store it and notify listeners)
     pub fn emit(&mut self, event: Event) {
-        self.push(event.clone());
+        let event = self.push_deferring_overflow(event);
+        self.run_overflow_hook();
         self.target.emit(event);
     }
 
     /// Get events by level
-    pub fn get_by_level(&self, level: Level) -> Vec<&Event> {
-        self.inner.iter().filter(|event| event.event_data.level == level).collect()
-    }
+    pub fn get_by_level(&self, level: Level) -> Vec<&Event> { self.inner.get_by_level(level) }
 
     /// Get events by target (module/crate)
     pub fn get_by_target(&self, target: &str) -> Vec<&Event> {
-        self.inner.iter().filter(|event| event.event_data.target.contains(target)).collect()
+        self.inner.filter(&|event| event.event_data.target.contains(target))
     }
 
     /// Get events within a specific span
     pub fn get_by_span(&self, span_name: &str) -> Vec<&Event> {
-        self.inner
-            .iter()
-            .filter(|event| {
-                event.span_stack.iter().chain(event.current_span.iter()).any(|span| span.name.contains(span_name))
-            })
-            .collect()
+        self.inner.filter(&|event| {
+            event.span_stack.iter().chain(event.current_span.iter()).any(|span| span.name_contains(span_name))
+        })
+    }
+
+    /// Get events whose source file contains `file_substr`, optionally within an inclusive
+    /// line range. Events without a recorded line are excluded when a range is given.
+    pub fn get_by_location(&self, file_substr: &str, line_range: Option<(u32, u32)>) -> Vec<&Event> {
+        self.inner.filter(&|event| {
+            let data = &event.event_data;
+            data.file.as_ref().is_some_and(|file| file.contains(file_substr))
+                && line_range.is_none_or(|(start, end)| data.line.is_some_and(|line| (start..=end).contains(&line)))
+        })
+    }
+
+    /// Get events that fired outside any span
+    pub fn get_orphans(&self) -> Vec<&Event> {
+        self.inner.filter(&|event| event.span_stack.is_empty() && event.current_span.is_none())
     }
 
     /// Get events by thread
     pub fn get_by_thread(&self, thread_id: &str) -> Vec<&Event> {
-        self.inner.iter().filter(|event| event.thread_id.as_ref().is_some_and(|id| id == thread_id)).collect()
+        self.inner.filter(&|event| event.thread_id.as_ref().is_some_and(|id| id == thread_id))
     }
 
     /// Get events with specific correlation ID
     pub fn get_by_correlation_id(&self, correlation_id: &str) -> Vec<&Event> {
-        self.inner.iter().filter(|event| event.correlation_id.as_ref().is_some_and(|id| id == correlation_id)).collect()
+        self.inner.filter(&|event| event.correlation_id.as_ref().is_some_and(|id| id == correlation_id))
+    }
+
+    /// Get events produced by the given kind of source, e.g. only panics
+    pub fn get_by_kind(&self, kind: EventKind) -> Vec<&Event> { self.inner.filter(&|event| event.kind == kind) }
+
+    /// Get events that fired inside the async task with the given name
+    pub fn get_by_task_name(&self, task_name: &str) -> Vec<&Event> {
+        self.inner.filter(&|event| event.task_name.as_deref() == Some(task_name))
     }
 
     /// Advanced search with multiple criteria
@@ -88,185 +600,2833 @@ impl EventManager {
         message_contains: Option<&str>,
         span_name_contains: Option<&str>,
     ) -> Vec<&Event> {
-        self.inner
-            .iter()
-            .filter(|event| event.matches_criteria(level_filter, target_filter, message_contains, span_name_contains))
-            .collect()
+        self.inner.filter(&|event| event.matches_criteria(level_filter, target_filter, message_contains, span_name_contains))
     }
 
-    /// Get the most recent N events
-    pub fn get_recent(&self, count: usize) -> Vec<&Event> { self.inner.iter().take(count).collect() }
-}
+    /// Like [`EventManager::search`], with `mode` choosing whether the target and message
+    /// filters are substrings or regular expressions. Patterns are compiled once per call, and
+    /// an invalid one is returned as [`SpannerError::InvalidRegex`] rather than panicking.
+    /// Regular expressions need the `regex` feature; without it they fail with
+    /// [`SpannerError::Unsupported`].
+    ///
+    /// To export regex matches, build a [`SearchQuery`] with [`SearchQuery::target_regex`] or
+    /// [`SearchQuery::message_regex`] and pass it to [`export_query_to_bin_file`].
+    pub fn search_with_mode(
+        &self,
+        mode: MatchMode,
+        level_filter: Option<Level>,
+        target_filter: Option<&str>,
+        message_filter: Option<&str>,
+        span_name_contains: Option<&str>,
+    ) -> Result<Vec<&Event>, SpannerError> {
+        if mode == MatchMode::Substring {
+            return Ok(self.search(level_filter, target_filter, message_filter, span_name_contains));
+        }
 
-/// Initialize the global event manager
-pub fn init_global_event_manager() { let _ = GLOBAL_EVENT_MANAGER.set(Arc::new(RwLock::new(EventManager::new(None)))); }
+        let target = target_filter.map(Pattern::new).transpose()?;
+        let message = message_filter.map(Pattern::new).transpose()?;
+        Ok(self.inner.filter(&|event| {
+            event.matches_criteria_regex(level_filter, target.as_ref(), message.as_ref(), span_name_contains)
+        }))
+    }
 
-/// Initialize the global event manager with max event count
-pub fn init_global_event_manager_with_count(max_events: usize) {
-    let _ = GLOBAL_EVENT_MANAGER.set(Arc::new(RwLock::new(EventManager::new(Some(max_events)))));
-}
+    /// Like [`EventManager::search`], only keeping events with timestamps within `after` and
+    /// `before`, both inclusive, e.g. the five minutes around an incident
+    pub fn search_in_range(
+        &self,
+        level_filter: Option<Level>,
+        target_filter: Option<&str>,
+        message_contains: Option<&str>,
+        span_name_contains: Option<&str>,
+        after: Option<DateTime<Utc>>,
+        before: Option<DateTime<Utc>>,
+    ) -> Vec<&Event> {
+        self.inner.filter(&|event| {
+            event.matches_criteria_in_range(level_filter, target_filter, message_contains, span_name_contains, after, before)
+        })
+    }
 
-/// Get a copy of all events from the global manager
-pub fn get_global_events() -> Option<Vec<Event>> { Some(GLOBAL_EVENT_MANAGER.get()?.read().ok()?.inner.clone().into()) }
+    /// Get all events matching a query, newest first.
+    ///
+    /// With a fuzzy message criterion, results are ordered by similarity score instead.
+    pub fn query(&self, query: &SearchQuery) -> Vec<&Event> {
+        let mut results = self.inner.filter(&|event| event.matches(query));
 
-/// Get the number of events in the global manager
-pub fn get_global_event_count() -> usize {
-    GLOBAL_EVENT_MANAGER.get().and_then(|v| v.read().map(|v| v.inner.len()).ok()).unwrap_or(0)
-}
+        if let Some(fuzzy) = &query.message_fuzzy {
+            let score = |event: &Event| fuzzy.score(&event.event_data.message).unwrap_or_default();
+            results.sort_by(|a, b| score(b).total_cmp(&score(a)));
+        }
 
-pub(crate) fn emit(event: Event) -> Option<()> {
-    GLOBAL_EVENT_MANAGER.get()?.write().ok()?.emit(event);
-    Some(())
-}
+        results
+    }
 
-/// Get access to the global event target for emitting events
-pub fn events() -> Option<EventTarget<Event>> { Some(GLOBAL_EVENT_MANAGER.get()?.read().ok()?.target.clone()) }
+    /// Get events from the last `duration`, newest first
+    pub fn get_last(&self, duration: Duration) -> Vec<&Event> {
+        self.get_in_range(cutoff(duration), DateTime::<Utc>::MAX_UTC)
+    }
 
-/// Clear all events from the global manager
-pub fn clear_global_events() {
-    if let Some(mut global) = GLOBAL_EVENT_MANAGER.get().and_then(|v| v.write().ok()) {
-        global.inner.clear();
+    /// Get events with timestamps between `start` and `end` inclusive, newest first.
+    ///
+    /// The default store keeps events sorted by time, so this binary searches the bounds
+    /// instead of scanning the whole buffer (see [`InMemoryStore`]).
+    pub fn get_in_range(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<&Event> { self.inner.range(start, end) }
+
+    /// The oldest buffered event matching a query, stopping at the first match
+    pub fn find_first(&self, query: &SearchQuery) -> Option<&Event> {
+        self.inner.iter().rev().find(|event| event.matches(query))
     }
-}
 
-/// Export format for binary files
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ExportMetadata {
-    pub version: String,
-    pub timestamp: DateTime<Utc>,
-    pub total_events: usize,
-    pub level_counts: BTreeMap<String, usize>,
-    pub description: Option<String>,
-}
+    /// The newest buffered event matching a query, stopping at the first match
+    pub fn find_last(&self, query: &SearchQuery) -> Option<&Event> { self.inner.iter().find(|event| event.matches(query)) }
 
-/// Container for exported data
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ExportData {
-    pub metadata: ExportMetadata,
-    pub events: Vec<Event>,
-}
+    /// Like [`EventManager::get_by_level`], returning shared handles that stay valid after
+    /// the manager (or the lock guarding it) is released
+    pub fn get_by_level_arc(&self, level: Level) -> Vec<Arc<Event>> {
+        self.inner.filter_shared(&|event| event.event_data.level == level)
+    }
 
-/// Export all events to a binary file
-pub fn export_to_bin_file<P: AsRef<Path>>(path: P) -> io::Result<usize> {
-    let events = get_global_events().unwrap_or_default();
-    let export_data = create_export_data(events, None);
+    /// Like [`EventManager::query`], returning shared handles that stay valid after the
+    /// manager (or the lock guarding it) is released
+    pub fn query_arc(&self, query: &SearchQuery) -> Vec<Arc<Event>> {
+        let mut results = self.inner.filter_shared(&|event| event.matches(query));
 
-    let encoded = serde_json::to_vec(&export_data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(fuzzy) = &query.message_fuzzy {
+            let score = |event: &Event| fuzzy.score(&event.event_data.message).unwrap_or_default();
+            results.sort_by(|a, b| score(b).total_cmp(&score(a)));
+        }
 
-    let mut file = File::create(path)?;
-    file.write_all(&encoded)?;
-    file.flush()?;
+        results
+    }
 
-    Ok(export_data.events.len())
-}
+    /// Find periods of at least `min_gap` with no buffered events, oldest first.
+    ///
+    /// Each interval runs from the timestamp of the last event before the silence to the
+    /// first event after it. A gap in a normally busy service can point to a stall or deadlock,
+    /// but it can just as well mean nothing happened, so treat results as leads to check.
+    pub fn find_gaps(&self, min_gap: Duration) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        let mut timestamps: Vec<_> = self.inner.iter().map(|event| event.event_data.timestamp).collect();
+        timestamps.sort();
+        timestamps
+            .windows(2)
+            .filter(|pair| (pair[1] - pair[0]).to_std().is_ok_and(|gap| gap >= min_gap))
+            .map(|pair| (pair[0], pair[1]))
+            .collect()
+    }
 
-/// Export events with filtering to a binary file
-pub fn export_filtered_to_bin_file<P: AsRef<Path>>(
-    path: P,
-    level_filter: Option<Level>,
-    target_filter: Option<&str>,
-    message_contains: Option<&str>,
-    span_name_contains: Option<&str>,
-    description: Option<String>,
-) -> io::Result<usize> {
-    let all_events = get_global_events().unwrap_or_default();
-    let filtered_events: Vec<Event> = all_events
-        .into_iter()
-        .filter(|event| event.matches_criteria(level_filter, target_filter, message_contains, span_name_contains))
-        .collect();
+    /// Count buffered events per correlation ID, keeping the `top_n` busiest.
+    ///
+    /// IDs with unusually many events often point at retry storms or slow requests. Ties are
+    /// ordered by ID and events without a correlation ID are ignored.
+    pub fn correlation_summary(&self, top_n: usize) -> CorrelationSummary {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for id in self.inner.iter().filter_map(|event| event.correlation_id.as_deref()) {
+            *counts.entry(id).or_default() += 1;
+        }
 
-    let export_data = create_export_data(filtered_events, description);
+        let singletons = counts.values().filter(|&&count| count == 1).count();
+        let mut top: Vec<_> = counts.iter().map(|(id, count)| (id.to_string(), *count)).collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(top_n);
 
-    let encoded = serde_json::to_vec(&export_data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        CorrelationSummary { distinct: counts.len(), top, singletons }
+    }
 
-    let mut file = File::create(path)?;
-    file.write_all(&encoded)?;
-    file.flush()?;
+    /// The `n` threads with the most events in the last `window`, busiest first, ties by name.
+    /// Events without a thread id are ignored.
+    pub fn top_threads(&self, window: Duration, n: usize) -> Vec<(String, usize)> {
+        self.top_by(window, n, |event| event.thread_id.as_deref())
+    }
 
-    Ok(export_data.events.len())
-}
+    /// The `n` targets with the most events in the last `window`, busiest first, ties by name
+    pub fn top_targets(&self, window: Duration, n: usize) -> Vec<(String, usize)> {
+        self.top_by(window, n, |event| Some(event.event_data.target.as_str()))
+    }
 
-/// Get binary data for export without writing to file
-pub fn export_to_bin_data() -> Result<Vec<u8>, serde_json::Error> {
-    let events = get_global_events().unwrap_or_default();
-    let export_data = create_export_data(events, None);
-    serde_json::to_vec(&export_data)
-}
+    fn top_by<'a>(&'a self, window: Duration, n: usize, key: impl Fn(&'a Event) -> Option<&'a str>) -> Vec<(String, usize)> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for event in self.get_last(window) {
+            if let Some(key) = key(event) {
+                *counts.entry(key).or_default() += 1;
+            }
+        }
 
-/// Import events from a binary file and return a new EventManager
-pub fn import_from_bin_file<P: AsRef<Path>>(path: P) -> io::Result<EventManager> {
-    let data = std::fs::read(path)?;
-    let export_data: ExportData =
-        serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut top: Vec<_> = counts.into_iter().map(|(key, count)| (key.to_string(), count)).collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(n);
+        top
+    }
 
-    // Create a new EventManager with the imported events
-    let mut manager = EventManager::new(None);
-    for event in export_data.events {
-        manager.push(event);
+    /// The `n` most common error message templates, most frequent first, ties by template.
+    ///
+    /// Messages are reduced with [`error_signature`], so errors differing only in ids or
+    /// numbers count as one problem; use [`EventManager::top_error_signatures_with`] to
+    /// normalize differently.
+    pub fn top_error_signatures(&self, n: usize) -> Vec<(String, usize)> {
+        self.top_error_signatures_with(n, error_signature)
     }
 
-    Ok(manager)
-}
+    /// Like [`EventManager::top_error_signatures`], with `normalize` turning each error message
+    /// into its template
+    pub fn top_error_signatures_with(&self, n: usize, normalize: impl Fn(&str) -> String) -> Vec<(String, usize)> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for event in self.inner.iter().filter(|event| event.event_data.level() == Level::ERROR) {
+            *counts.entry(normalize(&event.event_data.message)).or_default() += 1;
+        }
+
+        let mut top: Vec<_> = counts.into_iter().collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(n);
+        top
+    }
 
-/// Import events from a binary file and add to global manager
-pub fn import_and_merge_from_bin_file<P: AsRef<Path>>(path: P) -> io::Result<(ExportData, usize)> {
-    let data = std::fs::read(path)?;
-    let export_data: ExportData =
-        serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    /// Flag correlation IDs that look like collisions or leaked IDs, ordered by ID.
+    ///
+    /// An ID is flagged when its events span more than `max_window`, or when they fired under
+    /// more than one root span. Either can be legitimate (a long-running job, or work handed to
+    /// a detached task), so the anomalies carry the evidence needed to judge.
+    pub fn detect_correlation_anomalies(&self, max_window: Duration) -> Vec<CorrelationAnomaly> {
+        #[derive(Default)]
+        struct Seen<'a> {
+            first: Option<DateTime<Utc>>,
+            last: Option<DateTime<Utc>>,
+            events: usize,
+            roots: BTreeMap<(u64, DateTime<Utc>), &'a SpanInfo>,
+        }
 
-    // Add imported events to the global manager
-    if let Some(global) = GLOBAL_EVENT_MANAGER.get().and_then(|v| v.write().ok()) {
-        let mut manager = global;
-        for event in &export_data.events {
-            manager.push(event.clone());
+        let mut by_id: BTreeMap<&str, Seen> = BTreeMap::new();
+        for event in self.inner.iter() {
+            let Some(id) = event.correlation_id.as_deref() else { continue };
+            let seen = by_id.entry(id).or_default();
+            let timestamp = event.event_data.timestamp;
+            seen.first = Some(seen.first.map_or(timestamp, |first| first.min(timestamp)));
+            seen.last = seen.last.max(Some(timestamp));
+            seen.events += 1;
+            if let Some(root) = event.span_stack.first() {
+                seen.roots.insert((root.id, root.entered_at), root);
+            }
+        }
+
+        let mut anomalies = Vec::new();
+        for (id, seen) in by_id {
+            if let (Some(first), Some(last)) = (seen.first, seen.last)
+                && (last - first).to_std().is_ok_and(|window| window > max_window)
+            {
+                anomalies.push(CorrelationAnomaly::LongWindow {
+                    correlation_id: id.to_string(),
+                    first,
+                    last,
+                    events: seen.events,
+                });
+            }
+            if seen.roots.len() > 1 {
+                let roots = seen.roots.values().map(|root| (root.id, root.display_name().to_string())).collect();
+                anomalies.push(CorrelationAnomaly::DisjointRoots { correlation_id: id.to_string(), roots });
+            }
         }
+        anomalies
     }
 
-    let imported_count = export_data.events.len();
-    Ok((export_data, imported_count))
-}
+    /// Get the most recent N events
+    pub fn get_recent(&self, count: usize) -> Vec<&Event> { self.inner.get_recent(count) }
 
-/// Create export data structure with metadata
-fn create_export_data(events: Vec<Event>, description: Option<String>) -> ExportData {
-    let total_events = events.len();
-    let mut level_counts = BTreeMap::new();
+    /// Merge another manager's events into this one.
+    ///
+    /// Events from both managers are interleaved by timestamp and the oldest are evicted if
+    /// the combined set exceeds this manager's capacity. Listeners are not notified.
+    pub fn merge_from<T: EventStore>(&mut self, other: EventManager<T>) {
+        self.merge_with_policy(other, ImportMergePolicy::InterleaveByTime)
+    }
 
-    for event in &events {
-        let level_str = format!("{}", event.event_data.level);
-        *level_counts.entry(level_str).or_insert(0) += 1;
+    /// Merge another manager's events into this one, using `policy` to choose which events
+    /// to keep when the combined set exceeds this manager's event capacity.
+    ///
+    /// The kept events are interleaved by timestamp, and the rest go to the overflow hook and
+    /// [`EventManager::dropped_count`] like evicted ones. Listeners are not notified.
+    pub fn merge_with_policy<T: EventStore>(&mut self, other: EventManager<T>, policy: ImportMergePolicy) {
+        self.merge_deferring_overflow(other, policy);
+        self.run_overflow_hook();
     }
 
-    let metadata = ExportMetadata {
-        version: env!("CARGO_PKG_VERSION").to_string(),
-        timestamp: Utc::now(),
-        total_events,
-        level_counts,
-        description,
-    };
+    /// Like [`EventManager::merge_with_policy`], leaving dropped events for
+    /// [`EventManager::take_overflow`]
+    fn merge_deferring_overflow<T: EventStore>(&mut self, mut other: EventManager<T>, policy: ImportMergePolicy) {
+        let drain = |store: &mut dyn EventStore| std::iter::from_fn(|| store.pop_oldest()).collect::<Vec<_>>();
+        let live = drain(&mut self.inner);
+        let imported = drain(&mut other.inner);
+        self.size_bytes = 0;
 
-    ExportData { metadata, events }
-}
+        let (mut preferred, mut rest) = match policy {
+            ImportMergePolicy::PreferImported => (imported, live),
+            ImportMergePolicy::PreferLive => (live, imported),
+            ImportMergePolicy::InterleaveByTime => (live.into_iter().chain(imported).collect(), Vec::new()),
+        };
+        let mut events = split_newest(&mut preferred, self.max_events);
+        events.extend(split_newest(&mut rest, self.max_events - events.len()));
 
-/// Get summary of events without exporting
-pub fn get_event_summary() -> String {
-    if let Some(global) = GLOBAL_EVENT_MANAGER.get().and_then(|v| v.read().ok()) {
-        let total = global.len();
-        let by_level = [
-            (Level::ERROR, global.get_by_level(Level::ERROR).len()),
-            (Level::WARN, global.get_by_level(Level::WARN).len()),
-            (Level::INFO, global.get_by_level(Level::INFO).len()),
-            (Level::DEBUG, global.get_by_level(Level::DEBUG).len()),
-            (Level::TRACE, global.get_by_level(Level::TRACE).len()),
-        ];
-
-        let mut summary = format!("Event Summary: {} total events\n", total);
+        events.sort_by_key(|event| event.event_data.order_key());
+        for event in events {
+            self.insert_deferring_overflow(event);
+        }
+        preferred.into_iter().chain(rest).for_each(|event| self.record_dropped(event));
+    }
+
+    /// Human readable counts by level, plus how many events fired outside any span
+    pub fn summary(&self) -> String {
+        let by_level = [Level::ERROR, Level::WARN, Level::INFO, Level::DEBUG, Level::TRACE]
+            .map(|level| (level, self.get_by_level(level).len()));
+
+        let mut summary = format!("Event Summary: {} total events\n", self.len());
         for (level, count) in by_level {
             if count > 0 {
                 summary.push_str(&format!("  {}: {}\n", level, count));
             }
         }
 
-        return summary;
+        let orphans = self.get_orphans().len();
+        if orphans > 0 {
+            summary.push_str(&format!("  {} events outside any span\n", orphans));
+        }
+        if self.dropped_count > 0 {
+            summary.push_str(&format!("  Dropped (buffer full): {}\n", self.dropped_count));
+        }
+
+        summary
+    }
+
+    /// The `n` closed spans that took longest, slowest first
+    pub fn slowest_spans(&self, n: usize) -> Vec<&SpanInfo> {
+        let mut spans: Vec<&SpanInfo> = self.closed_spans.iter().filter(|span| span.duration.is_some()).collect();
+        spans.sort_by_key(|span| std::cmp::Reverse(span.duration));
+        spans.truncate(n);
+        spans
+    }
+
+    /// End-of-run report: the [`EventManager::summary`], then the most common
+    /// [error signatures](EventManager::top_error_signatures) and the slowest spans
+    pub fn report(&self) -> String {
+        let mut report = self.summary();
+
+        report.push_str("Top errors:\n");
+        let errors = self.top_error_signatures(REPORT_ROWS);
+        if errors.is_empty() {
+            report.push_str("  none\n");
+        }
+        for (signature, count) in errors {
+            report.push_str(&format!("  {}x {}\n", count, signature));
+        }
+
+        report.push_str("Slowest spans:\n");
+        let spans = self.slowest_spans(REPORT_ROWS);
+        if spans.is_empty() {
+            report.push_str("  none\n");
+        }
+        for span in spans {
+            let duration = span.duration.unwrap_or_default();
+            report.push_str(&format!("  {:?} {} ({})\n", duration, span.display_name(), span.target));
+        }
+
+        report
+    }
+
+    /// Group events into trees by their [`parent`](Event::parent) links, newest first.
+    ///
+    /// Events without a parent are roots. Events whose parent isn't buffered (e.g. it was
+    /// evicted) become roots too, marked with [`EventNode::dangling_parent`].
+    pub fn build_event_forest(&self) -> Vec<EventNode<'_>> {
+        let events: Vec<&Event> = self.iter().collect();
+        let mut index_of = HashMap::new();
+        for (index, event) in events.iter().enumerate() {
+            index_of.entry(event.id).or_insert(index);
+        }
+
+        let mut roots = Vec::new();
+        let mut children: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (index, event) in events.iter().enumerate() {
+            match event.parent.as_ref().and_then(|parent| index_of.get(&parent.id)) {
+                Some(&parent) if parent != index => children.entry(parent).or_default().push(index),
+                _ => roots.push(index),
+            }
+        }
+
+        fn build<'a>(index: usize, events: &[&'a Event], children: &HashMap<usize, Vec<usize>>) -> EventNode<'a> {
+            let nested = children.get(&index).map(Vec::as_slice).unwrap_or_default();
+            EventNode {
+                event: events[index],
+                children: nested.iter().map(|&child| build(child, events, children)).collect(),
+                dangling_parent: false,
+            }
+        }
+
+        roots
+            .into_iter()
+            .map(|index| EventNode { dangling_parent: events[index].parent.is_some(), ..build(index, &events, &children) })
+            .collect()
+    }
+
+    /// Iterate over all events, newest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Event> { self.inner.iter() }
+
+    /// Keep only the events matching the predicate
+    pub fn retain(&mut self, mut f: impl FnMut(&Event) -> bool) {
+        self.inner.retain(&mut f);
+        self.size_bytes = self.inner.iter().map(Event::estimated_size).sum();
+    }
+}
+
+/// Serializes tests that depend on the contents of the global manager
+#[cfg(test)]
+pub(crate) fn global_test_guard() -> std::sync::MutexGuard<'static, ()> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Initialize the global event manager
+pub fn init_global_event_manager() { install_global_manager(EventManager::new(None)); }
+
+/// Initialize the global event manager with max event count
+pub fn init_global_event_manager_with_count(max_events: usize) {
+    install_global_manager(EventManager::new(Some(max_events)));
+}
+
+/// Initialize the global event manager with a memory budget in addition to the default count cap
+pub fn init_global_event_manager_with_max_bytes(max_bytes: usize) {
+    install_global_manager(EventManager::new(None).with_max_bytes(max_bytes));
+}
+
+/// Initialize the global event manager with a separate ring buffer per level, see
+/// [`InMemoryStore::per_level`]. Levels without a capacity aren't captured.
+///
+/// ```
+/// use {std::collections::BTreeMap, tracing::Level};
+///
+/// tracing_spanner::init_with_per_level_buffers(BTreeMap::from([(Level::ERROR, 1_000), (Level::INFO, 10_000)]));
+/// ```
+pub fn init_with_per_level_buffers(capacities: BTreeMap<Level, usize>) {
+    let total = capacities.values().fold(0usize, |total, capacity| total.saturating_add(*capacity));
+    install_global_manager(EventManager::with_store(InMemoryStore::per_level(capacities), Some(total)));
+}
+
+fn install_global_manager(manager: EventManager) {
+    set_and_flush(&GLOBAL_EVENT_MANAGER, &INIT_CALLBACKS, Arc::new(RwLock::new(manager)));
+}
+
+/// Run `f` once the global event manager is available.
+///
+/// Runs immediately if it is already initialized, otherwise when the first
+/// `init_global_event_manager*` call succeeds. Lets library code register listeners
+/// regardless of whether it runs before or after the application initializes Spanner.
+pub fn on_initialized(f: impl FnOnce() + Send + 'static) {
+    run_or_queue(&GLOBAL_EVENT_MANAGER, &INIT_CALLBACKS, Box::new(f));
+}
+
+/// Queue `f` until `cell` is set, or run it straight away if it already is.
+///
+/// The check happens under the queue lock and [`set_and_flush`] sets the cell before taking
+/// that lock, so a callback is either run here or drained there, never lost.
+fn run_or_queue<T>(cell: &OnceLock<T>, queue: &Mutex<Vec<InitCallback>>, f: InitCallback) {
+    let mut pending = queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if cell.get().is_none() {
+        pending.push(f);
+        return;
+    }
+    drop(pending);
+    f();
+}
+
+fn set_and_flush<T>(cell: &OnceLock<T>, queue: &Mutex<Vec<InitCallback>>, value: T) -> bool {
+    if cell.set(value).is_err() {
+        return false;
+    }
+    let callbacks = std::mem::take(&mut *queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+    callbacks.into_iter().for_each(|f| f());
+    true
+}
+
+/// Store a closed span in `manager` directly, for layers bound to their own manager
+pub(crate) fn record_closed_span_into(manager: &RwLock<EventManager>, span: SpanInfo) -> Option<()> {
+    manager.write().ok()?.push_closed_span(span);
+    Some(())
+}
+
+/// Record a closed span in the global manager
+pub(crate) fn record_closed_span(span: SpanInfo) -> Option<()> {
+    let global = GLOBAL_EVENT_MANAGER.get()?;
+    if STAGED_EMIT.load(Ordering::Relaxed) {
+        return staging().send(Staged::ClosedSpan(Box::new(span))).ok();
+    }
+    global.write().ok()?.push_closed_span(span);
+    Some(())
+}
+
+/// Decouple emitting from the buffer lock: while enabled, [`emit`] queues events (and closed
+/// spans) on a channel and returns, and a single background thread stores them in batches
+/// and then notifies listeners.
+///
+/// Emitters then never wait on readers holding the read lock, at the cost of events showing
+/// up in queries slightly later; call [`flush_staged`] to wait for them. Listeners run on
+/// the background thread rather than inline, in the same order. Disabling flushes what is
+/// queued, so later events are stored after it. Off by default.
+pub fn set_staged_emit(enabled: bool) {
+    if enabled {
+        staging();
+        STAGED_EMIT.store(true, Ordering::Relaxed);
+    } else {
+        STAGED_EMIT.store(false, Ordering::Relaxed);
+        flush_staged();
+    }
+}
+
+/// Wait until every event staged so far is stored and its listeners have run, see
+/// [`set_staged_emit`]. Must not be called from a listener, which runs on the thread it waits for.
+pub fn flush_staged() {
+    let Some(staging) = STAGING.get() else { return };
+    let (ack, done) = mpsc::channel();
+    if staging.send(Staged::Flush(ack)).is_ok() {
+        let _ = done.recv();
+    }
+}
+
+/// The staging channel, starting its drainer thread on first use
+fn staging() -> &'static Sender<Staged> {
+    STAGING.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("spanner-drain".to_string())
+            .spawn(move || drain_staged(rx))
+            .expect("failed to spawn the spanner drain thread");
+        tx
+    })
+}
+
+fn drain_staged(rx: Receiver<Staged>) {
+    while let Ok(first) = rx.recv() {
+        let batch: Vec<Staged> = std::iter::once(first).chain(rx.try_iter().take(MAX_STAGED_BATCH - 1)).collect();
+
+        let mut events = Vec::new();
+        let mut waiters = Vec::new();
+        if let Some(mut global) = GLOBAL_EVENT_MANAGER.get().and_then(|v| v.write().ok()) {
+            for staged in batch {
+                match staged {
+                    Staged::Event(event) => {
+                        events.push(global.push_deferring_overflow(*event));
+                    }
+                    Staged::ClosedSpan(span) => global.push_closed_span(*span),
+                    Staged::Flush(ack) => waiters.push(ack),
+                }
+            }
+            let target = global.target.clone();
+            let overflow = global.take_overflow();
+            drop(global);
+            run_overflow(overflow);
+            events.into_iter().for_each(|event| target.emit(event));
+        } else {
+            waiters.extend(batch.into_iter().filter_map(|staged| match staged {
+                Staged::Flush(ack) => Some(ack),
+                _ => None,
+            }));
+        }
+        waiters.into_iter().for_each(|ack| {
+            let _ = ack.send(());
+        });
+    }
+}
+
+/// Get a copy of every span closed while the layer was installed, newest first.
+///
+/// Spans are recorded whatever their target and whether or not any event fired inside them,
+/// so timings of third-party spans (e.g. database query spans) show up here even when that
+/// crate emits no events. Capped at the manager's event capacity.
+pub fn get_all_spans() -> Vec<SpanInfo> {
+    GLOBAL_EVENT_MANAGER
+        .get()
+        .and_then(|v| v.read().ok().map(|v| v.closed_spans.iter().cloned().collect()))
+        .unwrap_or_default()
+}
+
+/// Span duration percentiles from the global manager, see [`EventManager::span_duration_percentiles`]
+pub fn get_span_duration_percentiles(span_name: &str, percentiles: &[f64]) -> Vec<(f64, Duration)> {
+    GLOBAL_EVENT_MANAGER
+        .get()
+        .and_then(|v| v.read().ok().map(|v| v.span_duration_percentiles(span_name, percentiles)))
+        .unwrap_or_default()
+}
+
+/// Status of the capture system, see [`health`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthStatus {
+    /// The global event manager has been initialized
+    pub initialized: bool,
+    /// A thread panicked while holding the global manager's lock
+    pub poisoned: bool,
+    /// Events not sent to streams because the backlog cap was reached, see [`backlog_dropped_count`](crate::events::backlog_dropped_count)
+    pub dropped_events: u64,
+    /// Buffered events as a fraction of the event capacity, from `0.0` to `1.0`
+    pub buffer_utilization: f64,
+    /// Events waiting in streams, see [`current_backlog`](crate::events::current_backlog)
+    pub backlog: usize,
+    /// Age of the newest buffered event, see [`EventManager::newest_event_age`]
+    #[serde(default)]
+    pub newest_event_age: Option<Duration>,
+    /// Initialized, not poisoned and with the backlog below its cap
+    pub healthy: bool,
+}
+
+/// Summarize the capture system's status in one call, e.g. for a readiness probe.
+///
+/// A full buffer is normal for a ring buffer, so `buffer_utilization`, `dropped_events` and
+/// `newest_event_age` are reported for monitoring but don't affect `healthy`.
+pub fn health() -> HealthStatus { health_of(GLOBAL_EVENT_MANAGER.get().map(Arc::as_ref)) }
+
+fn health_of(manager: Option<&RwLock<EventManager>>) -> HealthStatus {
+    let poisoned = manager.is_some_and(RwLock::is_poisoned);
+    let (buffer_utilization, newest_event_age) = manager.map_or((0.0, None), |manager| {
+        let manager = manager.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        (manager.len() as f64 / manager.max_events.max(1) as f64, manager.newest_event_age())
+    });
+    let backlog = crate::events::current_backlog();
+
+    HealthStatus {
+        initialized: manager.is_some(),
+        poisoned,
+        dropped_events: crate::events::backlog_dropped_count(),
+        buffer_utilization,
+        backlog,
+        newest_event_age,
+        healthy: manager.is_some() && !poisoned && crate::events::backlog_cap().is_none_or(|cap| backlog < cap),
+    }
+}
+
+/// Enable or disable correlation retention on the global manager, see
+/// [`EventManager::set_correlation_retention`]. Returns `false` if it isn't initialized.
+pub fn set_global_correlation_retention(retention: Option<(usize, Duration)>) -> bool {
+    GLOBAL_EVENT_MANAGER.get().and_then(|v| v.write().ok().map(|mut v| v.set_correlation_retention(retention))).is_some()
+}
+
+/// Set the global manager's overflow hook, see [`EventManager::on_overflow`]. Returns `false`
+/// if it isn't initialized.
+pub fn on_global_overflow(hook: impl Fn(&Event) + Send + Sync + 'static) -> bool {
+    GLOBAL_EVENT_MANAGER.get().and_then(|v| v.write().ok().map(|mut v| v.on_overflow(hook))).is_some()
+}
+
+/// Number of events the global manager has evicted because its buffer was full, see
+/// [`EventManager::dropped_count`]
+pub fn global_dropped_count() -> u64 {
+    GLOBAL_EVENT_MANAGER.get().and_then(|v| v.read().ok().map(|v| v.dropped_count())).unwrap_or(0)
+}
+
+/// Get a copy of a correlation's retained events from the global manager, oldest first
+pub fn get_global_correlation_trace(correlation_id: &str) -> Option<Vec<Event>> {
+    let manager = GLOBAL_EVENT_MANAGER.get()?.read().ok()?;
+    Some(manager.get_correlation_trace(correlation_id)?.into_iter().cloned().collect())
+}
+
+/// Get a copy of all events from the global manager
+pub fn get_global_events() -> Option<Vec<Event>> {
+    Some(GLOBAL_EVENT_MANAGER.get()?.read().ok()?.inner.iter().cloned().collect())
+}
+
+/// Get the number of events in the global manager
+pub fn get_global_event_count() -> usize {
+    GLOBAL_EVENT_MANAGER.get().and_then(|v| v.read().map(|v| v.inner.len()).ok()).unwrap_or(0)
+}
+
+/// Store an event in the global manager and notify its listeners.
+///
+/// Listeners run after the manager's lock is released, so they may query the global manager
+/// and spans closing inside them can be recorded. See [`set_staged_emit`] to avoid taking the
+/// lock here at all.
+///
+/// With the `metrics` feature, each stored event also increments the `spanner_events` counter,
+/// labelled with its `level` and `target`.
+pub fn emit(event: Event) -> Option<()> {
+    let global = GLOBAL_EVENT_MANAGER.get()?;
+    count_event(&event);
+    if STAGED_EMIT.load(Ordering::Relaxed) {
+        return staging().send(Staged::Event(Box::new(event))).ok();
+    }
+    push_and_notify(global, event)
+}
+
+/// Like [`emit`], but into `manager` instead of the global one, and never staged
+pub(crate) fn emit_into(manager: &RwLock<EventManager>, event: Event) -> Option<()> {
+    count_event(&event);
+    push_and_notify(manager, event)
+}
+
+fn push_and_notify(manager: &RwLock<EventManager>, event: Event) -> Option<()> {
+    let (event, target, overflow) = {
+        let mut manager = manager.write().ok()?;
+        let event = manager.push_deferring_overflow(event);
+        (event, manager.target.clone(), manager.take_overflow())
+    };
+    run_overflow(overflow);
+    target.emit(event);
+    Some(())
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn count_event(event: &Event) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(
+        "spanner_events",
+        "level" => event.event_data.level().as_str(),
+        "target" => event.event_data.target.to_string()
+    )
+    .increment(1);
+}
+
+/// Handle to a consumer of captured events running alongside the application, such as
+/// [`attach_line_writer`]. Dropping it detaches the consumer without flushing.
+pub struct SpannerHandle {
+    subscription: Arc<Subscription<Event>>,
+    finish: Box<dyn FnOnce() -> io::Result<()> + Send>,
+}
+
+impl fmt::Debug for SpannerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpannerHandle").field("subscription", &self.subscription).finish()
+    }
+}
+
+impl SpannerHandle {
+    /// Whether the consumer still receives events
+    pub fn is_active(&self) -> bool { self.subscription.is_active() }
+
+    /// Stop receiving events, wait for events already being handled, then flush and release
+    /// the consumer's output, for deterministic teardown.
+    ///
+    /// Consumers run inline as events are emitted, so there is no task to join; once this
+    /// returns nothing more is written.
+    pub fn shutdown(self) -> io::Result<()> {
+        self.subscription.off();
+        (self.finish)()
+    }
+}
+
+/// Write each event captured from now on to `writer` as one line, e.g. for tailing from
+/// another process through stdout, a pipe or a socket.
+///
+/// Initializes the global event manager if needed. Each line is flushed as it is written.
+/// Writing stops when the returned handle is dropped or shut down, or after the first write error,
+/// which unsubscribes and is reported as a `WARN` event.
+pub fn attach_line_writer<W: Write + Send + 'static>(writer: W, format: LineFormat) -> SpannerHandle {
+    init_global_event_manager();
+    let target = events().unwrap_or_default();
+
+    let writer = Arc::new(Mutex::new(Some(writer)));
+    let output = writer.clone();
+    let subscription: Arc<OnceLock<Weak<Subscription<Event>>>> = Arc::default();
+    let this = subscription.clone();
+    let handle = target.on(move |event| {
+        let failed = {
+            let Ok(mut writer) = writer.lock() else { return };
+            let Some(out) = writer.as_mut() else { return };
+            match writeln!(out, "{}", event.to_line(format)).and_then(|_| out.flush()) {
+                Ok(()) => return,
+                Err(error) => {
+                    *writer = None;
+                    error
+                }
+            }
+        };
+
+        if let Some(subscription) = this.get().and_then(Weak::upgrade) {
+            subscription.off();
+        }
+        tracing::warn!(error = %failed, "line writer failed, detaching it");
+    });
+
+    let _ = subscription.set(Arc::downgrade(&handle));
+    SpannerHandle {
+        subscription: handle,
+        finish: Box::new(move || {
+            let Some(mut writer) = output.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() else {
+                return Ok(());
+            };
+            writer.flush()
+        }),
+    }
+}
+
+/// Get access to the global event target for emitting events
+pub fn events() -> Option<EventTarget<Event>> { Some(GLOBAL_EVENT_MANAGER.get()?.read().ok()?.target.clone()) }
+
+/// Clear all events from the global manager
+pub fn clear_global_events() {
+    if let Some(mut global) = GLOBAL_EVENT_MANAGER.get().and_then(|v| v.write().ok()) {
+        global.clear();
+    }
+}
+
+/// Version information of the application producing the events
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub version: Option<String>,
+    pub git_hash: Option<String>,
+}
+
+/// Register the application's build information, recorded in every export.
+///
+/// Only the first call takes effect; returns whether this call set it. Use
+/// [`spanner_build_info!`](crate::spanner_build_info) to capture it at compile time.
+pub fn set_build_info(info: BuildInfo) -> bool { BUILD_INFO.set(info).is_ok() }
+
+/// Get the registered build information, if any
+pub fn build_info() -> Option<&'static BuildInfo> { BUILD_INFO.get() }
+
+/// Set the description recorded in exports that aren't given one explicitly, such as
+/// `"prod-us-east / incident-123"`. `None` removes it.
+pub fn set_export_description(description: Option<String>) {
+    *EXPORT_DESCRIPTION.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = description;
+}
+
+/// Get the description set with [`set_export_description`]
+pub fn export_description() -> Option<String> {
+    EXPORT_DESCRIPTION.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+/// Export format for binary files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportMetadata {
+    pub version: String,
+    pub timestamp: DateTime<Utc>,
+    pub total_events: usize,
+    pub level_counts: BTreeMap<String, usize>,
+    pub description: Option<String>,
+    /// Application version from [`set_build_info`]
+    #[serde(default)]
+    pub build_version: Option<String>,
+    /// Application git commit from [`set_build_info`]
+    #[serde(default)]
+    pub git_hash: Option<String>,
+    /// Session labels from [`set_session_labels`](crate::set_session_labels), shared by every
+    /// event in the dump that doesn't carry its own
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Events the buffer had already evicted when the dump was taken, see
+    /// [`EventManager::dropped_count`]. Non-zero means the dump is only the tail of the stream.
+    /// Dumps from [`export_and_clear_to_bin_file`] count only the drops since the previous drain.
+    #[serde(default)]
+    pub dropped_events: u64,
+}
+
+/// Container for exported data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportData {
+    pub metadata: ExportMetadata,
+    pub events: Vec<Event>,
+    /// Distinct ancestors of `events` set with [`Event::with_parent`], each stored once
+    #[serde(default)]
+    pub parents: Vec<Event>,
+    /// `(child, parent)` index pairs into `events` followed by `parents`, from which imports
+    /// rebuild each event's [`Event::parent`] chain
+    #[serde(default)]
+    pub parent_links: Vec<(usize, usize)>,
+}
+
+/// Magic bytes at the start of every export file
+const EXPORT_MAGIC: [u8; 4] = *b"SPNR";
+/// Current export header version, whose body is MessagePack with structs encoded as arrays
+const EXPORT_HEADER_VERSION: u16 = 2;
+/// Header version of exports whose body is JSON
+const JSON_EXPORT_HEADER_VERSION: u16 = 1;
+/// Size of the fixed export header in bytes
+pub const EXPORT_HEADER_LEN: usize = 32;
+
+/// Compression of an export's body. The header before it is never compressed, so it stays
+/// readable with [`read_export_header`], and imports detect the compression from it.
+///
+/// Compressing or decompressing fails with [`io::ErrorKind::Unsupported`] when the crate
+/// feature a variant needs isn't enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Needs the `gzip` feature
+    Gzip,
+    /// Needs the `zstd` feature
+    Zstd,
+}
+
+impl Compression {
+    fn code(self) -> u16 {
+        match self {
+            Compression::None => 0,
+            Compression::Gzip => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    fn from_code(code: u16) -> io::Result<Self> {
+        match code {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Gzip),
+            2 => Ok(Compression::Zstd),
+            code => Err(invalid_data(format!("unknown export compression {}", code))),
+        }
+    }
+
+    #[cfg(not(all(feature = "gzip", feature = "zstd")))]
+    fn unsupported(feature: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::Unsupported, format!("{} compression needs the `{}` feature", feature, feature))
+    }
+
+    fn compress(self, body: Vec<u8>) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(body),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&body)?;
+                encoder.finish()
+            }
+            #[cfg(not(feature = "gzip"))]
+            Compression::Gzip => Err(Self::unsupported("gzip")),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::encode_all(body.as_slice(), 0),
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => Err(Self::unsupported("zstd")),
+        }
+    }
+
+    /// Wrap `reader` to decompress a body as it is read
+    fn decompress<'a>(self, reader: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::None => Box::new(reader),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Box::new(flate2::read::GzDecoder::new(reader)),
+            #[cfg(not(feature = "gzip"))]
+            Compression::Gzip => return Err(Self::unsupported("gzip")),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => return Err(Self::unsupported("zstd")),
+        })
+    }
+}
+
+/// Fixed-size summary at the start of an export file, readable without parsing the events.
+///
+/// Layout (little endian): magic `SPNR`, `u16` header version, `u16` body compression (`0`
+/// none, `1` gzip, `2` zstd), `u64` event count, then the oldest and newest event timestamps
+/// as `i64` nanoseconds since the epoch.
+///
+/// The version also gives the body's encoding: `2` is MessagePack, `1` is JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportHeader {
+    pub version: u16,
+    pub compression: Compression,
+    pub event_count: u64,
+    /// Oldest event timestamp, `None` for an empty export
+    pub min_timestamp: Option<DateTime<Utc>>,
+    /// Newest event timestamp, `None` for an empty export
+    pub max_timestamp: Option<DateTime<Utc>>,
+}
+
+impl ExportHeader {
+    fn for_events(events: &[Event]) -> Self {
+        let timestamps = events.iter().map(|event| event.event_data.timestamp);
+        Self {
+            version: EXPORT_HEADER_VERSION,
+            compression: Compression::None,
+            event_count: events.len() as u64,
+            min_timestamp: timestamps.clone().min(),
+            max_timestamp: timestamps.max(),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; EXPORT_HEADER_LEN] {
+        let nanos = |time: Option<DateTime<Utc>>| time.and_then(|t| t.timestamp_nanos_opt()).unwrap_or(0);
+
+        let mut bytes = [0; EXPORT_HEADER_LEN];
+        bytes[0..4].copy_from_slice(&EXPORT_MAGIC);
+        bytes[4..6].copy_from_slice(&self.version.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.compression.code().to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.event_count.to_le_bytes());
+        bytes[16..24].copy_from_slice(&nanos(self.min_timestamp).to_le_bytes());
+        bytes[24..32].copy_from_slice(&nanos(self.max_timestamp).to_le_bytes());
+        bytes
+    }
+
+    /// Parse a header, returning `None` if the bytes don't start with the export magic
+    fn from_bytes(bytes: &[u8]) -> Option<io::Result<Self>> {
+        if bytes.len() < EXPORT_HEADER_LEN || bytes[0..4] != EXPORT_MAGIC {
+            return None;
+        }
+
+        let field = |range: std::ops::Range<usize>| -> [u8; 8] { bytes[range].try_into().unwrap() };
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if !(JSON_EXPORT_HEADER_VERSION..=EXPORT_HEADER_VERSION).contains(&version) {
+            return Some(Err(invalid_data(format!("unsupported export header version {}", version))));
+        }
+
+        let compression = match Compression::from_code(u16::from_le_bytes([bytes[6], bytes[7]])) {
+            Ok(compression) => compression,
+            Err(error) => return Some(Err(error)),
+        };
+        let event_count = u64::from_le_bytes(field(8..16));
+        let time = |nanos: i64| (event_count > 0).then(|| DateTime::from_timestamp_nanos(nanos));
+        Some(Ok(Self {
+            version,
+            compression,
+            event_count,
+            min_timestamp: time(i64::from_le_bytes(field(16..24))),
+            max_timestamp: time(i64::from_le_bytes(field(24..32))),
+        }))
+    }
+}
+
+/// Read only the fixed header of an export file.
+///
+/// Fails with [`io::ErrorKind::InvalidData`] for files without a header, such as exports
+/// written by versions that predate it.
+pub fn read_export_header<P: AsRef<Path>>(path: P) -> io::Result<ExportHeader> {
+    let mut bytes = [0; EXPORT_HEADER_LEN];
+    File::open(path)?.read_exact(&mut bytes)?;
+    ExportHeader::from_bytes(&bytes).unwrap_or_else(|| Err(invalid_data("missing export header")))
+}
+
+/// Read only the metadata of an export file, without loading its events.
+///
+/// Parsing stops once the metadata has been read, so this stays fast for large dumps, e.g. to
+/// list the time, event count and level breakdown of every file in a directory. Compressed
+/// bodies are only decompressed up to the end of the metadata. JSON and legacy headerless
+/// exports are supported too.
+pub fn read_metadata_only<P: AsRef<Path>>(path: P) -> io::Result<ExportMetadata> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = ExportHeader::from_bytes(reader.fill_buf()?).transpose()?;
+    if header.is_some() {
+        reader.consume(EXPORT_HEADER_LEN);
+    }
+
+    let mut metadata = None;
+    let reader = header.map_or(Compression::None, |header| header.compression).decompress(reader)?;
+    let result = match header {
+        Some(header) if header.version != JSON_EXPORT_HEADER_VERSION => {
+            rmp_serde::Deserializer::new(reader).deserialize_seq(MetadataOnly(&mut metadata)).map_err(invalid_data)
+        }
+        _ => {
+            serde_json::Deserializer::from_reader(reader).deserialize_map(MetadataOnly(&mut metadata)).map_err(invalid_data)
+        }
+    };
+    // Stopping before the events leaves the object unfinished, so the parser's error is only
+    // meaningful if no metadata was found
+    metadata.ok_or_else(|| match result {
+        Err(error) => error,
+        Ok(()) => invalid_data("export has no metadata"),
+    })
+}
+
+/// Visits an export object until its `metadata` entry, leaving the rest unparsed. Binary
+/// exports encode the object as an array, whose first element is the metadata.
+struct MetadataOnly<'a>(&'a mut Option<ExportMetadata>);
+
+impl<'de> Visitor<'de> for MetadataOnly<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str("an export object") }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "metadata" {
+                *self.0 = Some(map.next_value()?);
+                return Ok(());
+            }
+            map.next_value::<IgnoredAny>()?;
+        }
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        *self.0 = seq.next_element()?;
+        Ok(())
+    }
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Serialize export data as MessagePack, compressing it after the header
+fn encode_export(export_data: &ExportData, compression: Compression) -> io::Result<Vec<u8>> {
+    let header = ExportHeader { compression, ..ExportHeader::for_events(&export_data.events) };
+    let mut encoded = header.to_bytes().to_vec();
+    // Encoding into a `Vec` only fails for unserializable data, which JSON would reject too
+    match compression {
+        Compression::None => rmp_serde::encode::write(&mut encoded, export_data).map_err(invalid_data)?,
+        _ => encoded.extend_from_slice(&compression.compress(rmp_serde::to_vec(export_data).map_err(invalid_data)?)?),
+    }
+    Ok(encoded)
+}
+
+/// Deserialize export data, checking the header against the events when present.
+/// JSON exports, with a version 1 header or from before headers existed, are still accepted.
+fn decode_export(data: &[u8]) -> io::Result<ExportData> {
+    let (header, body) = match ExportHeader::from_bytes(data) {
+        Some(header) => (Some(header?), &data[EXPORT_HEADER_LEN..]),
+        None => (None, data),
+    };
+
+    let mut export_data: ExportData = match header {
+        Some(header) if header.version != JSON_EXPORT_HEADER_VERSION => {
+            rmp_serde::from_read(header.compression.decompress(body)?).map_err(invalid_data)?
+        }
+        _ => serde_json::from_slice(body).map_err(invalid_data)?,
+    };
+    if let Some(header) = header {
+        let expected = ExportHeader {
+            version: header.version,
+            compression: header.compression,
+            ..ExportHeader::for_events(&export_data.events)
+        };
+        if header != expected {
+            return Err(invalid_data(format!("export header {:?} does not match its events {:?}", header, expected)));
+        }
+    }
+
+    // Carry the dump's labels on its events, so they stay distinguishable once merged
+    let labels = &export_data.metadata.labels;
+    if !labels.is_empty() {
+        export_data
+            .events
+            .iter_mut()
+            .chain(export_data.parents.iter_mut())
+            .filter(|event| event.labels.is_empty())
+            .for_each(|event| event.labels = labels.clone());
+    }
+
+    link_parents(&mut export_data)?;
+    Ok(export_data)
+}
+
+fn write_export<P: AsRef<Path>>(path: P, export_data: &ExportData, compression: Compression) -> io::Result<usize> {
+    let encoded = encode_export(export_data, compression)?;
+
+    let mut file = File::create(path)?;
+    file.write_all(&encoded)?;
+    file.flush()?;
+
+    Ok(export_data.events.len())
+}
+
+/// Export all events to a binary file
+pub fn export_to_bin_file<P: AsRef<Path>>(path: P) -> io::Result<usize> {
+    let events = get_global_events().unwrap_or_default();
+    write_export(path, &create_export_data(events, None), Compression::None)
+}
+
+/// Export all events to a binary file, compressing everything but its header, e.g. to ship
+/// a dump with a support ticket. [`import_from_bin_file`] detects the compression.
+pub fn export_to_bin_file_compressed<P: AsRef<Path>>(path: P, compression: Compression) -> io::Result<usize> {
+    let events = get_global_events().unwrap_or_default();
+    write_export(path, &create_export_data(events, None), compression)
+}
+
+/// Export all events to a binary file and remove them from the global buffer, atomically.
+///
+/// The global write lock is held from reading the events until they are cleared, so in a
+/// periodic drain loop every event lands in exactly one file. Events are only cleared once the
+/// file is written; on error the buffer is left as it was. Closed spans are kept.
+pub fn export_and_clear_to_bin_file<P: AsRef<Path>>(path: P) -> io::Result<usize> {
+    let Some(mut global) = GLOBAL_EVENT_MANAGER.get().and_then(|v| v.write().ok()) else {
+        return write_export(path, &create_export_data(Vec::new(), None), Compression::None);
+    };
+
+    let events = global.inner.iter().cloned().collect();
+    // Reading the count through `global_dropped_count` would deadlock on the held write lock
+    let dropped = global.dropped_count - global.dropped_at_drain;
+    let count = write_export(path, &export_data_with_dropped(events, None, dropped), Compression::None)?;
+    global.inner.clear();
+    global.size_bytes = 0;
+    global.dropped_at_drain = global.dropped_count;
+    Ok(count)
+}
+
+/// Export events with filtering to a binary file
+pub fn export_filtered_to_bin_file<P: AsRef<Path>>(
+    path: P,
+    level_filter: Option<Level>,
+    target_filter: Option<&str>,
+    message_contains: Option<&str>,
+    span_name_contains: Option<&str>,
+    description: Option<String>,
+) -> io::Result<usize> {
+    let all_events = get_global_events().unwrap_or_default();
+    let filtered_events: Vec<Event> = all_events
+        .into_iter()
+        .filter(|event| event.matches_criteria(level_filter, target_filter, message_contains, span_name_contains))
+        .collect();
+
+    write_export(path, &create_export_data(filtered_events, description), Compression::None)
+}
+
+/// Export the events matching `query` to a binary file, e.g. only those from a time window
+/// with [`SearchQuery::after`] and [`SearchQuery::before`], or the last five minutes with
+/// [`SearchQuery::within_last`]. Any criterion [`export_filtered_to_bin_file`] takes can be
+/// set on the query as well.
+pub fn export_query_to_bin_file<P: AsRef<Path>>(
+    path: P,
+    query: &SearchQuery,
+    description: Option<String>,
+) -> io::Result<usize> {
+    let events = get_global_events().unwrap_or_default().into_iter().filter(|event| event.matches(query)).collect();
+    write_export(path, &create_export_data(events, description), Compression::None)
+}
+
+/// Export all events to `path` as newline-delimited JSON with keys renamed by `names`, e.g.
+/// [`FieldNames::ecs`] to load the dump straight into an existing Elasticsearch index.
+///
+/// One event per line, newest first, without a header or metadata, so the file is meant for
+/// other tools and can't be imported back. Returns the number of events written.
+pub fn export_renamed<P: AsRef<Path>>(path: P, names: &FieldNames) -> io::Result<usize> {
+    let mut events = get_global_events().unwrap_or_default();
+    let mut out = io::BufWriter::new(File::create(path)?);
+    for event in &mut events {
+        truncate_spans(event);
+        serde_json::to_writer(&mut out, &names.to_json(event).map_err(invalid_data)?).map_err(invalid_data)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    Ok(events.len())
+}
+
+/// Export all events to a binary file, keeping only the listed field and metadata keys.
+///
+/// `None` keeps every key and an empty list drops them all. The live buffer is unchanged.
+pub fn export_projected<P: AsRef<Path>>(
+    path: P,
+    include_fields: Option<Vec<String>>,
+    include_metadata: Option<Vec<String>>,
+) -> io::Result<usize> {
+    let mut events = get_global_events().unwrap_or_default();
+    for event in &mut events {
+        if let Some(keys) = &include_fields {
+            event.event_data.fields.retain(|key, _| keys.contains(key));
+        }
+        if let Some(keys) = &include_metadata {
+            event.custom_metadata.retain(|key, _| keys.contains(key));
+        }
+    }
+
+    write_export(path, &create_export_data(events, None), Compression::None)
+}
+
+/// Get binary data for export without writing to file
+pub fn export_to_bin_data() -> Result<Vec<u8>, serde_json::Error> {
+    let events = get_global_events().unwrap_or_default();
+    encode_export(&create_export_data(events, None), Compression::None).map_err(serde::ser::Error::custom)
+}
+
+/// Stream an export of the global events as newline-delimited JSON, e.g. as a chunked HTTP
+/// response body.
+///
+/// The first chunk is the [`ExportMetadata`] line, followed by one line per event, newest
+/// first like the other exports. The matching events are snapshotted once, when the stream
+/// is created, by sharing them with the buffer rather than copying; each line is only
+/// serialized as the stream is polled, so memory stays flat however large the export is.
+pub fn export_byte_stream(query: Option<SearchQuery>) -> impl Stream<Item = Result<Vec<u8>, SpannerError>> + Send {
+    let (events, dropped_events) = GLOBAL_EVENT_MANAGER
+        .get()
+        .and_then(|v| {
+            let manager = v.read().ok()?;
+            let events = manager.inner.filter_shared(&|event| query.as_ref().is_none_or(|query| event.matches(query)));
+            Some((events, manager.dropped_count()))
+        })
+        .unwrap_or_default();
+
+    let metadata = export_metadata(events.iter().map(Arc::as_ref), None, dropped_events);
+    let lines = stream::iter(events).map(|event| {
+        let mut event = Arc::unwrap_or_clone(event);
+        truncate_spans(&mut event);
+        json_line(&event)
+    });
+    stream::once(future::ready(json_line(&metadata))).chain(lines)
+}
+
+/// First line of an NDJSON export, marking the metadata apart from the events while keeping
+/// every line valid JSON
+#[derive(Serialize, Deserialize)]
+struct NdjsonHeader {
+    spanner_metadata: ExportMetadata,
+}
+
+/// Export all events to `path` as newline-delimited JSON, one [`Event`] per line and newest
+/// first like the other exports, for log pipelines that ingest NDJSON.
+///
+/// The first line holds the [`ExportMetadata`] under a `spanner_metadata` key. Parent chains
+/// aren't kept, see [`export_to_bin_file`] for a lossless export.
+pub fn export_to_ndjson_file<P: AsRef<Path>>(path: P) -> io::Result<usize> {
+    let mut events = get_global_events().unwrap_or_default();
+    events.iter_mut().for_each(truncate_spans);
+    let header = NdjsonHeader { spanner_metadata: export_metadata(&events, None, global_dropped_count()) };
+
+    let mut out = io::BufWriter::new(File::create(path)?);
+    out.write_all(&json_line(&header).map_err(invalid_data)?)?;
+    for event in &events {
+        out.write_all(&json_line(event).map_err(invalid_data)?)?;
+    }
+    out.flush()?;
+
+    Ok(events.len())
+}
+
+/// Import events from a newline-delimited JSON file, one [`Event`] per line, as written by
+/// [`export_to_ndjson_file`] or [`export_byte_stream`].
+///
+/// A leading metadata line is optional; its session labels are applied like on binary
+/// imports. An unterminated last line, as left by a truncated or still-growing file, is
+/// skipped if it doesn't parse, so every complete line is still imported. Any other line
+/// that isn't an event fails with [`io::ErrorKind::InvalidData`].
+pub fn import_from_ndjson_file<P: AsRef<Path>>(path: P) -> io::Result<EventManager> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut manager = EventManager::new(None);
+    let mut labels = BTreeMap::new();
+    let mut line = Vec::new();
+
+    for number in 1.. {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        let complete = line.ends_with(b"\n");
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+
+        if number == 1 {
+            let metadata = serde_json::from_slice::<NdjsonHeader>(&line)
+                .map(|header| header.spanner_metadata)
+                .or_else(|_| serde_json::from_slice::<ExportMetadata>(&line));
+            if let Ok(metadata) = metadata {
+                labels = metadata.labels;
+                continue;
+            }
+        }
+
+        match serde_json::from_slice::<Event>(&line) {
+            Ok(mut event) => {
+                if event.labels.is_empty() {
+                    event.labels = labels.clone();
+                }
+                manager.push(event);
+            }
+            Err(_) if !complete => break,
+            Err(error) => return Err(invalid_data(format!("line {}: {}", number, error))),
+        }
+    }
+
+    Ok(manager)
+}
+
+fn json_line<T: Serialize>(value: &T) -> Result<Vec<u8>, SpannerError> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Import events from a binary file and return a new EventManager
+pub fn import_from_bin_file<P: AsRef<Path>>(path: P) -> io::Result<EventManager> {
+    let export_data = decode_export(&std::fs::read(path)?)?;
+
+    // Create a new EventManager with the imported events
+    let mut manager = EventManager::new(None);
+    for event in export_data.events {
+        manager.push(event);
+    }
+
+    Ok(manager)
+}
+
+/// Import events from a binary file and add to global manager.
+///
+/// Imported events are interleaved with live ones by timestamp, and once over capacity the
+/// oldest events overall are evicted, whichever side they came from.
+///
+/// Events the global manager already holds are skipped, so overlapping dumps, or the same dump
+/// imported twice, don't double the buffer. Two events are the same if their ids, sequence numbers
+/// and [`content_key`](Event::content_key)s (capture time, level, target and message) all match.
+pub fn import_and_merge_from_bin_file<P: AsRef<Path>>(path: P) -> io::Result<(ExportData, MergeStats)> {
+    import_and_merge_with_progress(path, |_, _| {})
+}
+
+/// The parts of an event's content that identify it across dumps, see [`dedup_key`]
+const DEDUP_IDENTITY: IdentityConfig = IdentityConfig {
+    include_fields: false,
+    include_location: false,
+    include_span_path: false,
+    include_timestamp: true,
+    include_correlation_id: false,
+};
+
+/// Identifies an event across dumps: ids alone are only unique within a process, so they are
+/// combined with the event's sequence number and its [`content_key`](Event::content_key),
+/// which covers its capture time, level, target and message
+fn dedup_key(event: &Event) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (event.content_key(&DEDUP_IDENTITY), event.id, event.event_data.sequence).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Keys of the events currently in `manager`. Take them under the write lock that adds the
+/// imported events, so a concurrent import can't add the same events in between.
+fn dedup_keys(manager: &EventManager) -> HashSet<u64> { manager.iter().map(dedup_key).collect() }
+
+/// Import events from a binary file into the global manager, using `policy` to decide which
+/// events to keep if the combined set exceeds its capacity.
+///
+/// [`import_and_merge_from_bin_file`] behaves like [`ImportMergePolicy::InterleaveByTime`].
+/// Duplicates are skipped the same way.
+pub fn import_and_merge_with_policy<P: AsRef<Path>>(
+    path: P,
+    policy: ImportMergePolicy,
+) -> io::Result<(ExportData, MergeStats)> {
+    let export_data = decode_export(&std::fs::read(path)?)?;
+    let mut stats = MergeStats::default();
+
+    let overflow = GLOBAL_EVENT_MANAGER.get().and_then(|v| v.write().ok()).and_then(|mut manager| {
+        let mut seen = dedup_keys(&manager);
+        let mut imported = EventManager::new(Some(export_data.events.len().max(1)));
+        for event in &export_data.events {
+            if seen.insert(dedup_key(event)) {
+                imported.push(event.clone());
+                stats.added += 1;
+            } else {
+                stats.skipped_duplicates += 1;
+            }
+        }
+        manager.merge_deferring_overflow(imported, policy);
+        manager.take_overflow()
+    });
+    run_overflow(overflow);
+
+    Ok((export_data, stats))
+}
+
+/// Events pushed per global lock acquisition while importing with progress
+const IMPORT_CHUNK: usize = 1024;
+
+/// Import events from a binary file into the global manager, reporting progress.
+///
+/// `progress` is called with `(processed, total)`, first with `0` and then after each chunk of
+/// events is pushed, ending with `processed == total`. It runs outside the global lock, so it
+/// may log through `tracing` or update a progress bar freely. `processed` counts skipped
+/// duplicates too.
+pub fn import_and_merge_with_progress<P: AsRef<Path>>(
+    path: P,
+    mut progress: impl FnMut(usize, usize),
+) -> io::Result<(ExportData, MergeStats)> {
+    let export_data = decode_export(&std::fs::read(path)?)?;
+    let total = export_data.events.len();
+    progress(0, total);
+
+    let mut seen = None;
+    let mut stats = MergeStats::default();
+    let mut processed = 0;
+    for chunk in export_data.events.chunks(IMPORT_CHUNK) {
+        // Add imported events to the global manager
+        if let Some(mut manager) = GLOBAL_EVENT_MANAGER.get().and_then(|v| v.write().ok()) {
+            // Keys are taken under the first chunk's lock, then grow with the imported events
+            let seen = seen.get_or_insert_with(|| dedup_keys(&manager));
+            let (new, duplicates): (Vec<&Event>, Vec<&Event>) =
+                chunk.iter().partition(|event| seen.insert(dedup_key(event)));
+            stats.skipped_duplicates += duplicates.len();
+            stats.added += new.len();
+            for event in new {
+                manager.insert_deferring_overflow(event.clone());
+            }
+            let overflow = manager.take_overflow();
+            drop(manager);
+            run_overflow(overflow);
+        }
+        processed += chunk.len();
+        progress(processed, total);
+    }
+
+    Ok((export_data, stats))
+}
+
+/// Most spans exported per event across its span stack, and again for its current span.
+/// Larger trees are truncated with a marker so a span explosion can't blow up dump size.
+const MAX_EXPORTED_SPANS: usize = 256;
+
+/// Cap the span trees of an event about to be exported, see [`MAX_EXPORTED_SPANS`]
+fn truncate_spans(event: &mut Event) {
+    let mut budget = MAX_EXPORTED_SPANS;
+    event.span_stack.iter_mut().for_each(|span| span.truncate_tree(&mut budget));
+    if let Some(span) = &mut event.current_span {
+        budget = MAX_EXPORTED_SPANS;
+        span.truncate_tree(&mut budget);
+    }
+}
+
+/// Create export data structure with metadata
+fn create_export_data(events: Vec<Event>, description: Option<String>) -> ExportData {
+    export_data_with_dropped(events, description, global_dropped_count())
+}
+
+fn export_data_with_dropped(mut events: Vec<Event>, description: Option<String>, dropped_events: u64) -> ExportData {
+    events.iter_mut().for_each(truncate_spans);
+    let metadata = export_metadata(&events, description, dropped_events);
+    let (parents, parent_links) = flatten_parents(&events);
+    ExportData { metadata, events, parents, parent_links }
+}
+
+/// Collect the ancestors of `events`, storing parents shared by several events once, along
+/// with `(child, parent)` links indexing `events` followed by the ancestors
+fn flatten_parents(events: &[Event]) -> (Vec<Event>, Vec<(usize, usize)>) {
+    let mut parents = Vec::new();
+    let mut links = Vec::new();
+    let mut indices: HashMap<*const Event, usize> = HashMap::new();
+
+    for (index, event) in events.iter().enumerate() {
+        let (mut child, mut next) = (index, event.parent.as_ref());
+        while let Some(parent) = next {
+            if let Some(&known) = indices.get(&Arc::as_ptr(parent)) {
+                links.push((child, known));
+                break;
+            }
+
+            let parent_index = events.len() + parents.len();
+            indices.insert(Arc::as_ptr(parent), parent_index);
+            links.push((child, parent_index));
+
+            let mut flat = Event::clone(parent);
+            flat.parent = None;
+            truncate_spans(&mut flat);
+            parents.push(flat);
+
+            child = parent_index;
+            next = parent.parent.as_ref();
+        }
+    }
+
+    (parents, links)
+}
+
+/// Rebuild the [`Event::parent`] chains of decoded events from their export's parent links
+fn link_parents(export_data: &mut ExportData) -> io::Result<()> {
+    let parent_of: HashMap<usize, usize> = export_data.parent_links.iter().copied().collect();
+    let offset = export_data.events.len();
+    let parents = std::mem::take(&mut export_data.parents);
+    let mut built: HashMap<usize, Arc<Event>> = HashMap::new();
+
+    for (index, event) in export_data.events.iter_mut().enumerate() {
+        // Walk up to the first ancestor that's already built, then build back down
+        let mut chain = Vec::new();
+        let mut next = parent_of.get(&index).copied();
+        while let Some(parent) = next.filter(|parent| !built.contains_key(parent)) {
+            if !(offset..offset + parents.len()).contains(&parent) || chain.len() >= parents.len() {
+                return Err(invalid_data("export has an invalid parent link"));
+            }
+            chain.push(parent);
+            next = parent_of.get(&parent).copied();
+        }
+
+        for &parent in chain.iter().rev() {
+            let mut rebuilt = parents[parent - offset].clone();
+            rebuilt.parent = parent_of.get(&parent).map(|grandparent| built[grandparent].clone());
+            built.insert(parent, Arc::new(rebuilt));
+        }
+        event.parent = parent_of.get(&index).map(|parent| built[parent].clone());
+    }
+
+    export_data.parents = parents;
+    Ok(())
+}
+
+fn export_metadata<'a>(
+    events: impl IntoIterator<Item = &'a Event>,
+    description: Option<String>,
+    dropped_events: u64,
+) -> ExportMetadata {
+    let mut total_events = 0;
+    let mut level_counts = BTreeMap::new();
+
+    for event in events {
+        let level_str = format!("{}", event.event_data.level);
+        *level_counts.entry(level_str).or_insert(0) += 1;
+        total_events += 1;
+    }
+
+    let build = build_info().cloned().unwrap_or_default();
+    ExportMetadata {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        timestamp: Utc::now(),
+        total_events,
+        level_counts,
+        description: description.or_else(export_description),
+        build_version: build.version,
+        git_hash: build.git_hash,
+        labels: session_labels(),
+        dropped_events,
+    }
+}
+
+/// Get summary of events without exporting
+pub fn get_event_summary() -> String {
+    if let Some(global) = GLOBAL_EVENT_MANAGER.get().and_then(|v| v.read().ok()) {
+        return global.summary();
+    }
+    "No events captured".to_string()
+}
+
+/// Write an end-of-run report of the global events to `writer`, e.g. stderr at the end of a
+/// batch job or CLI tool, see [`EventManager::report`]
+pub fn print_shutdown_report(mut writer: impl Write) -> io::Result<()> {
+    let report = GLOBAL_EVENT_MANAGER.get().and_then(|v| v.read().ok().map(|v| v.report()));
+    writer.write_all(report.as_deref().unwrap_or("No events captured\n").as_bytes())?;
+    writer.flush()
+}
+
+/// Guard from [`report_on_shutdown`] that prints the shutdown report when dropped
+pub struct ShutdownReport<W: Write> {
+    writer: Option<W>,
+}
+
+impl<W: Write> ShutdownReport<W> {
+    /// Drop the guard without printing the report
+    pub fn cancel(mut self) { self.writer = None; }
+}
+
+impl<W: Write> Drop for ShutdownReport<W> {
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            let _ = print_shutdown_report(writer);
+        }
+    }
+}
+
+/// Print the shutdown report to `writer` once the returned guard is dropped, e.g. by holding
+/// it for the whole of `main`. Nothing is printed unless this is called; write errors are ignored.
+pub fn report_on_shutdown<W: Write>(writer: W) -> ShutdownReport<W> { ShutdownReport { writer: Some(writer) } }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{EventData, set_session_labels},
+        chrono::TimeDelta,
+        std::collections::HashSet,
+    };
+
+    pub(crate) fn event_at(message: &str, seconds_ago: i64) -> Event {
+        let mut data = EventData::new(message.to_string(), Level::INFO, "test".to_string());
+        data.timestamp = Utc::now() - TimeDelta::seconds(seconds_ago);
+        Event::new(data)
+    }
+
+    fn messages<S: EventStore>(manager: &EventManager<S>) -> Vec<&str> {
+        manager.iter().map(|event| event.event_data.message.as_str()).collect()
+    }
+
+    #[test]
+    fn test_correlation_retention_survives_eviction() {
+        let root = SpanInfo::new(7, "request".to_string(), "test".to_string(), Level::INFO);
+        let in_request = |message: &str, correlation_id: &str| {
+            event_at(message, 0).with_correlation_id(correlation_id.to_string()).with_span_stack(vec![root.clone()])
+        };
+        let mut manager = EventManager::new(Some(3)).with_correlation_retention(4, Duration::from_secs(60));
+
+        manager.push(in_request("request start", "req-1"));
+        (0..5).for_each(|i| manager.push(event_at(&format!("noise {i}"), 0)));
+        manager.push(in_request("request end", "req-1"));
+
+        assert!(!messages(&manager).contains(&"request start"), "the buffer alone loses the start");
+        let trace_messages = |manager: &EventManager| -> Vec<String> {
+            let trace = manager.get_correlation_trace("req-1").unwrap();
+            trace.iter().map(|event| event.event_data.message.clone()).collect()
+        };
+        assert_eq!(trace_messages(&manager), ["request start", "request end"]);
+
+        manager.push_closed_span(root.clone());
+        assert!(manager.correlations.as_ref().unwrap().in_flight.is_empty());
+        assert_eq!(trace_messages(&manager), ["request start", "request end"], "completed traces stay queryable");
+    }
+
+    #[test]
+    fn test_correlation_retention_caps_in_flight() {
+        let mut manager = EventManager::new(Some(10)).with_correlation_retention(2, Duration::from_secs(60));
+        for id in ["a", "b", "c"] {
+            manager.push(event_at(id, 0).with_correlation_id(id.to_string()));
+        }
+
+        let retention = manager.correlations.as_ref().unwrap();
+        assert_eq!(retention.in_flight.len(), 2);
+        assert_eq!(retention.completed.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(), ["a"]);
+
+        manager.push(event_at("late", 0).with_correlation_id("d".to_string()));
+        manager.push(event_at("later", -120).with_correlation_id("e".to_string()));
+        let retention = manager.correlations.as_ref().unwrap();
+        assert_eq!(retention.in_flight.keys().collect::<Vec<_>>(), ["e"], "idle correlations complete");
+
+        // Ids generated for lone events never open a trace, so they can't crowd out real ones
+        (0..5).for_each(|i| manager.push(event_at(&format!("lone {i}"), -120).with_generated_correlation_id()));
+        let retention = manager.correlations.as_ref().unwrap();
+        assert_eq!(retention.in_flight.keys().collect::<Vec<_>>(), ["e"]);
+        assert_eq!(retention.by_last_seen.len(), 1);
+    }
+
+    #[test]
+    fn test_merges_and_imports_leave_correlation_traces_alone() {
+        let mut manager = EventManager::new(Some(10)).with_correlation_retention(4, Duration::from_secs(60));
+        manager.push(event_at("start", 0).with_correlation_id("req-1".to_string()));
+        manager.merge_from(EventManager::new(None));
+        assert_eq!(manager.get_correlation_trace("req-1").unwrap().len(), 1, "live events aren't recorded twice");
+
+        let mut imported = EventManager::new(None);
+        imported.push(event_at("imported", 1).with_correlation_id("req-2".to_string()));
+        manager.merge_from(imported);
+        assert_eq!(manager.correlations.as_ref().unwrap().in_flight.keys().collect::<Vec<_>>(), ["req-1"]);
+
+        // Dumps lose the generated-id flag, so imports into the global manager don't open traces either
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        clear_global_events();
+        set_global_correlation_retention(Some((4, Duration::from_secs(60))));
+        let path = std::env::temp_dir().join(format!("spanner_lone_{}.bin", uuid::Uuid::new_v4()));
+        let lone = (0..3).map(|i| event_at("lone", i).with_generated_correlation_id()).collect();
+        write_export(&path, &create_export_data(lone, None), Compression::None).unwrap();
+        import_and_merge_with_progress(&path, |_, _| {}).unwrap();
+        let in_flight = GLOBAL_EVENT_MANAGER.get().unwrap().read().unwrap().correlations.as_ref().unwrap().in_flight.len();
+        set_global_correlation_retention(None);
+        assert_eq!(in_flight, 0);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_overflow_hook() {
+        let dropped = Arc::new(Mutex::new(Vec::new()));
+        let mut manager = EventManager::new(Some(3));
+        let sink = dropped.clone();
+        manager.on_overflow(move |event| sink.lock().unwrap().push(event.event_data.message.clone()));
+
+        for (i, message) in ["a", "b", "c", "d", "e"].into_iter().enumerate() {
+            manager.push(event_at(message, 10 - i as i64));
+        }
+        assert_eq!(*dropped.lock().unwrap(), ["a", "b"]);
+        assert_eq!(manager.dropped_count(), 2);
+
+        // Evictions from a tightened memory budget count too, and clearing isn't an overflow
+        manager.set_max_bytes(Some(1));
+        assert_eq!(*dropped.lock().unwrap(), ["a", "b", "c", "d"]);
+        manager.clear();
+        assert_eq!(manager.dropped_count(), 4);
+
+        // Through a shared manager, the hook runs once the write lock is released
+        let shared = Arc::new(RwLock::new(EventManager::new(Some(1))));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (reader, sink) = (shared.clone(), seen.clone());
+        shared.write().unwrap().on_overflow(move |event| {
+            let remaining = reader.read().unwrap().len();
+            sink.lock().unwrap().push((event.event_data.message.clone(), remaining));
+        });
+        emit_into(&shared, event_at("first", 2));
+        emit_into(&shared, event_at("second", 1));
+        assert_eq!(*seen.lock().unwrap(), [("first".to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_dropped_events_reported() {
+        let mut manager = EventManager::new(Some(2));
+        for i in 0..2 {
+            manager.push(event_at("kept", 10 - i));
+        }
+        assert!(!manager.summary().contains("Dropped"));
+        for i in 0..5 {
+            manager.push(event_at("overflowing", 5 - i));
+        }
+        assert!(manager.summary().contains("  Dropped (buffer full): 5\n"), "{}", manager.summary());
+
+        let events = manager.iter().cloned().collect();
+        let export = export_data_with_dropped(events, None, manager.dropped_count());
+        let decoded = decode_export(&encode_export(&export, Compression::None).unwrap()).unwrap();
+        assert_eq!((decoded.metadata.total_events, decoded.metadata.dropped_events), (2, 5));
+        assert_eq!(EventManager::restore_state(manager.snapshot_state()).dropped_count(), 5);
+
+        // Events that don't fit when merging are dropped too
+        let mut other = EventManager::new(Some(3));
+        (0..3).for_each(|i| other.push(event_at("imported", 20 - i)));
+        manager.merge_from(other);
+        assert_eq!(manager.dropped_count(), 8);
+    }
+
+    #[test]
+    fn test_max_bytes_evicts_oldest() {
+        let small = event_at("small", 0);
+        let mut large = event_at("large", 0);
+        large.event_data.add_field("payload".to_string(), "x".repeat(10_000));
+
+        let budget = small.estimated_size() * 3 + 100;
+        let mut manager = EventManager::new(None).with_max_bytes(budget);
+        for _ in 0..3 {
+            manager.push(small.clone());
+        }
+        assert_eq!(manager.len(), 3);
+        assert!(manager.estimated_size() <= budget);
+
+        // A single oversized event pushes every older event out, but is itself kept
+        manager.push(large.clone());
+        assert_eq!(messages(&manager), ["large"]);
+        assert_eq!(manager.estimated_size(), large.estimated_size());
+
+        // Buffers are ordered by time, so the next event must be newer than the large one
+        manager.push(event_at("small", 0));
+        assert_eq!(messages(&manager), ["small"]);
+        assert_eq!(manager.estimated_size(), small.estimated_size());
+
+        manager.clear();
+        assert_eq!(manager.estimated_size(), 0);
+    }
+
+    #[test]
+    fn test_build_info_in_export_metadata() {
+        set_build_info(crate::spanner_build_info!());
+
+        let export = create_export_data(vec![event_at("a", 0)], None);
+        assert_eq!(export.metadata.build_version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+        assert_eq!(export.metadata.git_hash.as_deref(), option_env!("GIT_HASH"));
+
+        let json = serde_json::to_value(&export.metadata).unwrap();
+        assert!(json.get("build_version").is_some());
+        assert!(json.get("git_hash").is_some(), "git_hash is written even when GIT_HASH was not set");
+    }
+
+    #[test]
+    fn test_on_initialized_runs_queued_callbacks() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let cell = OnceLock::new();
+        let queue = Mutex::new(Vec::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let counter = calls.clone();
+        run_or_queue(&cell, &queue, Box::new(move || _ = counter.fetch_add(1, Ordering::SeqCst)));
+        assert_eq!(calls.load(Ordering::SeqCst), 0, "callback waits for initialization");
+
+        assert!(set_and_flush(&cell, &queue, ()));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(!set_and_flush(&cell, &queue, ()), "only the first initialization flushes");
+
+        let counter = calls.clone();
+        run_or_queue(&cell, &queue, Box::new(move || _ = counter.fetch_add(1, Ordering::SeqCst)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2, "callback runs immediately once initialized");
+
+        // The global hook follows the same path
+        init_global_event_manager();
+        let counter = calls.clone();
+        on_initialized(move || _ = counter.fetch_add(1, Ordering::SeqCst));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_merge_from_interleaves_by_timestamp() {
+        let mut live = EventManager::new(Some(4));
+        live.push(event_at("a", 50));
+        live.push(event_at("c", 30));
+        live.push(event_at("e", 10));
+
+        let mut imported = EventManager::new(None);
+        imported.push(event_at("b", 40));
+        imported.push(event_at("d", 20));
+
+        live.merge_from(imported);
+
+        // Newest first, and the oldest event ("a") was evicted to respect capacity
+        assert_eq!(messages(&live), ["e", "d", "c", "b"]);
+    }
+
+    #[test]
+    fn test_merge_policies_at_capacity() {
+        let merged = |policy| {
+            let mut live = EventManager::new(Some(4));
+            for (message, seconds_ago) in [("live 50", 50), ("live 30", 30), ("live 10", 10)] {
+                live.push(event_at(message, seconds_ago));
+            }
+            let mut imported = EventManager::new(None);
+            for (message, seconds_ago) in [("old 60", 60), ("imported 40", 40), ("imported 20", 20)] {
+                imported.push(event_at(message, seconds_ago));
+            }
+
+            live.merge_with_policy(imported, policy);
+            messages(&live).into_iter().map(String::from).collect::<Vec<_>>()
+        };
+
+        // Newest first, with six events competing for four slots
+        assert_eq!(merged(ImportMergePolicy::InterleaveByTime), ["live 10", "imported 20", "live 30", "imported 40"]);
+        assert_eq!(merged(ImportMergePolicy::PreferLive), ["live 10", "imported 20", "live 30", "live 50"]);
+        assert_eq!(merged(ImportMergePolicy::PreferImported), ["live 10", "imported 20", "imported 40", "old 60"]);
+    }
+
+    #[test]
+    fn test_push_shares_repeated_strings() {
+        let event = |message: &str| {
+            let span = SpanInfo::new(1, "handle_request".to_string(), "app::http".to_string(), Level::INFO);
+            event_at(message, 0).with_span_stack(vec![span.clone()]).with_current_span(span)
+        };
+
+        let mut manager = EventManager::new(None);
+        manager.push(event("first"));
+        manager.push(event("second"));
+
+        let [second, first] = [0, 1].map(|index| manager.iter().nth(index).unwrap());
+        assert!(first.event_data.target.ptr_eq(&second.event_data.target));
+        let (first_span, second_span) = (&first.span_stack[0], &second.span_stack[0]);
+        assert!(first_span.name.ptr_eq(&second_span.name) && first_span.target.ptr_eq(&second_span.target));
+        assert_eq!(second_span.name, "handle_request");
+    }
+
+    #[test]
+    fn test_emit_shares_the_stored_event() {
+        let manager = RwLock::new(EventManager::new(None));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        let _subscription = manager.read().unwrap().target.on(move |event| sink.lock().unwrap().push(event));
+
+        emit_into(&manager, event_at("shared", 0));
+        manager.write().unwrap().emit(event_at("direct", 0));
+
+        let stored = manager.read().unwrap().query_arc(&SearchQuery::new());
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert!(stored.iter().all(|event| received.iter().any(|sent| Arc::ptr_eq(sent, event))));
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let mut manager = EventManager::new(None);
+        let events: Vec<_> = [100, 95, 90, 60, 58, 20, 19].into_iter().map(|ago| event_at(&ago.to_string(), ago)).collect();
+        // Out of order, to check gaps are found in time order
+        events.iter().rev().for_each(|event| manager.push(event.clone()));
+
+        let timestamp = |index: usize| events[index].event_data.timestamp;
+        assert_eq!(manager.find_gaps(Duration::from_secs(30)), [(timestamp(2), timestamp(3)), (timestamp(4), timestamp(5))]);
+        assert_eq!(manager.find_gaps(Duration::from_secs(38)), [(timestamp(4), timestamp(5))]);
+        assert!(manager.find_gaps(Duration::from_secs(60)).is_empty());
+        assert!(EventManager::new(None).find_gaps(Duration::ZERO).is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut manager = EventManager::new(Some(50)).with_max_bytes(1 << 20);
+        for (index, level) in [Level::ERROR, Level::WARN, Level::INFO, Level::INFO].into_iter().enumerate() {
+            let mut event = event_at(&index.to_string(), 10 - index as i64);
+            event.event_data.level = level.into();
+            manager.push(event);
+        }
+        for id in [1, 2] {
+            manager.push_closed_span(SpanInfo::new(id, format!("span{}", id), "app".to_string(), Level::INFO));
+        }
+
+        let json = serde_json::to_string(&manager.snapshot_state()).unwrap();
+        let restored = EventManager::restore_state(serde_json::from_str(&json).unwrap());
+
+        assert_eq!((restored.max_events, restored.max_bytes), (50, Some(1 << 20)));
+        assert_eq!(restored.summary(), manager.summary());
+        assert_eq!(restored.get_by_level(Level::INFO).len(), 2);
+        let messages = |manager: &EventManager| manager.iter().map(|e| e.event_data.message.clone()).collect::<Vec<_>>();
+        assert_eq!(messages(&restored), messages(&manager));
+        let span_ids = |manager: &EventManager| manager.closed_spans().map(|span| span.id).collect::<Vec<_>>();
+        assert_eq!(span_ids(&restored), [2, 1]);
+        assert_eq!(restored.estimated_size(), manager.estimated_size());
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_emit_records_metrics() {
+        use metrics_util::{
+            CompositeKey, MetricKind,
+            debugging::{DebugValue, DebuggingRecorder},
+        };
+
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            for level in [Level::ERROR, Level::ERROR, Level::WARN] {
+                emit(Event::new(EventData::new("m".to_string(), level, "spanner_test::metrics".to_string())));
+            }
+        });
+
+        let counters: HashMap<_, _> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| match (key, value) {
+                (key, DebugValue::Counter(count)) if key.kind() == MetricKind::Counter => {
+                    Some((label(&key, "level"), count))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(counters, HashMap::from([("ERROR".to_string(), 2), ("WARN".to_string(), 1)]));
+
+        fn label(key: &CompositeKey, name: &str) -> String {
+            key.key().labels().find(|label| label.key() == name).map(|label| label.value().to_string()).unwrap_or_default()
+        }
+    }
+
+    #[test]
+    fn test_detect_correlation_anomalies() {
+        let root = |id: u64, name: &str| SpanInfo::new(id, name.to_string(), "app".to_string(), Level::INFO);
+        let (checkout, refund) = (root(1, "checkout"), root(2, "refund"));
+        let event = |seconds_ago: i64, id: &str, root: &SpanInfo| {
+            event_at(id, seconds_ago).with_correlation_id(id.to_string()).with_span_stack(vec![root.clone()])
+        };
+
+        let mut manager = EventManager::new(None);
+        // Two concurrent requests that were handed the same id
+        manager.push(event(5, "collided", &checkout));
+        manager.push(event(4, "collided", &refund));
+        // An id that leaked into work hours later
+        manager.push(event(7200, "leaked", &checkout));
+        manager.push(event(1, "leaked", &checkout));
+        manager.push(event(3, "healthy", &checkout));
+        manager.push(event(2, "healthy", &checkout));
+
+        let anomalies = manager.detect_correlation_anomalies(Duration::from_secs(60));
+        assert_eq!(anomalies.len(), 2);
+        assert_eq!(anomalies[0], CorrelationAnomaly::DisjointRoots {
+            correlation_id: "collided".to_string(),
+            roots: vec![(1, "checkout".to_string()), (2, "refund".to_string())],
+        });
+        assert!(matches!(
+            &anomalies[1],
+            CorrelationAnomaly::LongWindow { correlation_id, events: 2, .. } if correlation_id == "leaked"
+        ));
+        assert_eq!(manager.detect_correlation_anomalies(Duration::from_secs(3 * 3600)).len(), 1);
+    }
+
+    #[test]
+    fn test_health() {
+        let _guard = global_test_guard();
+
+        let uninitialized = health_of(None);
+        assert!(!uninitialized.initialized && !uninitialized.healthy);
+
+        let manager = RwLock::new(EventManager::new(Some(4)));
+        manager.write().unwrap().push(event_at("one", 60));
+        let status = health_of(Some(&manager));
+        assert!(status.initialized && status.healthy && !status.poisoned);
+        assert_eq!(status.buffer_utilization, 0.25);
+        assert!(status.newest_event_age.is_some_and(|age| age >= Duration::from_secs(60)));
+        let mut skewed = EventManager::new(None);
+        skewed.push(event_at("future", -60));
+        assert_eq!(skewed.newest_event_age(), Some(Duration::ZERO));
+
+        let _ = std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _lock = manager.write().unwrap();
+                    panic!("poison the lock");
+                })
+                .join()
+        });
+        let status = health_of(Some(&manager));
+        assert!(status.poisoned && !status.healthy);
+        assert_eq!(status.buffer_utilization, 0.25);
+
+        let json = serde_json::to_value(health()).unwrap();
+        assert!(json["healthy"].is_boolean());
+    }
+
+    #[test]
+    fn test_correlation_summary() {
+        let mut manager = EventManager::new(None);
+        for (id, count) in [("retry-storm", 5), ("slow", 3), ("normal", 2), ("once-a", 1), ("once-b", 1)] {
+            for _ in 0..count {
+                manager.push(event_at(id, 0).with_correlation_id(id.to_string()));
+            }
+        }
+        manager.push(event_at("uncorrelated", 0));
+
+        let summary = manager.correlation_summary(2);
+        assert_eq!(summary.distinct, 5);
+        assert_eq!(summary.singletons, 2);
+        assert_eq!(summary.top, [("retry-storm".to_string(), 5), ("slow".to_string(), 3)]);
+        assert_eq!(manager.correlation_summary(10).top.len(), 5);
+        assert_eq!(EventManager::new(None).correlation_summary(3), CorrelationSummary::default());
+    }
+
+    #[test]
+    fn test_get_by_kind() {
+        let mut manager = EventManager::new(None);
+        manager.push(event_at("log", 0));
+        manager.push(event_at("crash", 0).with_kind(EventKind::Panic));
+        manager.push(event_at("slow span", 0).with_kind(EventKind::SpanSummary));
+
+        let messages = |events: Vec<&Event>| events.iter().map(|e| e.event_data.message.clone()).collect::<Vec<_>>();
+        assert_eq!(messages(manager.get_by_kind(EventKind::Panic)), ["crash"]);
+        assert_eq!(messages(manager.get_by_kind(EventKind::Log)), ["log"]);
+        assert_eq!(messages(manager.query(&SearchQuery::new().kind(EventKind::SpanSummary))), ["slow span"]);
+
+        let export = decode_export(
+            &encode_export(&create_export_data(manager.iter().cloned().collect(), None), Compression::None).unwrap(),
+        )
+        .unwrap();
+        let kinds: Vec<_> = export.events.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, [EventKind::SpanSummary, EventKind::Panic, EventKind::Log]);
+    }
+
+    #[test]
+    fn test_find_first_and_last() {
+        let mut manager = EventManager::new(None);
+        for (message, level) in
+            [("boot", Level::INFO), ("disk slow", Level::ERROR), ("retry", Level::INFO), ("disk full", Level::ERROR)]
+        {
+            let mut event = event_at(message, 0);
+            event.event_data.level = level.into();
+            manager.push(event);
+        }
+
+        let errors = SearchQuery::new().min_level(Level::ERROR);
+        let message = |event: Option<&Event>| event.map(|e| e.event_data.message.clone());
+        assert_eq!(message(manager.find_first(&errors)).as_deref(), Some("disk slow"));
+        assert_eq!(message(manager.find_last(&errors)).as_deref(), Some("disk full"));
+        assert_eq!(message(manager.find_first(&SearchQuery::new())).as_deref(), Some("boot"));
+        assert!(manager.find_last(&SearchQuery::new().target("missing")).is_none());
+    }
+
+    #[test]
+    fn test_top_error_signatures() {
+        let mut manager = EventManager::new(None);
+        let error = |message: &str| {
+            let mut event = event_at(message, 0);
+            event.event_data.level = Level::ERROR.into();
+            event
+        };
+        for order in [17, 4521, 9] {
+            manager.push(error(&format!("order {order} failed after {}ms", order * 3)));
+        }
+        manager.push(error("connection to 10.0.0.12 refused"));
+        manager.push(event_at("order 1 failed after 3ms", 0));
+
+        assert_eq!(manager.top_error_signatures(1), [("order <n> failed after <n>ms".to_string(), 3)]);
+        assert_eq!(manager.top_error_signatures(5)[1], ("connection to <n>.<n>.<n>.<n> refused".to_string(), 1));
+        assert_eq!(manager.top_error_signatures_with(5, |message| message.split(' ').next().unwrap().to_string())[0].1, 3);
+        assert_eq!(
+            error_signature("job 3fa85f64-5717-4562-b3fc-2c963f66afa6 hit 0xdeadbeef at a1b2c3d4e5f6"),
+            "job <uuid> hit <hex> at <hex>"
+        );
+    }
+
+    #[test]
+    fn test_staged_emit_skips_the_buffer_lock() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        let target = "spanner_test::staged";
+        let listener_threads = Arc::new(Mutex::new(HashSet::new()));
+        let seen = listener_threads.clone();
+        let _listener = events().unwrap().on(move |event| {
+            if event.event_data.target == "spanner_test::staged" {
+                seen.lock().unwrap().insert(thread::current().name().map(String::from));
+            }
+        });
+
+        set_staged_emit(true);
+        let reader = GLOBAL_EVENT_MANAGER.get().unwrap().read().unwrap();
+        // Would deadlock if emitting took the write lock while this thread holds the read lock
+        for i in 0..10 {
+            emit(Event::new(EventData::new(format!("staged {i}"), Level::INFO, target.to_string())));
+        }
+        drop(reader);
+        flush_staged();
+        let stored = get_global_events().unwrap().iter().filter(|event| event.event_data.target == target).count();
+        set_staged_emit(false);
+
+        assert_eq!(stored, 10);
+        assert_eq!(*listener_threads.lock().unwrap(), HashSet::from([Some("spanner-drain".to_string())]));
+    }
+
+    #[test]
+    fn test_search_and_export_in_range() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        let mut manager = EventManager::new(None);
+        let target = "spanner_test::range";
+        for minutes_ago in [30, 12, 6, 4, 1] {
+            let mut event = event_at(&format!("{minutes_ago}m ago"), minutes_ago * 60);
+            event.event_data.target = target.into();
+            manager.push(event.clone());
+            emit(event);
+        }
+
+        let window = (Some(Utc::now() - TimeDelta::minutes(10)), Some(Utc::now() - TimeDelta::minutes(2)));
+        let found: Vec<&str> = manager
+            .search_in_range(None, Some(target), None, None, window.0, window.1)
+            .iter()
+            .map(|event| event.event_data.message.as_str())
+            .collect();
+        assert_eq!(found, ["4m ago", "6m ago"]);
+        assert_eq!(manager.search_in_range(None, None, Some("ago"), None, None, window.1).len(), 4);
+
+        let path = std::env::temp_dir().join(format!("spanner_range_{}.bin", uuid::Uuid::new_v4()));
+        let query = SearchQuery::new().target(target).within_last(Duration::from_secs(5 * 60));
+        assert_eq!(export_query_to_bin_file(&path, &query, None).unwrap(), 2);
+        let exported = decode_export(&std::fs::read(&path).unwrap()).unwrap();
+        let messages: Vec<&str> = exported.events.iter().map(|event| event.event_data.message.as_str()).collect();
+        assert_eq!(messages, ["1m ago", "4m ago"]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_shutdown_report() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        clear_global_events();
+        let mut error = EventData::new("batch 7 failed".to_string(), Level::ERROR, "spanner_test::report".to_string());
+        error.timestamp = Utc::now();
+        emit(Event::new(error));
+        let mut span = SpanInfo::new(1, "spanner_test_report_job".to_string(), "app".to_string(), Level::INFO);
+        span.duration = Some(Duration::from_secs(3600));
+        record_closed_span(span);
+
+        let mut report = Vec::new();
+        print_shutdown_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("Event Summary:"));
+        assert!(report.contains("Top errors:\n") && report.contains("x batch <n> failed\n"));
+        assert!(report.contains("Slowest spans:\n  3600s spanner_test_report_job (app)\n"));
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        drop(report_on_shutdown(SharedWriter(output.clone(), usize::MAX)));
+        report_on_shutdown(SharedWriter(output.clone(), usize::MAX)).cancel();
+        assert_eq!(String::from_utf8(output.lock().unwrap().clone()).unwrap(), report);
+    }
+
+    #[test]
+    fn test_top_threads_and_targets() {
+        let mut manager = EventManager::new(None);
+        assert!(manager.top_threads(Duration::from_secs(60), 3).is_empty());
+
+        let event = |seconds_ago: i64, thread: &str, target: &str| {
+            let mut event = event_at("m", seconds_ago).with_thread_info(thread.to_string(), None);
+            event.event_data.target = target.into();
+            event
+        };
+        for (seconds_ago, thread, target) in [
+            (5, "worker-1", "app::db"),
+            (4, "worker-1", "app::db"),
+            (3, "worker-2", "app::http"),
+            (2, "worker-1", "app::http"),
+            (1, "worker-3", "app::db"),
+            // Outside the window, would otherwise make worker-2 the busiest
+            (600, "worker-2", "app::cache"),
+            (601, "worker-2", "app::cache"),
+            (602, "worker-2", "app::cache"),
+        ] {
+            manager.push(event(seconds_ago, thread, target));
+        }
+
+        let window = Duration::from_secs(60);
+        assert_eq!(manager.top_threads(window, 2), [("worker-1".to_string(), 3), ("worker-2".to_string(), 1)]);
+        assert_eq!(manager.top_targets(window, 5), [("app::db".to_string(), 3), ("app::http".to_string(), 2)]);
+        assert_eq!(manager.top_targets(Duration::from_secs(3600), 1), [("app::cache".to_string(), 3)]);
+    }
+
+    #[test]
+    fn test_get_last() {
+        let mut manager = EventManager::new(None);
+        for seconds_ago in [600, 310, 290, 60, 0] {
+            manager.push(event_at(&seconds_ago.to_string(), seconds_ago));
+        }
+
+        let recent: Vec<_> =
+            manager.get_last(Duration::from_secs(300)).iter().map(|e| e.event_data.message.as_str()).collect();
+        assert_eq!(recent, ["0", "60", "290"]);
+        assert_eq!(manager.get_last(Duration::MAX).len(), 5);
+        assert!(manager.get_last(Duration::ZERO).len() <= 1);
+    }
+
+    #[test]
+    fn test_export_caps_span_trees() {
+        let mut root = SpanInfo::new(1, "fan_out".to_string(), "app".to_string(), Level::INFO);
+        for id in 0..1000 {
+            root.add_child(SpanInfo::new(id + 2, "task".to_string(), "app".to_string(), Level::INFO));
+        }
+        let event = event_at("wide", 0).with_span_stack(vec![root.clone()]).with_current_span(root);
+
+        let export = create_export_data(vec![event.clone()], None);
+        for span in [&export.events[0].span_stack[0], export.events[0].current_span.as_ref().unwrap()] {
+            assert_eq!(span.node_count(), MAX_EXPORTED_SPANS + 1);
+            assert_eq!(span.children.last().unwrap().name, format!("...{} more spans omitted", 1001 - MAX_EXPORTED_SPANS));
+        }
+        assert_eq!(event.span_stack[0].node_count(), 1001, "live events are left intact");
+
+        // Small trees are exported unchanged
+        let small =
+            event_at("narrow", 0).with_span_stack(vec![SpanInfo::new(1, "a".to_string(), "app".to_string(), Level::INFO)]);
+        assert_eq!(create_export_data(vec![small], None).events[0].span_stack[0].node_count(), 1);
+    }
+
+    #[test]
+    fn test_read_metadata_only() {
+        let path = std::env::temp_dir().join(format!("spanner_metadata_{}.bin", uuid::Uuid::new_v4()));
+        let mut events = vec![event_at("first", 20), event_at("second", 10)];
+        events[1].event_data.level = Level::ERROR.into();
+        let export = create_export_data(events, Some("inventory".to_string()));
+        write_export(&path, &export, Compression::None).unwrap();
+
+        let metadata = read_metadata_only(&path).unwrap();
+        assert_eq!(metadata.total_events, 2);
+        assert_eq!(metadata.timestamp, export.metadata.timestamp);
+        assert_eq!(metadata.level_counts, export.metadata.level_counts);
+        assert_eq!(metadata.description.as_deref(), Some("inventory"));
+
+        // Events after the metadata aren't parsed, so even a truncated dump lists fine
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 20]).unwrap();
+        assert_eq!(read_metadata_only(&path).unwrap().total_events, 2);
+
+        std::fs::write(&path, serde_json::to_vec(&export).unwrap()).unwrap();
+        assert_eq!(read_metadata_only(&path).unwrap().description.as_deref(), Some("inventory"));
+
+        std::fs::write(&path, b"{\"events\": []}").unwrap();
+        assert_eq!(read_metadata_only(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_binary_export_round_trip() {
+        let mut events: Vec<Event> = (0..200).map(|i| event_at(&format!("request {i} handled"), i)).collect();
+        for (i, event) in events.iter_mut().enumerate() {
+            event.event_data.add_field("status".to_string(), "200".to_string());
+            event.span_stack.push(SpanInfo::new(i as u64, "handle".to_string(), "app::http".to_string(), Level::INFO));
+        }
+        events[3].labels.insert("host".to_string(), "a".to_string());
+        let export = create_export_data(events, Some("sizes".to_string()));
+
+        let binary = encode_export(&export, Compression::None).unwrap();
+        assert_eq!(ExportHeader::from_bytes(&binary).unwrap().unwrap().version, EXPORT_HEADER_VERSION);
+        let json = serde_json::to_vec(&export).unwrap();
+        assert!(binary.len() * 2 < json.len(), "binary {} bytes vs JSON {} bytes", binary.len(), json.len());
+
+        let decoded = decode_export(&binary).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), serde_json::to_value(&export).unwrap());
+
+        // Exports with a JSON body behind a version 1 header still import
+        let mut header = ExportHeader::for_events(&export.events);
+        header.version = JSON_EXPORT_HEADER_VERSION;
+        let mut legacy = header.to_bytes().to_vec();
+        legacy.extend_from_slice(&json);
+        assert_eq!(decode_export(&legacy).unwrap().events.len(), 200);
+
+        let path = std::env::temp_dir().join(format!("spanner_legacy_{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, &legacy).unwrap();
+        assert_eq!(read_metadata_only(&path).unwrap().description.as_deref(), Some("sizes"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn test_compression_needs_feature() {
+        let export = create_export_data(vec![event_at("compressed", 0)], None);
+        let error = encode_export(&export, Compression::Gzip).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+
+        // Headers of compressed exports stay readable, the body is what needs the feature
+        let mut data = encode_export(&export, Compression::None).unwrap();
+        data[6] = 1;
+        assert_eq!(ExportHeader::from_bytes(&data).unwrap().unwrap().compression, Compression::Gzip);
+        assert_eq!(decode_export(&data).unwrap_err().kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    #[test]
+    fn test_compressed_export() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        clear_global_events();
+        for i in 0..500 {
+            emit(event_at(&format!("request {} handled", i % 20), 0));
+        }
+
+        let uncompressed = std::env::temp_dir().join(format!("spanner_plain_{}.bin", uuid::Uuid::new_v4()));
+        assert_eq!(export_to_bin_file(&uncompressed).unwrap(), 500);
+        let plain_len = std::fs::metadata(&uncompressed).unwrap().len();
+
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let path = std::env::temp_dir().join(format!("spanner_{:?}_{}.bin", compression, uuid::Uuid::new_v4()));
+            assert_eq!(export_to_bin_file_compressed(&path, compression).unwrap(), 500);
+            assert!(std::fs::metadata(&path).unwrap().len() * 5 < plain_len, "{:?} barely compressed", compression);
+
+            let header = read_export_header(&path).unwrap();
+            assert_eq!((header.compression, header.event_count), (compression, 500));
+            assert_eq!(read_metadata_only(&path).unwrap().level_counts.get("INFO"), Some(&500));
+            assert_eq!(import_from_bin_file(&path).unwrap().len(), 500);
+            let _ = std::fs::remove_file(&path);
+        }
+
+        // Unknown compression codes are rejected rather than misread
+        let mut data = std::fs::read(&uncompressed).unwrap();
+        data[6] = 9;
+        assert!(decode_export(&data).is_err());
+        let _ = std::fs::remove_file(&uncompressed);
+    }
+
+    #[test]
+    fn test_export_preserves_parent_chains() {
+        let mut root = event_at("request received", 30);
+        root.event_data.add_field("route".to_string(), "\"/orders\"".to_string());
+        let root = Arc::new(root);
+        let query = Arc::new(event_at("query started", 20).with_parent(root.clone()));
+        let failed = event_at("query failed", 10).with_parent(query.clone());
+        let retried = event_at("query retried", 5).with_parent(query.clone());
+
+        let export = create_export_data(vec![failed.clone(), retried, event_at("unrelated", 1)], None);
+        // The shared parent and grandparent are stored once
+        assert_eq!(export.parents.len(), 2);
+
+        let decoded = decode_export(&encode_export(&export, Compression::None).unwrap()).unwrap();
+        assert_eq!(decoded.events[0].get_full_context(), failed.get_full_context());
+        let grandparent = decoded.events[0].parent.as_ref().and_then(|parent| parent.parent.clone()).unwrap();
+        assert_eq!(grandparent.event_data.message, "request received");
+        assert!(Arc::ptr_eq(decoded.events[0].parent.as_ref().unwrap(), decoded.events[1].parent.as_ref().unwrap()));
+        assert!(decoded.events[2].parent.is_none());
+
+        let mut cyclic = export.clone();
+        cyclic.parent_links.push((4, 3));
+        assert!(decode_export(&encode_export(&cyclic, Compression::None).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_ndjson_round_trip() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        clear_global_events();
+        set_session_labels(BTreeMap::from([("host".to_string(), "web-1".to_string())]));
+        for (i, message) in ["first", "second", "third"].into_iter().enumerate() {
+            emit(event_at(message, 10 - i as i64));
+        }
+
+        let path = std::env::temp_dir().join(format!("spanner_ndjson_{}.ndjson", uuid::Uuid::new_v4()));
+        assert_eq!(export_to_ndjson_file(&path).unwrap(), 3);
+        set_session_labels(BTreeMap::new());
+        clear_global_events();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written.lines().count(), 4);
+        assert!(written.starts_with("{\"spanner_metadata\":"));
+        let imported = import_from_ndjson_file(&path).unwrap();
+        assert_eq!(messages(&imported), ["third", "second", "first"]);
+        assert!(imported.iter().all(|event| event.labels.get("host").map(String::as_str) == Some("web-1")));
+
+        // A truncated file keeps every complete line
+        std::fs::write(&path, &written[..written.len() - 10]).unwrap();
+        assert_eq!(messages(&import_from_ndjson_file(&path).unwrap()), ["third", "second"]);
+
+        // The metadata line is optional, but a malformed complete line is an error
+        let events_only: String = written.lines().skip(1).map(|line| format!("{line}\n")).collect();
+        std::fs::write(&path, &events_only).unwrap();
+        assert_eq!(import_from_ndjson_file(&path).unwrap().len(), 3);
+        std::fs::write(&path, format!("{events_only}not json\n")).unwrap();
+        assert_eq!(import_from_ndjson_file(&path).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_export_header() {
+        let path = std::env::temp_dir().join(format!("spanner_header_{}.bin", uuid::Uuid::new_v4()));
+        let events = vec![event_at("old", 30), event_at("new", 10)];
+        let export = create_export_data(events.clone(), None);
+        write_export(&path, &export, Compression::None).unwrap();
+
+        let header = read_export_header(&path).unwrap();
+        assert_eq!(header.event_count, 2);
+        assert_eq!(header.min_timestamp, Some(events[0].event_data.timestamp));
+        assert_eq!(header.max_timestamp, Some(events[1].event_data.timestamp));
+        assert_eq!(import_from_bin_file(&path).unwrap().len(), 2);
+
+        // A header that disagrees with the body is rejected on import
+        let mut data = std::fs::read(&path).unwrap();
+        data[8] = 3;
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(import_from_bin_file(&path).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+
+        // Legacy exports without a header still import, but have no header to read
+        std::fs::write(&path, serde_json::to_vec(&export).unwrap()).unwrap();
+        assert_eq!(import_from_bin_file(&path).unwrap().len(), 2);
+        assert_eq!(read_export_header(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let empty = encode_export(&create_export_data(Vec::new(), None), Compression::None).unwrap();
+        let header = ExportHeader::from_bytes(&empty).unwrap().unwrap();
+        assert_eq!((header.event_count, header.min_timestamp), (0, None));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_get_orphans() {
+        let span = crate::SpanInfo::new(1, "request".to_string(), "test".to_string(), Level::INFO);
+        let mut manager = EventManager::new(None);
+        manager.push(event_at("top level", 0));
+        manager.push(event_at("in stack", 0).with_span_stack(vec![span.clone()]));
+        manager.push(event_at("current only", 0).with_current_span(span));
+        manager.push(event_at("also top level", 0));
+
+        let orphans: Vec<_> = manager.get_orphans().iter().map(|e| e.event_data.message.as_str()).collect();
+        assert_eq!(orphans, ["also top level", "top level"]);
+        assert_eq!(manager.get_by_span("request").len() + orphans.len(), manager.len());
+        assert!(manager.summary().contains("2 events outside any span"));
+    }
+
+    #[test]
+    fn test_export_byte_stream() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        let target = "spanner_test::byte_stream";
+        for message in ["first", "second", "third"] {
+            emit(Event::new(EventData::new(message.to_string(), Level::INFO, target.to_string())));
+        }
+        let stream = export_byte_stream(Some(SearchQuery::new().target(target)));
+        emit(Event::new(EventData::new("after".to_string(), Level::INFO, target.to_string())));
+
+        let chunks: Vec<Vec<u8>> = futures::executor::block_on(stream.map(Result::unwrap).collect());
+        let body = String::from_utf8(chunks.concat()).unwrap();
+        let mut lines = body.lines();
+        let metadata: ExportMetadata = serde_json::from_str(lines.next().unwrap()).unwrap();
+        let events: Vec<Event> = lines.map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        assert_eq!(metadata.total_events, 3, "the snapshot is taken when the stream is created");
+        let messages: Vec<&str> = events.iter().map(|event| event.event_data.message.as_str()).collect();
+        assert_eq!(messages, ["third", "second", "first"]);
+    }
+
+    #[test]
+    fn test_export_and_clear_is_exactly_once() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        clear_global_events();
+
+        const PER_THREAD: usize = 500;
+        let target = "spanner_test::drain";
+        let emitters: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    for index in 0..PER_THREAD {
+                        emit(Event::new(EventData::new(index.to_string(), Level::INFO, target.to_string())));
+                    }
+                })
+            })
+            .collect();
+
+        let dir = std::env::temp_dir().join(format!("spanner_drain_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut drains = 0;
+        while emitters.iter().any(|emitter| !emitter.is_finished()) || drains == 0 {
+            export_and_clear_to_bin_file(dir.join(format!("{}.bin", drains))).unwrap();
+            drains += 1;
+        }
+        emitters.into_iter().for_each(|emitter| emitter.join().unwrap());
+        export_and_clear_to_bin_file(dir.join(format!("{}.bin", drains))).unwrap();
+
+        let mut ids = HashSet::new();
+        for drain in 0..=drains {
+            let drained = import_from_bin_file(dir.join(format!("{}.bin", drain))).unwrap();
+            for event in drained.iter().filter(|e| e.event_data.target == target) {
+                assert!(ids.insert(event.id), "event {} was exported twice", event.id);
+            }
+        }
+        assert_eq!(ids.len(), 4 * PER_THREAD, "every event is exported once");
+        assert!(get_global_events().unwrap_or_default().iter().all(|e| e.event_data.target != target));
+
+        // Each drain reports only the events dropped since the previous one
+        GLOBAL_EVENT_MANAGER.get().unwrap().write().unwrap().dropped_count += 3;
+        for expected in [3, 0] {
+            export_and_clear_to_bin_file(dir.join("dropped.bin")).unwrap();
+            assert_eq!(read_metadata_only(dir.join("dropped.bin")).unwrap().dropped_events, expected);
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_session_labels_in_metadata_and_merged_queries() {
+        let _guard = global_test_guard();
+        let dump = |service: &str, message: &str| {
+            set_session_labels(BTreeMap::from([("service".to_string(), service.to_string())]));
+            let encoded = encode_export(&create_export_data(vec![event_at(message, 0)], None), Compression::None).unwrap();
+            decode_export(&encoded).unwrap()
+        };
+        let checkout = dump("checkout", "from checkout");
+        let billing = dump("billing", "from billing");
+        set_session_labels(BTreeMap::new());
+
+        assert_eq!(checkout.metadata.labels.get("service").map(String::as_str), Some("checkout"));
+        let mut merged = EventManager::new(None);
+        checkout.events.into_iter().chain(billing.events).for_each(|event| merged.push(event));
+        merged.push(event_at("live", 0));
+
+        let messages = |query: SearchQuery| -> Vec<String> {
+            merged.query(&query).iter().map(|event| event.event_data.message.clone()).collect()
+        };
+        assert_eq!(messages(SearchQuery::new().label("service", "checkout")), ["from checkout"]);
+        assert_eq!(messages(SearchQuery::new().label("service", "billing")), ["from billing"]);
+
+        set_session_labels(BTreeMap::from([("service".to_string(), "api".to_string())]));
+        assert_eq!(messages(SearchQuery::new().label("service", "api")), ["live"], "live events take the session's labels");
+        set_session_labels(BTreeMap::new());
+    }
+
+    #[test]
+    fn test_default_export_description() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        set_export_description(Some("prod-us-east / incident-123".to_string()));
+        let path = std::env::temp_dir().join(format!("spanner_description_{}.bin", uuid::Uuid::new_v4()));
+        export_to_bin_file(&path).unwrap();
+        let described = decode_export(&std::fs::read(&path).unwrap()).unwrap();
+        export_filtered_to_bin_file(&path, None, None, None, None, Some("explicit".to_string())).unwrap();
+        let explicit = decode_export(&std::fs::read(&path).unwrap()).unwrap();
+        set_export_description(None);
+
+        assert_eq!(described.metadata.description.as_deref(), Some("prod-us-east / incident-123"));
+        assert_eq!(explicit.metadata.description.as_deref(), Some("explicit"));
+        assert_eq!(create_export_data(Vec::new(), None).metadata.description, None);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// Writer appending to a shared buffer, failing once `fail_after` bytes are written
+    #[derive(Clone)]
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>, usize);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut written = self.0.lock().unwrap();
+            if written.len() + buf.len() > self.1 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn test_attach_line_writer() {
+        let _guard = global_test_guard();
+        let line = |writer: &SharedWriter| String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        let emit_line = |message: &str| {
+            let mut data = EventData::new(message.to_string(), Level::WARN, "spanner_test::tail".to_string());
+            data.add_field("attempt".to_string(), "2".to_string());
+            emit(Event::new(data));
+        };
+
+        let compact = SharedWriter(Arc::default(), usize::MAX);
+        let subscription = attach_line_writer(compact.clone(), LineFormat::Compact);
+        emit_line("retrying\nsoon");
+        drop(subscription);
+        emit_line("after detaching");
+
+        let written = line(&compact);
+        let ours: Vec<_> = written.lines().filter(|line| line.contains("spanner_test::tail")).collect();
+        assert_eq!(ours.len(), 1, "{}", written);
+        assert!(ours[0].ends_with(" WARN spanner_test::tail: retrying\\nsoon attempt=2"), "{}", written);
+
+        // A failing writer is detached after the first error
+        let broken = SharedWriter(Arc::default(), 0);
+        let subscription = attach_line_writer(broken.clone(), LineFormat::Json);
+        emit_line("fails");
+        assert!(!subscription.is_active());
+        subscription.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_line_writer_shutdown_flushes() {
+        let _guard = global_test_guard();
+        let path = std::env::temp_dir().join(format!("spanner_tail_{}.log", uuid::Uuid::new_v4()));
+        let handle = attach_line_writer(io::BufWriter::new(File::create(&path).unwrap()), LineFormat::Compact);
+        for attempt in 0..3 {
+            emit(Event::new(EventData::new(
+                format!("pending {}", attempt),
+                Level::INFO,
+                "spanner_test::shutdown".to_string(),
+            )));
+        }
+
+        handle.shutdown().unwrap();
+        emit(Event::new(EventData::new("late".to_string(), Level::INFO, "spanner_test::shutdown".to_string())));
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let ours: Vec<_> = written.lines().filter(|line| line.contains("spanner_test::shutdown")).collect();
+        assert_eq!(ours.len(), 3, "{}", written);
+        assert!(ours[2].ends_with("pending 2"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_import_with_progress() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        clear_global_events();
+
+        let path = std::env::temp_dir().join(format!("spanner_progress_{}.bin", uuid::Uuid::new_v4()));
+        let total = IMPORT_CHUNK * 2 + 5;
+        write_export(
+            &path,
+            &create_export_data((0..total).map(|_| event_at("imported", 0)).collect(), None),
+            Compression::None,
+        )
+        .unwrap();
+
+        let mut calls = Vec::new();
+        let (_, stats) = import_and_merge_with_progress(&path, |processed, of| calls.push((processed, of))).unwrap();
+
+        assert_eq!(stats, MergeStats { added: total, skipped_duplicates: 0 });
+        assert_eq!(calls.first(), Some(&(0, total)));
+        assert_eq!(calls.last(), Some(&(total, total)));
+        assert!(calls.windows(2).all(|pair| pair[0].0 < pair[1].0), "progress must increase: {:?}", calls);
+        assert_eq!(get_global_event_count(), total);
+
+        clear_global_events();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_import_skips_duplicates() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        clear_global_events();
+
+        let events: Vec<Event> = (0..6).map(|i| event_at(&format!("window event {i}"), i)).collect();
+        let first = std::env::temp_dir().join(format!("spanner_dedup_a_{}.bin", uuid::Uuid::new_v4()));
+        let second = std::env::temp_dir().join(format!("spanner_dedup_b_{}.bin", uuid::Uuid::new_v4()));
+        write_export(&first, &create_export_data(events[..4].to_vec(), None), Compression::None).unwrap();
+        // Overlapping window, with one event repeated within the dump itself
+        let mut overlapping = events[2..].to_vec();
+        overlapping.push(events[5].clone());
+        write_export(&second, &create_export_data(overlapping, None), Compression::None).unwrap();
+
+        let (_, stats) = import_and_merge_from_bin_file(&first).unwrap();
+        assert_eq!(stats, MergeStats { added: 4, skipped_duplicates: 0 });
+        let (_, stats) = import_and_merge_from_bin_file(&first).unwrap();
+        assert_eq!(stats, MergeStats { added: 0, skipped_duplicates: 4 });
+        let (_, stats) = import_and_merge_with_policy(&second, ImportMergePolicy::InterleaveByTime).unwrap();
+        assert_eq!(stats, MergeStats { added: 2, skipped_duplicates: 3 });
+        assert_eq!(get_global_event_count(), 6);
+
+        // Events that only share a message and target with a live one are still distinct
+        emit(event_at("window event 0", 0));
+        assert_eq!(get_global_event_count(), 7);
+
+        clear_global_events();
+        let _ = std::fs::remove_file(&first);
+        let _ = std::fs::remove_file(&second);
+    }
+
+    #[test]
+    fn test_get_by_location() {
+        let at = |message: &str, file: &str, line: Option<u32>| {
+            let mut event = event_at(message, 0);
+            event.event_data.file = Some(file.to_string());
+            event.event_data.line = line;
+            event
+        };
+
+        let mut manager = EventManager::new(None);
+        manager.push(at("before", "src/main.rs", Some(99)));
+        manager.push(at("start", "src/main.rs", Some(100)));
+        manager.push(at("end", "src/main.rs", Some(200)));
+        manager.push(at("after", "src/main.rs", Some(201)));
+        manager.push(at("no line", "src/main.rs", None));
+        manager.push(at("other file", "src/lib.rs", Some(150)));
+        manager.push(event_at("no file", 0));
+
+        let located = |file: &str, range: Option<(u32, u32)>| -> Vec<String> {
+            manager.get_by_location(file, range).iter().map(|e| e.event_data.message.clone()).collect()
+        };
+        assert_eq!(located("main.rs", Some((100, 200))), ["end", "start"]);
+        assert_eq!(located("main.rs", None), ["no line", "after", "end", "start", "before"]);
+        assert_eq!(located("src/", Some((150, 150))), ["other file"]);
+        assert!(located("main.rs", Some((300, 400))).is_empty());
+    }
+
+    #[test]
+    fn test_span_duration_percentiles() {
+        let closed = |name: &str, millis: u64| {
+            let mut span = SpanInfo::new(0, name.to_string(), "test".to_string(), Level::INFO);
+            span.duration = Some(Duration::from_millis(millis));
+            span
+        };
+
+        let mut manager = EventManager::new(None);
+        assert!(manager.span_duration_percentiles("db", &[50.0]).is_empty());
+
+        manager.push_closed_span(closed("db", 7));
+        assert_eq!(manager.span_duration_percentiles("db", &[50.0, 99.0]), [
+            (50.0, Duration::from_millis(7)),
+            (99.0, Duration::from_millis(7))
+        ]);
+
+        for millis in (1..=100).rev().filter(|m| *m != 7) {
+            manager.push_closed_span(closed("db", millis));
+        }
+        manager.push_closed_span(closed("http", 5_000));
+
+        let millis: Vec<_> = manager
+            .span_duration_percentiles("db", &[0.0, 50.0, 90.0, 99.0, 100.0])
+            .into_iter()
+            .map(|(_, duration)| duration.as_millis())
+            .collect();
+        assert_eq!(millis, [1, 50, 90, 99, 100]);
+    }
+
+    #[test]
+    fn test_build_event_forest() {
+        let request = Arc::new(event_at("request", 30));
+        let query = Arc::new(event_at("query", 20).with_parent(request.clone()));
+        let evicted = Arc::new(event_at("evicted", 40));
+
+        let mut manager = EventManager::new(None);
+        manager.push((*request).clone());
+        manager.push((*query).clone());
+        manager.push(event_at("row", 15).with_parent(query.clone()));
+        manager.push(event_at("response", 10).with_parent(request.clone()));
+        manager.push(event_at("late", 5).with_parent(evicted));
+        manager.push(event_at("standalone", 0));
+
+        fn shape(node: &EventNode) -> String {
+            let children: Vec<_> = node.children.iter().map(shape).collect();
+            let marker = if node.dangling_parent { "?" } else { "" };
+            match children.is_empty() {
+                true => format!("{}{}", marker, node.event.event_data.message),
+                false => format!("{}{}({})", marker, node.event.event_data.message, children.join(" ")),
+            }
+        }
+
+        let forest: Vec<_> = manager.build_event_forest().iter().map(shape).collect();
+        assert_eq!(forest, ["standalone", "?late", "request(response query(row))"]);
+    }
+
+    #[test]
+    fn test_export_projected() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        clear_global_events();
+
+        let mut event = event_at("projected", 0);
+        event.event_data.add_field("user_id".to_string(), "42".to_string());
+        event.event_data.add_field("internal_trace_blob".to_string(), "x".repeat(64));
+        event.add_metadata("region".to_string(), "eu".to_string());
+        event.add_metadata("debug_handle".to_string(), "0xdead".to_string());
+        emit(event);
+
+        let path = std::env::temp_dir().join(format!("spanner_projected_{}.bin", uuid::Uuid::new_v4()));
+        export_projected(&path, Some(vec!["user_id".to_string()]), Some(Vec::new())).unwrap();
+        let bytes = String::from_utf8_lossy(&std::fs::read(&path).unwrap()).into_owned();
+
+        assert!(bytes.contains("user_id"));
+        for excluded in ["internal_trace_blob", "region", "debug_handle"] {
+            assert!(!bytes.contains(excluded), "{} should be projected out", excluded);
+        }
+        let live = get_global_events().unwrap().into_iter().find(|e| e.event_data.message == "projected").unwrap();
+        assert_eq!(live.event_data.fields.len(), 2, "the live buffer is untouched");
+
+        clear_global_events();
+        let _ = std::fs::remove_file(&path);
     }
-    "No events captured".to_string()
 }
@@ -0,0 +1,340 @@
+//! Memory-mapped ring buffer store that survives hard crashes
+
+use {
+    crate::{event::Event, store::EventStore},
+    memmap2::MmapMut,
+    std::{collections::VecDeque, fs::OpenOptions, io, path::Path, sync::Arc},
+};
+
+const MAGIC: [u8; 8] = *b"SPNRRING";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 64;
+const LEN_PREFIX: usize = 4;
+
+/// Ring buffer [`EventStore`] persisted to a memory-mapped file, so the last captured events
+/// survive `SIGKILL`s and segfaults.
+///
+/// Events are also kept in memory for querying. The oldest events are dropped once the
+/// file's data region is full, and events too large to fit in it at all are not stored.
+/// Either way the manager is handed them through [`EventStore::pop_over_capacity`], so they
+/// count as dropped and reach its overflow hook.
+///
+/// # File format
+///
+/// All integers are little endian. The file starts with a 64 byte header:
+///
+/// | offset | size | field                                              |
+/// |--------|------|----------------------------------------------------|
+/// | 0      | 8    | magic `SPNRRING`                                   |
+/// | 8      | 4    | format version (`1`)                               |
+/// | 16     | 8    | capacity of the data region in bytes               |
+/// | 24     | 8    | offset of the oldest record in the data region     |
+/// | 32     | 8    | offset where the next record will be written       |
+/// | 40     | 8    | number of live records                             |
+///
+/// The data region follows the header. Each record is a `u32` length and that many bytes of
+/// JSON-encoded [`Event`]. When a record doesn't fit before the end of the region, writing
+/// wraps to offset `0`; a zero length (or fewer than 4 bytes left) marks the wrap point.
+///
+/// # Recovery
+///
+/// Evicted records are dropped from the header before their bytes are overwritten, and new
+/// records are written before the header counts them, so after a crash the header always
+/// describes complete records. Reading stops at the first record that fails to decode and
+/// keeps the ones before it. Read the surviving events with [`MmapStore::read_events`]
+/// from any process, or reopen the file with [`MmapStore::open`] to keep appending to it.
+pub struct MmapStore {
+    map: MmapMut,
+    capacity: usize,
+    events: VecDeque<Arc<Event>>,
+    /// Events dropped to make room, or that didn't fit, oldest first, waiting for the manager
+    evicted: VecDeque<Arc<Event>>,
+}
+
+/// Ring positions read from or written to the header
+#[derive(Debug, Clone, Copy, Default)]
+struct Cursor {
+    head: usize,
+    tail: usize,
+    count: usize,
+}
+
+impl MmapStore {
+    /// Open or create a ring file with a data region of `capacity` bytes.
+    ///
+    /// Events already in an existing file are loaded. Fails with
+    /// [`io::ErrorKind::InvalidData`] if the file isn't a ring file of the same capacity.
+    pub fn open<P: AsRef<Path>>(path: P, capacity: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        if is_new {
+            file.set_len((HEADER_LEN + capacity) as u64)?;
+        }
+
+        // SAFETY: the file is only meant to be modified through this store. Another process
+        // truncating it while mapped is the one case memmap2 can't protect against.
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        let mut store = Self { map, capacity, events: VecDeque::new(), evicted: VecDeque::new() };
+
+        if is_new {
+            store.map[0..8].copy_from_slice(&MAGIC);
+            store.map[8..12].copy_from_slice(&VERSION.to_le_bytes());
+            write_u64(&mut store.map, 16, capacity);
+            store.set_cursor(Cursor::default());
+        } else {
+            let events = parse(&store.map)?;
+            if read_u64(&store.map, 16) != capacity {
+                return Err(invalid_data("ring file was created with a different capacity"));
+            }
+            let recovered = events.len() == store.cursor().count;
+            store.events = events.into_iter().rev().map(Arc::new).collect();
+            if !recovered {
+                // Drop the corrupt records from the file too
+                store.rewrite();
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// Read the events in a ring file, oldest first, without opening it for writing
+    pub fn read_events<P: AsRef<Path>>(path: P) -> io::Result<Vec<Event>> { parse(&std::fs::read(path)?) }
+
+    /// Flush written events to disk, for durability beyond process crashes (e.g. power loss)
+    pub fn flush(&self) -> io::Result<()> { self.map.flush() }
+
+    fn cursor(&self) -> Cursor {
+        Cursor { head: read_u64(&self.map, 24), tail: read_u64(&self.map, 32), count: read_u64(&self.map, 40) }
+    }
+
+    fn set_cursor(&mut self, cursor: Cursor) {
+        write_u64(&mut self.map, 24, cursor.head);
+        write_u64(&mut self.map, 32, cursor.tail);
+        write_u64(&mut self.map, 40, cursor.count);
+    }
+
+    fn data(&mut self) -> &mut [u8] { &mut self.map[HEADER_LEN..] }
+
+    /// Drop the oldest record from the file and memory
+    fn evict(&mut self, cursor: &mut Cursor) -> Option<Arc<Event>> {
+        let start = record_start(&self.map[HEADER_LEN..], cursor.head);
+        let len = read_len(&self.map[HEADER_LEN..], start);
+        cursor.head = start + LEN_PREFIX + len;
+        cursor.count -= 1;
+        if cursor.count == 0 {
+            *cursor = Cursor::default();
+        }
+        self.events.pop_back()
+    }
+
+    /// Append a record, evicting old ones until it fits. Returns `false` if it never can.
+    fn write_record(&mut self, payload: &[u8]) -> bool {
+        let record_len = LEN_PREFIX + payload.len();
+        if record_len > self.capacity || payload.len() > u32::MAX as usize {
+            return false;
+        }
+
+        let mut cursor = self.cursor();
+        let position = loop {
+            // Wrapped: live records run from `head` to the end and from `0` to `tail`
+            let wrapped = cursor.count > 0 && cursor.head >= cursor.tail;
+            if wrapped {
+                if cursor.tail + record_len <= cursor.head {
+                    break cursor.tail;
+                }
+            } else if cursor.tail + record_len <= self.capacity {
+                break cursor.tail;
+            } else if cursor.count == 0 || record_len <= cursor.head {
+                // Mark the wrap point so readers skip the unused end of the region
+                let tail = cursor.tail;
+                if self.capacity - tail >= LEN_PREFIX {
+                    self.data()[tail..tail + LEN_PREFIX].copy_from_slice(&0u32.to_le_bytes());
+                }
+                break 0;
+            }
+            let evicted = self.evict(&mut cursor);
+            self.evicted.extend(evicted);
+        };
+        // Forget the evicted records before their bytes are overwritten
+        self.set_cursor(cursor);
+
+        let data = self.data();
+        data[position..position + LEN_PREFIX].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        data[position + LEN_PREFIX..position + record_len].copy_from_slice(payload);
+
+        cursor.tail = position + record_len;
+        cursor.count += 1;
+        self.set_cursor(cursor);
+        true
+    }
+
+    /// Rewrite the file from the in-memory events
+    fn rewrite(&mut self) {
+        self.set_cursor(Cursor::default());
+        let events = std::mem::take(&mut self.events);
+        events.into_iter().rev().for_each(|event| self.push(event));
+    }
+}
+
+impl EventStore for MmapStore {
+    fn push(&mut self, event: Arc<Event>) {
+        if serde_json::to_vec(event.as_ref()).is_ok_and(|payload| self.write_record(&payload)) {
+            self.events.push_front(event);
+        } else {
+            self.evicted.push_back(event);
+        }
+    }
+
+    fn pop_over_capacity(&mut self) -> Option<Event> { self.evicted.pop_front().map(Arc::unwrap_or_clone) }
+
+    fn pop_oldest(&mut self) -> Option<Event> {
+        let mut cursor = self.cursor();
+        if cursor.count == 0 {
+            return None;
+        }
+        let oldest = self.evict(&mut cursor);
+        self.set_cursor(cursor);
+        oldest.map(Arc::unwrap_or_clone)
+    }
+
+    fn len(&self) -> usize { self.events.len() }
+
+    fn clear(&mut self) {
+        self.events.clear();
+        self.evicted.clear();
+        self.set_cursor(Cursor::default());
+    }
+
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = &Event> + '_> { Box::new(self.events.iter().map(Arc::as_ref)) }
+
+    fn retain(&mut self, f: &mut dyn FnMut(&Event) -> bool) {
+        self.events.retain(|event| f(event));
+        self.rewrite();
+    }
+}
+
+/// Parse the live records in a ring file, oldest first, stopping at the first corrupt one
+fn parse(file: &[u8]) -> io::Result<Vec<Event>> {
+    if file.len() < HEADER_LEN || file[0..8] != MAGIC {
+        return Err(invalid_data("not a spanner ring file"));
+    }
+    if u32::from_le_bytes(file[8..12].try_into().unwrap()) != VERSION {
+        return Err(invalid_data("unsupported ring file version"));
+    }
+
+    let capacity = read_u64(file, 16);
+    let data = file.get(HEADER_LEN..HEADER_LEN + capacity).ok_or_else(|| invalid_data("ring file is truncated"))?;
+
+    let mut position = read_u64(file, 24);
+    let mut events = Vec::new();
+    for _ in 0..read_u64(file, 40) {
+        let start = record_start(data, position);
+        if data.len() < start + LEN_PREFIX {
+            break;
+        }
+        let len = read_len(data, start);
+        let Some(Ok(event)) = data.get(start + LEN_PREFIX..start + LEN_PREFIX + len).map(serde_json::from_slice) else {
+            break;
+        };
+        events.push(event);
+        position = start + LEN_PREFIX + len;
+    }
+
+    Ok(events)
+}
+
+/// Resolve a record position, following the wrap marker back to the start of the region
+fn record_start(data: &[u8], position: usize) -> usize {
+    if data.len().saturating_sub(position) < LEN_PREFIX || read_len(data, position) == 0 { 0 } else { position }
+}
+
+fn read_len(data: &[u8], position: usize) -> usize {
+    u32::from_le_bytes(data[position..position + LEN_PREFIX].try_into().unwrap()) as usize
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> usize {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize
+}
+
+fn write_u64(bytes: &mut [u8], offset: usize, value: usize) {
+    bytes[offset..offset + 8].copy_from_slice(&(value as u64).to_le_bytes());
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{EventData, EventManager},
+        tracing::Level,
+    };
+
+    fn event(index: usize) -> Event {
+        Event::new(EventData::new(format!("event {}", index), Level::INFO, "ring".to_string()))
+    }
+
+    fn messages(events: &[Event]) -> Vec<String> { events.iter().map(|e| e.event_data.message.clone()).collect() }
+
+    #[test]
+    fn test_ring_survives_reopen() {
+        let path = std::env::temp_dir().join(format!("spanner_ring_{}.bin", uuid::Uuid::new_v4()));
+        let record_len = LEN_PREFIX + serde_json::to_vec(&event(10)).unwrap().len();
+        let capacity = record_len * 5 + record_len / 2;
+
+        {
+            let mut manager = EventManager::with_store(MmapStore::open(&path, capacity).unwrap(), None);
+            // Enough events to wrap around the region several times
+            for index in 10..40 {
+                manager.push(event(index));
+            }
+            assert_eq!(manager.len(), 5);
+            // Records the ring overwrote count as dropped and leave the size estimate
+            assert_eq!(manager.dropped_count(), 25);
+            assert_eq!(manager.estimated_size(), manager.iter().map(Event::estimated_size).sum::<usize>());
+        }
+
+        let expected: Vec<_> = (35..40).map(|index| format!("event {}", index)).collect();
+        assert_eq!(messages(&MmapStore::read_events(&path).unwrap()), expected);
+
+        // Reopening loads the surviving events and keeps appending after them
+        let mut store = MmapStore::open(&path, capacity).unwrap();
+        assert_eq!(store.len(), 5);
+        store.push(Arc::new(event(40)));
+        store.retain(&mut |e| e.event_data.message != "event 38");
+        assert_eq!(messages(&MmapStore::read_events(&path).unwrap()), ["event 36", "event 37", "event 39", "event 40"]);
+        assert_eq!(store.pop_oldest().map(|e| e.event_data.message), Some("event 36".to_string()));
+        assert_eq!(MmapStore::read_events(&path).unwrap().len(), 3);
+
+        assert!(MmapStore::open(&path, capacity * 2).is_err(), "capacity must match the existing file");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_corrupt_record_keeps_earlier_events() {
+        let path = std::env::temp_dir().join(format!("spanner_ring_{}.bin", uuid::Uuid::new_v4()));
+        let record_len = LEN_PREFIX + serde_json::to_vec(&event(10)).unwrap().len();
+
+        {
+            let mut store = MmapStore::open(&path, record_len * 4).unwrap();
+            (10..14).for_each(|index| store.push(Arc::new(event(index))));
+        }
+
+        // Garble the third record's payload, as a crash mid-write would
+        let mut bytes = std::fs::read(&path).unwrap();
+        let offset = HEADER_LEN + record_len * 2 + LEN_PREFIX;
+        bytes[offset..offset + 4].copy_from_slice(b"\xff\xff\xff\xff");
+        std::fs::write(&path, bytes).unwrap();
+
+        assert_eq!(messages(&MmapStore::read_events(&path).unwrap()), ["event 10", "event 11"]);
+
+        // Reopening drops the corrupt tail and keeps appending after the surviving events
+        let mut store = MmapStore::open(&path, record_len * 4).unwrap();
+        assert_eq!(store.len(), 2);
+        store.push(Arc::new(event(14)));
+        assert_eq!(messages(&MmapStore::read_events(&path).unwrap()), ["event 10", "event 11", "event 14"]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,526 @@
+use {
+    crate::{error::SpannerError, event::EventKind},
+    chrono::{DateTime, TimeDelta, Utc},
+    std::time::Duration,
+    tracing::Level,
+};
+
+/// How [`EventManager::search_with_mode`](crate::EventManager::search_with_mode) interprets
+/// its target and message filters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+    /// The filter must appear verbatim, as with [`EventManager::search`](crate::EventManager::search)
+    #[default]
+    Substring,
+    /// The filter is a regular expression that must match somewhere, e.g. `^myapp::db`.
+    /// Needs the `regex` feature.
+    Regex,
+}
+
+/// A compiled regular expression, for [`SearchQuery::target_regex`] and
+/// [`SearchQuery::message_regex`]. Can only be created with the `regex` feature.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    #[cfg(feature = "regex")]
+    regex: regex::Regex,
+    #[cfg(not(feature = "regex"))]
+    unconstructible: std::convert::Infallible,
+}
+
+impl Pattern {
+    /// Compile `pattern`, failing with [`SpannerError::InvalidRegex`] if it isn't valid, or
+    /// with [`SpannerError::Unsupported`] without the `regex` feature
+    pub fn new(pattern: &str) -> Result<Self, SpannerError> {
+        #[cfg(feature = "regex")]
+        return Ok(Self { regex: regex::Regex::new(pattern)? });
+        #[cfg(not(feature = "regex"))]
+        {
+            let _ = pattern;
+            Err(SpannerError::Unsupported("regex"))
+        }
+    }
+
+    /// Whether the pattern matches somewhere in `text`
+    pub fn is_match(&self, text: &str) -> bool {
+        #[cfg(feature = "regex")]
+        return self.regex.is_match(text);
+        #[cfg(not(feature = "regex"))]
+        {
+            let _ = text;
+            match self.unconstructible {}
+        }
+    }
+}
+
+/// Comparison applied to a single event field
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldOp {
+    /// The field is present, whatever its value
+    Exists,
+    /// The field value equals the string (surrounding quotes from `Debug` capture are ignored)
+    Equals(String),
+    /// The field value contains the string
+    Contains(String),
+    /// The field parses as a number greater than the operand
+    GreaterThan(f64),
+    /// The field parses as a number less than the operand
+    LessThan(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldFilter {
+    pub key: String,
+    pub op: FieldOp,
+}
+
+impl FieldFilter {
+    /// Check a captured field value (if any) against this filter
+    pub fn matches(&self, value: Option<&str>) -> bool {
+        let Some(value) = value else { return false };
+        let unquoted = value.trim_matches('"');
+        match &self.op {
+            FieldOp::Exists => true,
+            FieldOp::Equals(expected) => unquoted == expected,
+            FieldOp::Contains(needle) => unquoted.contains(needle.as_str()),
+            FieldOp::GreaterThan(bound) => parse_number(value).is_some_and(|v| v > *bound),
+            FieldOp::LessThan(bound) => parse_number(value).is_some_and(|v| v < *bound),
+        }
+    }
+}
+
+/// The time `duration` ago, or the earliest representable time if that is out of range
+pub(crate) fn cutoff(duration: Duration) -> DateTime<Utc> {
+    let cutoff = TimeDelta::from_std(duration).ok().and_then(|duration| Utc::now().checked_sub_signed(duration));
+    cutoff.unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+/// Parse a captured field value as a number, ignoring surrounding whitespace and the quotes
+/// `Debug` capture adds to strings. Accepts anything `f64::from_str` does, e.g. `1500`, `-2.5`, `1e3`.
+pub(crate) fn parse_number(value: &str) -> Option<f64> { value.trim().trim_matches('"').trim().parse().ok() }
+
+/// Composable event filter. All set criteria must match (logical AND), and no exclusion may
+/// match, so an event matching both an inclusion and an exclusion is filtered out.
+///
+/// Used by [`Event::matches`](crate::Event::matches) and
+/// [`EventManager::query`](crate::EventManager::query).
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    /// Exact level match
+    pub level: Option<Level>,
+    /// Minimum severity, e.g. `WARN` matches `WARN` and `ERROR`
+    pub min_level: Option<Level>,
+    /// Target substring
+    pub target: Option<String>,
+    /// Message substring
+    pub message: Option<String>,
+    /// Substring of any span name in the event's span stack
+    pub span_name: Option<String>,
+    /// Event field comparisons
+    pub fields: Vec<FieldFilter>,
+    /// Span field comparisons, each satisfied by any span in the event's span stack
+    pub span_fields: Vec<FieldFilter>,
+    /// Inclusive lower bound on the event timestamp
+    pub after: Option<DateTime<Utc>>,
+    /// Inclusive upper bound on the event timestamp
+    pub before: Option<DateTime<Utc>>,
+    /// Exact event kind match
+    pub kind: Option<EventKind>,
+    /// Exact `custom_metadata` key/value matches
+    pub metadata: Vec<(String, String)>,
+    /// Exact session label matches, see [`Event::effective_labels`](crate::Event::effective_labels)
+    pub labels: Vec<(String, String)>,
+    /// Target substrings that exclude an event
+    pub exclude_targets: Vec<String>,
+    /// Levels that exclude an event
+    pub exclude_levels: Vec<Level>,
+    /// Message substrings that exclude an event
+    pub exclude_messages: Vec<String>,
+    /// Approximate message match, see [`SearchQuery::message_fuzzy`]
+    pub message_fuzzy: Option<FuzzyMatch>,
+    /// Target pattern, see [`SearchQuery::target_regex`]
+    pub target_regex: Option<Pattern>,
+    /// Message pattern, see [`SearchQuery::message_regex`]
+    pub message_regex: Option<Pattern>,
+}
+
+/// Approximate message criterion for interactive search. Scoring needs the `fuzzy` feature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyMatch {
+    pub query: String,
+    pub min_score: f64,
+}
+
+impl FuzzyMatch {
+    /// Similarity between the query and a message, from `0.0` (unrelated) to `1.0` (exact).
+    ///
+    /// Both sides are lowercased and have whitespace collapsed. The query is then compared,
+    /// using normalized Damerau-Levenshtein similarity, against the whole message and against
+    /// every run of consecutive message words as long as the query, keeping the best score.
+    /// Short queries therefore score highly against long messages that contain a close match.
+    ///
+    /// Fails with [`SpannerError::Unsupported`] without the `fuzzy` feature.
+    pub fn score(&self, message: &str) -> Result<f64, SpannerError> {
+        #[cfg(feature = "fuzzy")]
+        return Ok(similarity(&self.query, message));
+        #[cfg(not(feature = "fuzzy"))]
+        {
+            let _ = message;
+            Err(SpannerError::Unsupported("fuzzy"))
+        }
+    }
+}
+
+#[cfg(feature = "fuzzy")]
+fn similarity(query: &str, message: &str) -> f64 {
+    let query = normalize(query);
+    let message = normalize(message);
+    if query.is_empty() {
+        return 1.0;
+    }
+
+    let words: Vec<&str> = message.split(' ').collect();
+    let window = query.split(' ').count().min(words.len()).max(1);
+    words
+        .windows(window)
+        .map(|run| strsim::normalized_damerau_levenshtein(&query, &run.join(" ")))
+        .fold(strsim::normalized_damerau_levenshtein(&query, &message), f64::max)
+}
+
+#[cfg(feature = "fuzzy")]
+fn normalize(text: &str) -> String { text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase() }
+
+impl SearchQuery {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    pub fn kind(mut self, kind: EventKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn min_level(mut self, level: Level) -> Self {
+        self.min_level = Some(level);
+        self
+    }
+
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    pub fn message_contains(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    pub fn span_name(mut self, span_name: impl Into<String>) -> Self {
+        self.span_name = Some(span_name.into());
+        self
+    }
+
+    pub fn field(mut self, key: impl Into<String>, op: FieldOp) -> Self {
+        self.fields.push(FieldFilter { key: key.into(), op });
+        self
+    }
+
+    /// Require a span in the event's stack whose field satisfies `op`, e.g.
+    /// `span_field("rows", FieldOp::GreaterThan(1000.0))`.
+    ///
+    /// Each span field filter may be satisfied by a different span. Spans without the field
+    /// never match, and numeric comparisons also fail for values that don't parse as numbers
+    /// (see [`SpanInfo::field_as_f64`](crate::SpanInfo::field_as_f64)).
+    pub fn span_field(mut self, key: impl Into<String>, op: FieldOp) -> Self {
+        self.span_fields.push(FieldFilter { key: key.into(), op });
+        self
+    }
+
+    pub fn after(mut self, time: DateTime<Utc>) -> Self {
+        self.after = Some(time);
+        self
+    }
+
+    pub fn before(mut self, time: DateTime<Utc>) -> Self {
+        self.before = Some(time);
+        self
+    }
+
+    /// Only match events from the last `duration`, measured from when this is called
+    pub fn within_last(self, duration: Duration) -> Self { self.after(cutoff(duration)) }
+
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.push((key.into(), value.into()));
+        self
+    }
+
+    /// Only match events whose session label `key` is `value`, e.g. to pick one dump's
+    /// events out of several merged ones
+    pub fn label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.push((key.into(), value.into()));
+        self
+    }
+
+    /// Exclude events whose target contains `target`
+    pub fn exclude_target(mut self, target: impl Into<String>) -> Self {
+        self.exclude_targets.push(target.into());
+        self
+    }
+
+    /// Exclude events at exactly `level`
+    pub fn exclude_level(mut self, level: Level) -> Self {
+        self.exclude_levels.push(level);
+        self
+    }
+
+    /// Exclude events whose message contains `message`
+    pub fn exclude_message_contains(mut self, message: impl Into<String>) -> Self {
+        self.exclude_messages.push(message.into());
+        self
+    }
+
+    /// Match messages approximately, tolerating typos, case and whitespace differences.
+    ///
+    /// Events score between `0.0` and `1.0` (see [`FuzzyMatch::score`]) and only those
+    /// scoring at least `min_score` match; [`EventManager::query`](crate::EventManager::query)
+    /// returns them best match first. Around `0.8` suits typo tolerance, lower values
+    /// behave more like "sounds similar". Without the `fuzzy` feature scoring fails, see
+    /// [`FuzzyMatch::score`], so the criterion matches no events.
+    pub fn message_fuzzy(mut self, query: impl Into<String>, min_score: f64) -> Self {
+        self.message_fuzzy = Some(FuzzyMatch { query: query.into(), min_score });
+        self
+    }
+
+    /// Only match events whose target matches the regular expression `pattern` somewhere,
+    /// e.g. `^myapp::db` for a target prefix. Fails if `pattern` doesn't compile, or without
+    /// the `regex` feature, see [`Pattern::new`].
+    pub fn target_regex(mut self, pattern: &str) -> Result<Self, SpannerError> {
+        self.target_regex = Some(Pattern::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Only match events whose message matches the regular expression `pattern` somewhere,
+    /// e.g. `user_id=\d+`. Fails if `pattern` doesn't compile, or without the `regex`
+    /// feature, see [`Pattern::new`].
+    pub fn message_regex(mut self, pattern: &str) -> Result<Self, SpannerError> {
+        self.message_regex = Some(Pattern::new(pattern)?);
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{Event, EventData, SpanInfo},
+        chrono::Duration,
+    };
+
+    fn event(message: &str, level: Level, target: &str) -> Event {
+        Event::new(EventData::new(message.to_string(), level, target.to_string()))
+    }
+
+    #[test]
+    fn test_query_level() {
+        let warn = event("m", Level::WARN, "t");
+        assert!(warn.matches(&SearchQuery::new().level(Level::WARN)));
+        assert!(!warn.matches(&SearchQuery::new().level(Level::ERROR)));
+    }
+
+    #[test]
+    fn test_query_min_level() {
+        let query = SearchQuery::new().min_level(Level::WARN);
+        assert!(event("m", Level::ERROR, "t").matches(&query));
+        assert!(event("m", Level::WARN, "t").matches(&query));
+        assert!(!event("m", Level::INFO, "t").matches(&query));
+        assert!(!event("m", Level::TRACE, "t").matches(&query));
+    }
+
+    #[test]
+    fn test_query_target_and_message() {
+        let e = event("connection refused", Level::INFO, "app::db");
+        assert!(e.matches(&SearchQuery::new().target("db")));
+        assert!(!e.matches(&SearchQuery::new().target("http")));
+        assert!(e.matches(&SearchQuery::new().message_contains("refused")));
+        assert!(!e.matches(&SearchQuery::new().message_contains("accepted")));
+    }
+
+    #[test]
+    fn test_query_exclusions() {
+        let events = [
+            event("poll", Level::TRACE, "hyper::proto"),
+            event("request done", Level::INFO, "hyper::server"),
+            event("cache miss", Level::DEBUG, "app::cache"),
+            event("healthcheck ok", Level::INFO, "app::http"),
+            event("order placed", Level::INFO, "app::orders"),
+        ];
+        let matching = |query: &SearchQuery| {
+            events.iter().filter(|e| e.matches(query)).map(|e| e.event_data.message.as_str()).collect::<Vec<_>>()
+        };
+
+        let quiet =
+            SearchQuery::new().exclude_level(Level::TRACE).exclude_target("hyper").exclude_message_contains("healthcheck");
+        assert_eq!(matching(&quiet), ["cache miss", "order placed"]);
+        // Inclusions and exclusions combine, and an exclusion wins over a matching inclusion
+        assert_eq!(matching(&quiet.clone().min_level(Level::INFO)), ["order placed"]);
+        assert!(matching(&SearchQuery::new().target("hyper").exclude_target("hyper")).is_empty());
+        assert!(matching(&SearchQuery::new().level(Level::TRACE).exclude_level(Level::TRACE)).is_empty());
+    }
+
+    #[test]
+    fn test_query_span_name() {
+        let e = event("m", Level::INFO, "t")
+            .with_span_stack(vec![SpanInfo::new(1, "handle_request".to_string(), "t".to_string(), Level::INFO)])
+            .with_current_span(SpanInfo::new(2, "db_query".to_string(), "t".to_string(), Level::DEBUG));
+        assert!(e.matches(&SearchQuery::new().span_name("handle")));
+        assert!(e.matches(&SearchQuery::new().span_name("db_query")));
+        assert!(!e.matches(&SearchQuery::new().span_name("render")));
+        assert!(!event("m", Level::INFO, "t").matches(&SearchQuery::new().span_name("handle")));
+    }
+
+    #[test]
+    fn test_query_fields() {
+        let mut e = event("m", Level::INFO, "t");
+        e.event_data.add_field("rows".to_string(), "1500".to_string());
+        e.event_data.add_field("table".to_string(), "\"users\"".to_string());
+
+        assert!(e.matches(&SearchQuery::new().field("rows", FieldOp::Exists)));
+        assert!(!e.matches(&SearchQuery::new().field("cols", FieldOp::Exists)));
+        assert!(e.matches(&SearchQuery::new().field("table", FieldOp::Equals("users".to_string()))));
+        assert!(e.matches(&SearchQuery::new().field("table", FieldOp::Contains("use".to_string()))));
+        assert!(e.matches(&SearchQuery::new().field("rows", FieldOp::GreaterThan(1000.0))));
+        assert!(!e.matches(&SearchQuery::new().field("rows", FieldOp::LessThan(1000.0))));
+        assert!(!e.matches(&SearchQuery::new().field("table", FieldOp::GreaterThan(0.0))));
+    }
+
+    #[test]
+    fn test_query_span_fields() {
+        let mut request = SpanInfo::new(1, "request".to_string(), "t".to_string(), Level::INFO);
+        request.add_field("route".to_string(), "\"/users\"".to_string());
+        let mut query = SpanInfo::new(2, "query".to_string(), "t".to_string(), Level::DEBUG);
+        query.add_field("rows".to_string(), "1500".to_string());
+        query.add_field("table".to_string(), "\"users\"".to_string());
+        assert_eq!(query.field_as_f64("rows"), Some(1500.0));
+        assert_eq!(query.field_as_f64("table"), None);
+        assert_eq!(query.field_as_f64("missing"), None);
+
+        let e = event("m", Level::INFO, "t").with_span_stack(vec![request, query.clone()]).with_current_span(query);
+        assert!(e.matches(&SearchQuery::new().span_field("rows", FieldOp::GreaterThan(1000.0))));
+        assert!(!e.matches(&SearchQuery::new().span_field("rows", FieldOp::LessThan(1000.0))));
+        assert!(!e.matches(&SearchQuery::new().span_field("table", FieldOp::GreaterThan(0.0))));
+        assert!(!e.matches(&SearchQuery::new().span_field("missing", FieldOp::LessThan(0.0))));
+        assert!(
+            e.matches(
+                &SearchQuery::new()
+                    .span_field("route", FieldOp::Equals("/users".to_string()))
+                    .span_field("rows", FieldOp::Exists)
+            ),
+            "filters may match different spans"
+        );
+        assert!(!event("m", Level::INFO, "t").matches(&SearchQuery::new().span_field("rows", FieldOp::Exists)));
+    }
+
+    #[test]
+    fn test_query_time_range() {
+        let mut e = event("m", Level::INFO, "t");
+        let now = Utc::now();
+        e.event_data.timestamp = now;
+
+        assert!(e.matches(&SearchQuery::new().after(now).before(now)));
+        assert!(e.matches(&SearchQuery::new().after(now - Duration::seconds(5))));
+        assert!(!e.matches(&SearchQuery::new().after(now + Duration::seconds(5))));
+        assert!(!e.matches(&SearchQuery::new().before(now - Duration::seconds(5))));
+    }
+
+    #[test]
+    fn test_query_metadata() {
+        let mut e = event("m", Level::INFO, "t");
+        e.add_metadata("region".to_string(), "eu".to_string());
+        assert!(e.matches(&SearchQuery::new().metadata("region", "eu")));
+        assert!(!e.matches(&SearchQuery::new().metadata("region", "us")));
+        assert!(!e.matches(&SearchQuery::new().metadata("zone", "eu")));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_query_regex() {
+        use crate::{EventManager, MatchMode, SpannerError};
+
+        let mut manager = EventManager::new(None);
+        manager.push(event("login user_id=42", Level::INFO, "myapp::db::pool"));
+        manager.push(event("login user_id=guest", Level::INFO, "myapp::db"));
+        manager.push(event("login user_id=7", Level::WARN, "other::myapp::db"));
+
+        let messages = |events: Vec<&Event>| events.iter().map(|e| e.event_data.message.clone()).collect::<Vec<_>>();
+        let found = manager.search_with_mode(MatchMode::Regex, None, Some("^myapp::db"), Some(r"user_id=\d+"), None);
+        assert_eq!(messages(found.unwrap()), ["login user_id=42"]);
+        // Substring mode keeps the plain `search` behaviour, so the pattern is taken literally
+        let literal = manager.search_with_mode(MatchMode::default(), None, Some("^myapp"), None, None).unwrap();
+        assert!(literal.is_empty());
+        assert!(matches!(
+            manager.search_with_mode(MatchMode::Regex, None, None, Some("user_id=("), None),
+            Err(SpannerError::InvalidRegex(_))
+        ));
+
+        let query = SearchQuery::new().target_regex("db$").unwrap().message_regex(r"=\d").unwrap();
+        assert_eq!(messages(manager.query(&query)), ["login user_id=7"]);
+        assert!(SearchQuery::new().message_regex("[").is_err());
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[test]
+    fn test_query_regex_needs_feature() {
+        use crate::{EventManager, MatchMode};
+
+        assert!(matches!(SearchQuery::new().target_regex("db$"), Err(SpannerError::Unsupported("regex"))));
+        let manager = EventManager::new(None);
+        let found = manager.search_with_mode(MatchMode::Regex, None, Some("^myapp"), None, None);
+        assert!(matches!(found, Err(SpannerError::Unsupported("regex"))));
+        assert!(manager.search_with_mode(MatchMode::Substring, None, Some("^myapp"), None, None).unwrap().is_empty());
+    }
+
+    #[cfg(feature = "fuzzy")]
+    #[test]
+    fn test_query_message_fuzzy() {
+        use crate::EventManager;
+
+        let mut manager = EventManager::new(None);
+        for message in ["Connection  REFUSED by upstream", "connection refused", "disk full", "conection refusd"] {
+            manager.push(event(message, Level::INFO, "t"));
+        }
+
+        let matches: Vec<_> = manager
+            .query(&SearchQuery::new().message_fuzzy("connection refused", 0.8))
+            .iter()
+            .map(|e| e.event_data.message.as_str())
+            .collect();
+
+        assert_eq!(matches.len(), 3);
+        assert!(!matches.contains(&"disk full"));
+        assert_eq!(matches.last(), Some(&"conection refusd"), "the misspelt message should rank last");
+    }
+
+    #[cfg(not(feature = "fuzzy"))]
+    #[test]
+    fn test_query_message_fuzzy_needs_feature() {
+        use crate::EventManager;
+
+        let mut manager = EventManager::new(None);
+        manager.push(event("connection refused", Level::INFO, "t"));
+
+        let query = SearchQuery::new().message_fuzzy("connection refused", 0.8);
+        let fuzzy = query.message_fuzzy.as_ref().unwrap();
+        assert!(matches!(fuzzy.score("connection refused"), Err(SpannerError::Unsupported("fuzzy"))));
+        assert!(manager.query(&query).is_empty());
+    }
+
+    #[test]
+    fn test_matches_criteria_delegates() {
+        let e = event("disk full", Level::ERROR, "app::fs");
+        assert!(e.matches_criteria(Some(Level::ERROR), Some("fs"), Some("disk"), None));
+        assert!(!e.matches_criteria(Some(Level::WARN), None, None, None));
+        assert!(!e.matches_criteria(None, None, None, Some("span")));
+    }
+}
@@ -0,0 +1,196 @@
+use {
+    crate::{
+        event::Event,
+        events::EventTarget,
+        manager::{EventManager, import_from_bin_file},
+        query::SearchQuery,
+        store::EventStore,
+    },
+    chrono::{DateTime, SecondsFormat, Utc},
+    std::{io, path::Path, thread, time::Duration},
+    tracing::Level,
+};
+
+/// Target of the `tracing` events emitted by [`Recording::reemit_to_tracing`].
+/// [`SpannerLayer`](crate::SpannerLayer) ignores it, so replays aren't captured a second time.
+pub const REPLAY_TARGET: &str = "spanner::replay";
+
+/// Captured events that can be replayed into an [`EventTarget`] with their original pacing
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    /// Events ordered oldest first
+    pub events: Vec<Event>,
+}
+
+impl Recording {
+    /// Build a recording from events in any order
+    pub fn from_events(mut events: Vec<Event>) -> Self {
+        events.sort_by_key(|event| event.event_data.order_key());
+        Self { events }
+    }
+
+    /// Snapshot the events buffered in a manager
+    pub fn from_manager<S: EventStore>(manager: &EventManager<S>) -> Self {
+        Self::from_events(manager.iter().cloned().collect())
+    }
+
+    /// Load a recording from an export file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> { Ok(Self::from_manager(&import_from_bin_file(path)?)) }
+
+    /// Replay every event into `target`, blocking the calling thread.
+    ///
+    /// Gaps between events are their original timestamp differences divided by `speed`, so
+    /// `2.0` replays twice as fast. A non-finite or non-positive speed replays without delays.
+    pub fn play(&self, target: &EventTarget<Event>, speed: f64) { self.play_filtered(target, speed, &SearchQuery::new()) }
+
+    /// Replay only the events matching `query`, keeping the original time between them
+    pub fn play_filtered(&self, target: &EventTarget<Event>, speed: f64, query: &SearchQuery) {
+        self.paced(speed, query, |event| target.emit(event.clone()))
+    }
+
+    /// Re-emit every event as a `tracing` event through the current dispatcher, paced like
+    /// [`Recording::play`], so it is rendered by whatever fmt or JSON layers are installed.
+    ///
+    /// `tracing` callsites are static, so events can't be recreated exactly. Each one has the
+    /// original level and message, but its target is [`REPLAY_TARGET`] and it carries the
+    /// rest as field values: `replay.target`, `replay.timestamp` (RFC 3339) and `replay.fields`
+    /// (the original fields as sorted `key=value` pairs). The source file, line, module path and
+    /// spans are lost, and layers see the time of the replay rather than of the original event.
+    pub fn reemit_to_tracing(&self, speed: f64) { self.paced(speed, &SearchQuery::new(), reemit) }
+
+    /// Hand each event matching `query` to `f`, keeping the original time between them
+    fn paced(&self, speed: f64, query: &SearchQuery, mut f: impl FnMut(&Event)) {
+        let mut previous: Option<DateTime<Utc>> = None;
+        for event in self.events.iter().filter(|event| event.matches(query)) {
+            let timestamp = event.event_data.timestamp;
+            if let Some(previous) = previous
+                && speed.is_finite()
+                && speed > 0.0
+            {
+                let gap = (timestamp - previous).to_std().unwrap_or_default();
+                thread::sleep(Duration::from_secs_f64(gap.as_secs_f64() / speed));
+            }
+            previous = Some(timestamp);
+            f(event);
+        }
+    }
+}
+
+fn reemit(event: &Event) {
+    let data = &event.event_data;
+    let mut fields: Vec<_> = data.fields.iter().collect();
+    fields.sort();
+    let fields = fields.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join(" ");
+    let timestamp = data.timestamp.to_rfc3339_opts(SecondsFormat::Micros, true);
+
+    macro_rules! reemit {
+        ($level:expr) => {
+            tracing::event!(
+                target: REPLAY_TARGET,
+                $level,
+                replay.target = %data.target,
+                replay.timestamp = %timestamp,
+                replay.fields = %fields,
+                "{}",
+                data.message
+            )
+        };
+    }
+    match data.level() {
+        Level::ERROR => reemit!(Level::ERROR),
+        Level::WARN => reemit!(Level::WARN),
+        Level::INFO => reemit!(Level::INFO),
+        Level::DEBUG => reemit!(Level::DEBUG),
+        Level::TRACE => reemit!(Level::TRACE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::EventData,
+        chrono::TimeDelta,
+        std::{
+            sync::{Arc, Mutex},
+            time::Instant,
+        },
+        tracing::{
+            Subscriber,
+            field::{Field, Visit},
+        },
+        tracing_subscriber::{Layer, Registry, layer::SubscriberExt},
+    };
+
+    #[test]
+    fn test_play_filtered_keeps_matching_events_in_order() {
+        let start = Utc::now();
+        let events = [
+            ("boot", Level::TRACE, 0),
+            ("disk slow", Level::WARN, 100),
+            ("noise", Level::TRACE, 150),
+            ("disk full", Level::ERROR, 400),
+        ]
+        .map(|(message, level, offset_ms)| {
+            let mut data = EventData::new(message.to_string(), level, "replay".to_string());
+            data.timestamp = start + TimeDelta::milliseconds(offset_ms);
+            Event::new(data)
+        });
+        let recording = Recording::from_events(events.into_iter().rev().collect());
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let target = EventTarget::new();
+        let sink = received.clone();
+        target.subscribe_persistent(move |event: Arc<Event>| sink.lock().unwrap().push(event.event_data.message.clone()));
+
+        let started = Instant::now();
+        recording.play_filtered(&target, 10.0, &SearchQuery::new().min_level(Level::WARN));
+
+        assert_eq!(*received.lock().unwrap(), ["disk slow", "disk full"]);
+        // 300ms between the two matching events at 10x speed
+        assert!(started.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_reemit_to_tracing() {
+        type Collected = (Level, String, Vec<(String, String)>);
+
+        #[derive(Clone, Default)]
+        struct Collect(Arc<Mutex<Vec<Collected>>>);
+
+        struct Fields<'a>(&'a mut Vec<(String, String)>);
+
+        impl Visit for Fields<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                self.0.push((field.name().to_string(), format!("{:?}", value)));
+            }
+        }
+
+        impl<S: Subscriber> Layer<S> for Collect {
+            fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+                let mut fields = Vec::new();
+                event.record(&mut Fields(&mut fields));
+                let metadata = event.metadata();
+                self.0.lock().unwrap().push((*metadata.level(), metadata.target().to_string(), fields));
+            }
+        }
+
+        let mut data = EventData::new("disk full".to_string(), Level::ERROR, "app::storage".to_string());
+        data.add_field("free".to_string(), "0".to_string());
+        data.add_field("disk".to_string(), "\"sda\"".to_string());
+        let recording = Recording::from_events(vec![Event::new(data)]);
+
+        let collect = Collect::default();
+        tracing::subscriber::with_default(Registry::default().with(collect.clone()), || recording.reemit_to_tracing(0.0));
+
+        let events = collect.0.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        let (level, target, fields) = &events[0];
+        assert_eq!((*level, target.as_str()), (Level::ERROR, REPLAY_TARGET));
+        let field = |name: &str| fields.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+        assert_eq!(field("message"), Some("disk full"));
+        assert_eq!(field("replay.target"), Some("app::storage"));
+        assert_eq!(field("replay.fields"), Some("disk=\"sda\" free=0"));
+        assert!(field("replay.timestamp").is_some());
+    }
+}
@@ -1,10 +1,14 @@
-use std::{
-    collections::HashMap,
-    time::Duration,
+use {
+    crate::intern::SharedStr,
+    chrono::{DateTime, Utc},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    },
+    tracing::Level,
 };
-use tracing::Level;
-use serde::{Serialize, Deserialize};
-use chrono::{DateTime, Utc};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct SerializableLevel(pub String);
@@ -39,41 +43,125 @@ impl From<SerializableLevel> for Level {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpanInfo {
+    /// Capture-local id, unique within the process even when `tracing` reuses span ids, so
+    /// it identifies the span in queries and traces
     pub id: u64,
-    pub name: String,
-    pub target: String,
+    /// The id `tracing` gave the span, which the subscriber may reuse once the span closes
+    #[serde(default)]
+    pub tracing_id: u64,
+    pub(crate) name: SharedStr,
+    pub(crate) target: SharedStr,
     pub level: SerializableLevel,
     pub file: Option<String>,
     pub line: Option<u32>,
-    pub module_path: Option<String>,
+    pub(crate) module_path: Option<SharedStr>,
+    #[serde(serialize_with = "crate::event::sorted_map")]
     pub fields: HashMap<String, String>,
+    /// Display name override taken from an `otel.name` or `name` field
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(deserialize_with = "tolerant_time::deserialize")]
     pub entered_at: DateTime<Utc>,
+    #[serde(default, deserialize_with = "tolerant_time::deserialize_option")]
     pub exited_at: Option<DateTime<Utc>>,
     pub duration: Option<Duration>,
+    /// Number of events fired while this was the innermost entered span
+    #[serde(default)]
+    pub event_count: usize,
+    /// Total time spent entered, summed over every enter/exit
+    #[serde(default)]
+    pub busy_time: Duration,
+    /// Times the span was entered again after its first entry, e.g. async task resumes
+    #[serde(default)]
+    pub resume_count: usize,
     pub children: Vec<SpanInfo>,
 }
 
+/// Next unique span id, see [`SpanInfo::id`]
+pub(crate) fn next_span_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
 impl SpanInfo {
+    /// Describe a span, with `id` also used as its [`tracing_id`](SpanInfo::tracing_id)
     pub fn new(id: u64, name: String, target: String, level: Level) -> Self {
         Self {
             id,
-            name,
-            target,
+            tracing_id: id,
+            name: name.into(),
+            target: target.into(),
             level: level.into(),
             file: None,
             line: None,
             module_path: None,
             fields: HashMap::new(),
+            display_name: None,
             entered_at: Utc::now(),
             exited_at: None,
             duration: None,
+            event_count: 0,
+            busy_time: Duration::ZERO,
+            resume_count: 0,
             children: Vec::new(),
         }
     }
 
     pub fn level(&self) -> Level { self.level.clone().into() }
 
-    pub fn add_field(&mut self, key: String, value: String) { self.fields.insert(key, value); }
+    /// Static span name from the callsite metadata, see [`SpanInfo::display_name`]
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn target(&self) -> &str { &self.target }
+
+    pub fn module_path(&self) -> Option<&str> { self.module_path.as_deref() }
+
+    /// Set the module path the span was created in
+    pub fn with_module_path(mut self, module_path: String) -> Self {
+        self.module_path = Some(module_path.into());
+        self
+    }
+
+    /// Name to show for this span.
+    ///
+    /// Precedence: an `otel.name` field, then a `name` field, then the static span name
+    /// from the callsite metadata (always kept in `name`).
+    pub fn display_name(&self) -> &str { self.display_name.as_deref().unwrap_or(&self.name) }
+
+    /// Name of the async task this span represents, if it names one.
+    ///
+    /// Taken from a `task.name` field, as on the task spans `tokio` emits for named tasks
+    /// (`tokio::task::Builder::name`), or from the `name` field of a `runtime.spawn` span.
+    pub fn task_name(&self) -> Option<&str> {
+        match self.fields.get("task.name") {
+            Some(name) => Some(name.trim_matches('"')),
+            None => (self.name == "runtime.spawn").then_some(self.display_name.as_deref()).flatten(),
+        }
+    }
+
+    /// Check whether either the metadata name or the display name contains `needle`
+    pub fn name_contains(&self, needle: &str) -> bool {
+        self.name.contains(needle) || self.display_name.as_deref().is_some_and(|name| name.contains(needle))
+    }
+
+    /// Parse a field as a number.
+    ///
+    /// Surrounding whitespace and the quotes `Debug` capture adds to strings are ignored, so
+    /// `1500`, `"1500"` and `2.5e3` all parse. Returns `None` if the field is missing or isn't
+    /// numeric.
+    pub fn field_as_f64(&self, key: &str) -> Option<f64> { self.fields.get(key).and_then(|v| crate::query::parse_number(v)) }
+
+    pub fn add_field(&mut self, key: String, value: String) {
+        let renames = key == "otel.name" || key == "name";
+        self.fields.insert(key, value);
+        if renames {
+            self.display_name = self
+                .fields
+                .get("otel.name")
+                .or_else(|| self.fields.get("name"))
+                .map(|name| name.trim_matches('"').to_string());
+        }
+    }
 
     pub fn add_child(&mut self, child: SpanInfo) { self.children.push(child); }
 
@@ -85,9 +173,120 @@ impl SpanInfo {
         }
     }
 
+    /// Approximate memory footprint including children, see [`Event::estimated_size`](crate::Event::estimated_size)
+    pub fn estimated_size(&self) -> usize {
+        let optional = |value: Option<&str>| value.map_or(0, str::len);
+        std::mem::size_of::<Self>()
+            + self.name.len()
+            + self.target.len()
+            + self.level.0.len()
+            + optional(self.file.as_deref())
+            + optional(self.module_path.as_deref())
+            + optional(self.display_name.as_deref())
+            + self.fields.iter().map(|(k, v)| k.len() + v.len() + 2 * std::mem::size_of::<String>()).sum::<usize>()
+            + self.children.iter().map(SpanInfo::estimated_size).sum::<usize>()
+    }
+
+    /// Number of spans in this tree, including itself
+    pub fn node_count(&self) -> usize { 1 + self.children.iter().map(SpanInfo::node_count).sum::<usize>() }
+
+    /// Keep spans depth first while `budget` lasts, replacing the children that don't fit with
+    /// one `"...N more spans omitted"` marker span per parent. This span itself is always kept.
+    pub(crate) fn truncate_tree(&mut self, budget: &mut usize) {
+        *budget = budget.saturating_sub(1);
+        let mut kept = 0;
+        for child in &mut self.children {
+            if *budget == 0 {
+                break;
+            }
+            child.truncate_tree(budget);
+            kept += 1;
+        }
+
+        let omitted: usize = self.children.drain(kept..).map(|child| child.node_count()).sum();
+        if omitted > 0 {
+            let marker = format!("...{} more spans omitted", omitted);
+            self.children.push(SpanInfo::new(0, marker, self.target.to_string(), self.level.clone().into()));
+        }
+    }
+
     pub fn is_active(&self) -> bool { self.exited_at.is_none() }
 
-    pub fn get_duration(&self) -> Option<Duration> {
-        self.duration.or_else(|| (Utc::now() - self.entered_at).to_std().ok())
+    pub fn get_duration(&self) -> Option<Duration> { self.duration.or_else(|| (Utc::now() - self.entered_at).to_std().ok()) }
+}
+
+/// Span timestamps are written as RFC3339 strings (matching `EventData::timestamp`).
+/// Older dumps stored them as `SystemTime`, i.e. `{ secs_since_epoch, nanos_since_epoch }`,
+/// so human-readable formats accept either representation when reading.
+mod tolerant_time {
+    use {
+        chrono::{DateTime, Utc},
+        serde::{Deserialize, Deserializer, de::Error},
+    };
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Timestamp {
+        Rfc3339(DateTime<Utc>),
+        SystemTime { secs_since_epoch: i64, nanos_since_epoch: u32 },
+    }
+
+    impl Timestamp {
+        fn into_datetime<E: Error>(self) -> Result<DateTime<Utc>, E> {
+            match self {
+                Timestamp::Rfc3339(time) => Ok(time),
+                Timestamp::SystemTime { secs_since_epoch, nanos_since_epoch } => {
+                    DateTime::from_timestamp(secs_since_epoch, nanos_since_epoch)
+                        .ok_or_else(|| E::custom("timestamp out of range"))
+                }
+            }
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        if !deserializer.is_human_readable() {
+            return DateTime::deserialize(deserializer);
+        }
+        Timestamp::deserialize(deserializer)?.into_datetime()
+    }
+
+    pub fn deserialize_option<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+        if !deserializer.is_human_readable() {
+            return Option::deserialize(deserializer);
+        }
+        Option::<Timestamp>::deserialize(deserializer)?.map(Timestamp::into_datetime).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span_timestamps_round_trip_as_rfc3339() {
+        let mut span = SpanInfo::new(1, "request".to_string(), "test".to_string(), Level::INFO);
+        span.exit();
+
+        let json = serde_json::to_value(&span).unwrap();
+        let entered = json["entered_at"].as_str().expect("entered_at should serialize as a string");
+        let exited = json["exited_at"].as_str().expect("exited_at should serialize as a string");
+        assert_eq!(DateTime::parse_from_rfc3339(entered).unwrap(), span.entered_at);
+        assert_eq!(DateTime::parse_from_rfc3339(exited).unwrap(), span.exited_at.unwrap());
+
+        let decoded: SpanInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded.entered_at, span.entered_at);
+        assert_eq!(decoded.exited_at, span.exited_at);
+    }
+
+    #[test]
+    fn test_span_timestamps_accept_legacy_system_time() {
+        let mut json =
+            serde_json::to_value(SpanInfo::new(1, "request".to_string(), "test".to_string(), Level::INFO)).unwrap();
+        json["entered_at"] = serde_json::json!({ "secs_since_epoch": 1_700_000_000, "nanos_since_epoch": 500 });
+        json["exited_at"] = serde_json::json!({ "secs_since_epoch": 1_700_000_001, "nanos_since_epoch": 0 });
+
+        let decoded: SpanInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded.entered_at, DateTime::from_timestamp(1_700_000_000, 500).unwrap());
+        assert_eq!(decoded.exited_at, DateTime::from_timestamp(1_700_000_001, 0));
     }
 }
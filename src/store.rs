@@ -0,0 +1,357 @@
+use {
+    crate::event::Event,
+    chrono::{DateTime, Utc},
+    std::{
+        collections::{BTreeMap, HashSet, VecDeque},
+        sync::Arc,
+    },
+    tracing::Level,
+};
+
+/// Storage backend behind an [`EventManager`](crate::EventManager).
+///
+/// Stores hold events newest-first. Capacity is enforced by the manager, which calls
+/// [`EventStore::pop_oldest`] once the store grows past its limit, so implementations
+/// only need to keep events in order.
+pub trait EventStore {
+    /// Add an event as the newest entry. The manager shares the same `Arc` with its
+    /// listeners, so stores can keep it rather than copying the event.
+    fn push(&mut self, event: Arc<Event>);
+
+    /// Remove and return the oldest entry
+    fn pop_oldest(&mut self) -> Option<Event>;
+
+    /// Remove and return an event beyond a limit the store enforces itself, such as the
+    /// per-level capacities of [`InMemoryStore::per_level`]. Called by the manager after each
+    /// push until it returns `None`.
+    fn pop_over_capacity(&mut self) -> Option<Event> { None }
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool { self.len() == 0 }
+
+    fn clear(&mut self);
+
+    /// Iterate over stored events, newest first
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = &Event> + '_>;
+
+    /// Keep only the events matching the predicate
+    fn retain(&mut self, f: &mut dyn FnMut(&Event) -> bool);
+
+    /// Make room for at least `additional` more events, if the store preallocates
+    fn reserve(&mut self, _additional: usize) {}
+
+    /// Release spare capacity, if the store preallocates
+    fn shrink_to_fit(&mut self) {}
+
+    /// Get the most recent N events
+    fn get_recent(&self, count: usize) -> Vec<&Event> { self.iter().take(count).collect() }
+
+    /// Get events with timestamps between `start` and `end` inclusive, newest first.
+    ///
+    /// The default scans every event; stores that keep events sorted by time override it.
+    fn range(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<&Event> {
+        self.filter(&|event| (start..=end).contains(&event.event_data.timestamp))
+    }
+
+    /// Get the events at exactly `level`, newest first
+    fn get_by_level(&self, level: Level) -> Vec<&Event> { self.filter(&|event| event.event_data.level == level) }
+
+    /// Get all events matching the predicate, newest first
+    fn filter(&self, predicate: &dyn Fn(&Event) -> bool) -> Vec<&Event> { self.iter().filter(|e| predicate(e)).collect() }
+
+    /// Get all events matching the predicate as shared handles, newest first.
+    ///
+    /// The default clones each matching event; stores that keep events behind an `Arc`
+    /// override this to hand out their own handles instead.
+    fn filter_shared(&self, predicate: &dyn Fn(&Event) -> bool) -> Vec<Arc<Event>> {
+        self.iter().filter(|e| predicate(e)).map(|e| Arc::new(e.clone())).collect()
+    }
+}
+
+/// Default in-memory store backed by a `VecDeque`.
+///
+/// Events are kept behind an `Arc`, so shared query results such as
+/// [`EventManager::query_arc`](crate::EventManager::query_arc) don't copy them.
+///
+/// Events are kept sorted by [`EventData::order_key`](crate::EventData::order_key), so time
+/// range queries binary search their bounds instead of scanning. Events almost always arrive
+/// in order and are simply prepended; one arriving out of order (e.g. from another thread that
+/// was descheduled, or an import) is inserted in place, which costs an O(log n) search plus
+/// moving the events newer than it.
+#[derive(Debug, Default, Clone)]
+pub struct InMemoryStore {
+    inner: VecDeque<Arc<Event>>,
+    /// Per-level buffers, see [`InMemoryStore::per_level`]
+    levels: Option<BTreeMap<Level, LevelBuffer>>,
+}
+
+#[derive(Debug, Clone)]
+struct LevelBuffer {
+    capacity: usize,
+    /// The level's events, sorted like the main buffer
+    events: VecDeque<Arc<Event>>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self { Self::default() }
+
+    /// A store with a separate ring buffer per level, each holding up to its own capacity.
+    ///
+    /// A flood of `TRACE` events then only evicts older `TRACE` events, never `ERROR`s, and
+    /// [`EventManager::get_by_level`](crate::EventManager::get_by_level) reads one level's
+    /// buffer instead of filtering everything. Queries across levels still see all events in
+    /// time order. Levels missing from `capacities` aren't stored at all.
+    pub fn per_level(capacities: BTreeMap<Level, usize>) -> Self {
+        let levels =
+            capacities.into_iter().map(|(level, capacity)| (level, LevelBuffer { capacity, events: VecDeque::new() }));
+        Self { inner: VecDeque::new(), levels: Some(levels.collect()) }
+    }
+
+    /// Number of events the store can hold without reallocating
+    pub fn capacity(&self) -> usize { self.inner.capacity() }
+
+    /// Remove one event, found by its position in time, from the main buffer
+    fn remove_from_main(&mut self, event: &Arc<Event>) {
+        let key = event.event_data.order_key();
+        let from = self.inner.partition_point(|stored| stored.event_data.order_key() > key);
+        if let Some(offset) = self.inner.range(from..).position(|stored| Arc::ptr_eq(stored, event)) {
+            self.inner.remove(from + offset);
+        }
+    }
+}
+
+/// Insert into a buffer sorted newest first, prepending in the usual in-order case
+fn insert_sorted(events: &mut VecDeque<Arc<Event>>, event: Arc<Event>) {
+    let key = event.event_data.order_key();
+    match events.front() {
+        Some(newest) if newest.event_data.order_key() > key => {
+            let index = events.partition_point(|stored| stored.event_data.order_key() > key);
+            events.insert(index, event);
+        }
+        _ => events.push_front(event),
+    }
+}
+
+impl EventStore for InMemoryStore {
+    fn push(&mut self, event: Arc<Event>) {
+        if let Some(levels) = &mut self.levels {
+            // Unlisted levels get an empty buffer, so the manager evicts them like any overflow
+            let buffer =
+                levels.entry(event.event_data.level()).or_insert(LevelBuffer { capacity: 0, events: VecDeque::new() });
+            insert_sorted(&mut buffer.events, event.clone());
+        }
+        insert_sorted(&mut self.inner, event);
+    }
+
+    fn pop_oldest(&mut self) -> Option<Event> {
+        let oldest = self.inner.pop_back()?;
+        // Both are sorted the same way, so the oldest event overall is the oldest of its level
+        if let Some(buffer) = self.levels.as_mut().and_then(|levels| levels.get_mut(&oldest.event_data.level()))
+            && buffer.events.back().is_some_and(|event| Arc::ptr_eq(event, &oldest))
+        {
+            buffer.events.pop_back();
+        }
+        Some(Arc::unwrap_or_clone(oldest))
+    }
+
+    fn pop_over_capacity(&mut self) -> Option<Event> {
+        let buffer = self.levels.as_mut()?.values_mut().find(|buffer| buffer.events.len() > buffer.capacity)?;
+        let oldest = buffer.events.pop_back()?;
+        self.remove_from_main(&oldest);
+        Some(Arc::unwrap_or_clone(oldest))
+    }
+
+    fn len(&self) -> usize { self.inner.len() }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+        self.levels.iter_mut().flat_map(BTreeMap::values_mut).for_each(|buffer| buffer.events.clear());
+    }
+
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = &Event> + '_> { Box::new(self.inner.iter().map(Arc::as_ref)) }
+
+    fn retain(&mut self, f: &mut dyn FnMut(&Event) -> bool) {
+        self.inner.retain(|event| f(event));
+        if let Some(levels) = &mut self.levels {
+            // Keep exactly the events the main buffer kept, without calling `f` twice
+            let kept: HashSet<*const Event> = self.inner.iter().map(Arc::as_ptr).collect();
+            levels.values_mut().for_each(|buffer| buffer.events.retain(|event| kept.contains(&Arc::as_ptr(event))));
+        }
+    }
+
+    fn range(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<&Event> {
+        let from = self.inner.partition_point(|event| event.event_data.timestamp > end);
+        let to = self.inner.partition_point(|event| event.event_data.timestamp >= start);
+        self.inner.range(from..to.max(from)).map(Arc::as_ref).collect()
+    }
+
+    fn get_by_level(&self, level: Level) -> Vec<&Event> {
+        match &self.levels {
+            Some(levels) => {
+                levels.get(&level).map(|buffer| buffer.events.iter().map(Arc::as_ref).collect()).unwrap_or_default()
+            }
+            None => self.filter(&|event| event.event_data.level == level),
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) { self.inner.reserve(additional); }
+
+    fn shrink_to_fit(&mut self) {
+        self.inner.shrink_to_fit();
+        self.levels.iter_mut().flat_map(BTreeMap::values_mut).for_each(|buffer| buffer.events.shrink_to_fit());
+    }
+
+    fn filter_shared(&self, predicate: &dyn Fn(&Event) -> bool) -> Vec<Arc<Event>> {
+        self.inner.iter().filter(|e| predicate(e)).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{EventData, EventManager},
+        tracing::Level,
+    };
+
+    /// Minimal oldest-first backend, standing in for a user-provided store
+    #[derive(Default)]
+    struct VecStore(Vec<Event>);
+
+    impl EventStore for VecStore {
+        fn push(&mut self, event: Arc<Event>) { self.0.push(Arc::unwrap_or_clone(event)); }
+
+        fn pop_oldest(&mut self) -> Option<Event> { (!self.0.is_empty()).then(|| self.0.remove(0)) }
+
+        fn len(&self) -> usize { self.0.len() }
+
+        fn clear(&mut self) { self.0.clear(); }
+
+        fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = &Event> + '_> { Box::new(self.0.iter().rev()) }
+
+        fn retain(&mut self, f: &mut dyn FnMut(&Event) -> bool) { self.0.retain(|event| f(event)); }
+    }
+
+    fn event(message: &str, level: Level) -> Event {
+        Event::new(EventData::new(message.to_string(), level, "store".to_string()))
+    }
+
+    #[test]
+    fn test_manager_with_custom_store() {
+        let mut manager = EventManager::with_store(VecStore::default(), Some(3));
+        manager.push(event("one", Level::INFO));
+        manager.push(event("two", Level::ERROR));
+        manager.push(event("three", Level::INFO));
+        manager.push(event("four", Level::ERROR));
+
+        assert_eq!(manager.len(), 3);
+        assert_eq!(manager.store().0.len(), 3);
+
+        let recent: Vec<_> = manager.get_recent(2).iter().map(|e| e.event_data.message.as_str()).collect();
+        assert_eq!(recent, ["four", "three"]);
+
+        let errors: Vec<_> = manager.get_by_level(Level::ERROR).iter().map(|e| e.event_data.message.as_str()).collect();
+        assert_eq!(errors, ["four", "two"]);
+
+        manager.retain(|e| e.event_data.message != "three");
+        assert_eq!(manager.len(), 2);
+
+        // Stores without shared storage fall back to copying
+        assert_eq!(manager.get_by_level_arc(Level::ERROR).len(), 2);
+    }
+
+    #[test]
+    fn test_shared_results_outlive_mutation() {
+        let mut manager = EventManager::new(None);
+        manager.push(event("kept", Level::ERROR));
+        manager.push(event("other", Level::INFO));
+
+        let errors = manager.get_by_level_arc(Level::ERROR);
+        let all = manager.query_arc(&crate::SearchQuery::new());
+        assert!(Arc::ptr_eq(&errors[0], &all[1]), "handles are shared, not copied");
+
+        manager.clear();
+        manager.push(event("new", Level::ERROR));
+        assert_eq!(errors[0].event_data.message, "kept");
+        assert_eq!(all.iter().map(|e| e.event_data.message.as_str()).collect::<Vec<_>>(), ["other", "kept"]);
+    }
+
+    #[test]
+    fn test_push_reserves_capacity_up_front() {
+        let mut manager = EventManager::new(Some(100));
+        manager.push(event("first", Level::INFO));
+        let capacity = manager.store().capacity();
+        assert!(capacity > 100, "room for a full buffer after the first push");
+
+        for index in 0..1000 {
+            manager.push(event(&index.to_string(), Level::INFO));
+            assert_eq!(manager.store().capacity(), capacity, "steady-state pushes don't reallocate");
+        }
+
+        manager.clear();
+        manager.shrink_to_fit();
+        assert_eq!(manager.store().capacity(), 0);
+    }
+
+    #[test]
+    fn test_out_of_order_events_are_sorted_in() {
+        let now = Utc::now();
+        let at = |message: &str, seconds_ago: i64| {
+            let mut event = event(message, Level::INFO);
+            event.event_data.timestamp = now - chrono::TimeDelta::seconds(seconds_ago);
+            event
+        };
+
+        let mut manager = EventManager::new(None);
+        for (message, seconds_ago) in [("a", 50), ("c", 30), ("b", 40), ("e", 10), ("d", 20), ("early", 60)] {
+            manager.push(at(message, seconds_ago));
+        }
+        let messages =
+            |events: Vec<&Event>| events.iter().map(|e| e.event_data.message.as_str()).collect::<Vec<_>>().join("");
+        assert_eq!(messages(manager.iter().collect()), "edcbaearly");
+
+        let range = |from: i64, to: i64| {
+            messages(manager.get_in_range(now - chrono::TimeDelta::seconds(from), now - chrono::TimeDelta::seconds(to)))
+        };
+        assert_eq!(range(40, 20), "dcb", "bounds are inclusive");
+        assert_eq!(range(45, 35), "b");
+        assert_eq!(range(100, 55), "early");
+        assert_eq!(range(5, 0), "");
+        assert_eq!(range(20, 40), "", "an inverted range is empty");
+
+        // The linear default agrees with the binary search
+        let mut linear = EventManager::with_store(VecStore::default(), None);
+        manager.iter().rev().for_each(|event| linear.push(event.clone()));
+        assert_eq!(
+            messages(linear.get_in_range(now - chrono::TimeDelta::seconds(40), now - chrono::TimeDelta::seconds(20))),
+            "dcb"
+        );
+    }
+
+    #[test]
+    fn test_per_level_buffers() {
+        let store = InMemoryStore::per_level(BTreeMap::from([(Level::ERROR, 2), (Level::TRACE, 3)]));
+        let mut manager = EventManager::with_store(store, Some(5));
+        manager.push(event("error 1", Level::ERROR));
+        for index in 0..100 {
+            manager.push(event(&format!("trace {}", index), Level::TRACE));
+        }
+        manager.push(event("error 2", Level::ERROR));
+        manager.push(event("dropped", Level::INFO));
+
+        let messages = |events: Vec<&Event>| events.iter().map(|e| e.event_data.message.clone()).collect::<Vec<_>>();
+        assert_eq!(messages(manager.get_by_level(Level::ERROR)), ["error 2", "error 1"], "trace floods don't evict errors");
+        assert_eq!(messages(manager.get_by_level(Level::TRACE)), ["trace 99", "trace 98", "trace 97"]);
+        assert!(manager.get_by_level(Level::INFO).is_empty(), "unlisted levels aren't kept");
+        assert_eq!(messages(manager.iter().collect()), ["error 2", "trace 99", "trace 98", "trace 97", "error 1"]);
+        let expected_size: usize = manager.iter().map(Event::estimated_size).sum();
+        assert_eq!(manager.estimated_size(), expected_size);
+
+        manager.retain(|event| event.event_data.message != "trace 98");
+        assert_eq!(messages(manager.get_by_level(Level::TRACE)), ["trace 99", "trace 97"]);
+        manager.set_max_bytes(Some(1));
+        assert_eq!(messages(manager.iter().collect()), ["error 2"]);
+        assert_eq!(messages(manager.get_by_level(Level::ERROR)), ["error 2"]);
+    }
+}
@@ -1,16 +1,45 @@
 use {
     crate::{
-        event::Event,
+        context::{apply_context, current_correlation_id},
+        error::SpannerError,
+        event::{Event, EventKind},
         event_data::EventData,
-        manager::{emit, init_global_event_manager},
+        intern::SharedStr,
+        manager::{EventManager, emit, emit_into, init_global_event_manager, record_closed_span, record_closed_span_into},
+        recording::REPLAY_TARGET,
+        span::{SpanInfo, next_span_id},
+    },
+    chrono::{DateTime, Utc},
+    std::{
+        collections::{BTreeMap, HashMap, HashSet, VecDeque},
+        panic,
+        sync::{Arc, Mutex, Once, RwLock},
+        thread::{self, ThreadId},
+        time::Duration,
+    },
+    tracing::{
+        Level, Metadata, Subscriber,
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
     },
-    std::collections::HashMap,
-    tracing::Subscriber,
     tracing_subscriber::{Layer, Registry, layer::Context, prelude::*},
 };
 
+type LevelRemapper = Box<dyn Fn(&EventData) -> Option<Level> + Send + Sync>;
+
+static LEVEL_REMAPPER: RwLock<Option<LevelRemapper>> = RwLock::new(None);
+
+/// Override the level of captured events, replacing any previous remapper.
+///
+/// `f` sees each event as captured; returning `Some(level)` stores it at that level instead.
+/// It runs before the event is buffered, so queries, level filters, exports and
+/// [`EventManager::summary`](crate::EventManager::summary) level counts all see the remapped level.
+pub fn set_level_remapper(f: impl Fn(&EventData) -> Option<Level> + Send + Sync + 'static) {
+    *LEVEL_REMAPPER.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Box::new(f));
+}
+
 /// Initialize tracing with Spanner layer only (use with existing subscriber)
-pub fn init_layer_only() -> Result<(), Box<dyn std::error::Error>> {
+pub fn init_layer_only() -> Result<(), SpannerError> {
     init_global_event_manager();
     Ok(())
 }
@@ -21,110 +50,1418 @@ where
     S: Subscriber + Send + Sync + 'static,
 {
     init_global_event_manager();
-    subscriber.with(SpannerLayer)
+    subscriber.with(SpannerLayer::new())
 }
 
 /// Initialize with custom subscriber
-pub fn init_with_subscriber<S>(subscriber: S) -> Result<(), Box<dyn std::error::Error>>
+///
+/// Returns [`SpannerError::AlreadyInitialized`] if a global subscriber is already set.
+pub fn init_with_subscriber<S>(subscriber: S) -> Result<(), SpannerError>
 where
     S: Subscriber + Send + Sync + 'static,
 {
     init_global_event_manager();
-    let subscriber_with_spanner = subscriber.with(SpannerLayer);
-    tracing::subscriber::set_global_default(subscriber_with_spanner)?;
+    let subscriber_with_spanner = subscriber.with(SpannerLayer::new());
+    tracing::subscriber::set_global_default(subscriber_with_spanner).map_err(|_| SpannerError::AlreadyInitialized)?;
     tracing::info!("Spanner initialized with custom subscriber");
     Ok(())
 }
 
+/// Capture panics as `ERROR` events with the target `panic`.
+///
+/// The event holds the panic message, its source location and the panicking thread, and is
+/// buffered before any previously installed hook runs, so it makes it into dumps taken while
+/// the process goes down. Calling this more than once has no further effect.
+pub fn install_panic_capture() {
+    static INSTALLED: Once = Once::new();
+
+    INSTALLED.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let payload = info.payload();
+            let message = match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+                (Some(message), _) => message.to_string(),
+                (_, Some(message)) => message.clone(),
+                _ => "Box<dyn Any>".to_string(),
+            };
+
+            let mut event_data = EventData::new(message, Level::ERROR, "panic".to_string());
+            if let Some(location) = info.location() {
+                event_data.file = Some(location.file().to_string());
+                event_data.line = Some(location.line());
+            }
+            emit(with_thread_context(Event::new(event_data), true, true).with_kind(EventKind::Panic));
+
+            previous(info);
+        }));
+    });
+}
+
 /// Initialize the complete tracing system with event capture
 /// This sets up both the global event manager and the tracing subscriber
-pub fn init_tracing_capture() -> Result<(), Box<dyn std::error::Error>> {
+///
+/// Returns [`SpannerError::AlreadyInitialized`] if a global subscriber is already set.
+pub fn init_tracing_capture() -> Result<(), SpannerError> {
     use tracing_subscriber::prelude::*;
 
     // Initialize global event manager
     init_global_event_manager();
 
     // Set up tracing subscriber with our custom layer
-    let subscriber = Registry::default().with(SpannerLayer).with(tracing_subscriber::fmt::layer());
+    let subscriber = Registry::default().with(SpannerLayer::new()).with(tracing_subscriber::fmt::layer());
 
-    tracing::subscriber::set_global_default(subscriber)?;
+    tracing::subscriber::set_global_default(subscriber).map_err(|_| SpannerError::AlreadyInitialized)?;
     tracing::info!("Spanner tracing capture initialized");
     Ok(())
 }
 
+/// Target of the spans around the crate's own event dispatch, which are not tracked so that
+/// capturing an event doesn't record a closed span of its own
+const INTERNAL_SPAN_TARGET: &str = concat!(env!("CARGO_CRATE_NAME"), "::events");
+
 /// Custom tracing layer that captures events and spans
-pub struct SpannerLayer;
+///
+/// Span state is tracked by the layer itself instead of registry extensions, so span
+/// context is captured on top of any subscriber, not just `Registry`.
+///
+/// Spans created before the layer was installed (for example when it is added later through
+/// `tracing_subscriber::reload`) were never seen by the layer. For those, events fall back to
+/// the subscriber's `current_span`, which only provides the innermost span's name, target and
+/// level, without fields or parents. Subscribers that don't track the current span give no
+/// span context at all in that case.
+#[derive(Clone)]
+pub struct SpannerLayer {
+    spans: Arc<SpanTracker>,
+    /// Emit a WARN event when a span stays entered longer than this, see [`SpannerLayer::with_busy_threshold`]
+    pub busy_threshold: Option<Duration>,
+    /// Time each captured event, see [`SpannerLayer::with_overhead_tracking`]
+    pub track_overhead: bool,
+    /// Collect events per root span, see [`SpannerLayer::with_span_traces`]
+    pub span_traces: Option<TraceRetention>,
+    /// Store a synthesized message for events logged without one, see [`SpannerLayer::with_derived_messages`]
+    pub derive_messages: bool,
+    /// Record the enclosing async task's name, see [`SpannerLayer::with_task_names`]
+    pub capture_task_names: bool,
+    /// Record the thread id and name on events, see [`SpannerLayer::without_thread_info`]
+    pub capture_thread_info: bool,
+    /// Record the process id on events, see [`SpannerLayer::without_process_id`]
+    pub capture_process_id: bool,
+    /// Only capture events with these fields, see [`SpannerLayer::with_required_fields`]
+    pub require_fields: Vec<String>,
+    /// Whether events need all or any of `require_fields`
+    pub require_fields_match: FieldMatch,
+    /// Only capture events inside a span with this field value, see [`SpannerLayer::with_required_span_field`]
+    pub require_span_field: Option<(String, String)>,
+    /// Field taken as the event's message, see [`SpannerLayer::with_message_field`]
+    pub message_field: String,
+    /// Manager events are stored in instead of the global one, see [`SpannerLayer::with_manager`]
+    pub manager: Option<Arc<RwLock<EventManager>>>,
+}
 
-impl<S> Layer<S> for SpannerLayer
-where
-    S: Subscriber,
-{
-    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
-        let mut fields = HashMap::new();
-        let mut message = String::new();
+impl Default for SpannerLayer {
+    fn default() -> Self {
+        Self {
+            spans: Arc::default(),
+            busy_threshold: None,
+            track_overhead: false,
+            span_traces: None,
+            derive_messages: false,
+            capture_task_names: false,
+            capture_thread_info: true,
+            capture_process_id: true,
+            require_fields: Vec::new(),
+            require_fields_match: FieldMatch::All,
+            require_span_field: None,
+            message_field: "message".to_string(),
+            manager: None,
+        }
+    }
+}
 
-        // Capture event fields using a visitor
-        struct FieldVisitor<'a> {
-            fields: &'a mut HashMap<String, String>,
-            message: &'a mut String,
-        }
-
-        impl<'a> tracing::field::Visit for FieldVisitor<'a> {
-            fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
-                let value_str = format!("{:?}", value);
-                if field.name() == "message" {
-                    *self.message = value_str.trim_matches('"').to_string();
-                } else {
-                    self.fields.insert(field.name().to_string(), value_str);
-                }
-            }
+/// How an event's fields are checked against [`SpannerLayer::require_fields`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FieldMatch {
+    /// The event must have every required field
+    #[default]
+    All,
+    /// The event must have at least one of the required fields
+    Any,
+}
+
+/// What happens to a root span's trace when the span closes, see [`SpannerLayer::with_span_traces`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceRetention {
+    /// Drop the trace
+    Discard,
+    /// Keep the trace if it contains an `ERROR` event, up to the most recent 64 such traces
+    KeepOnError,
+}
+
+impl SpannerLayer {
+    pub fn new() -> Self { Self::default() }
+
+    /// Warn about spans that stay entered for longer than `threshold` in one go.
+    ///
+    /// An instrumented future enters its span for each poll, so a long continuous interval
+    /// usually means blocking work inside async code. When a span exits after being entered
+    /// for longer than the threshold, a synthetic WARN event ("span X held the executor for
+    /// Yms") is captured with the span's target, carrying `span` and `busy_ms` fields.
+    pub fn with_busy_threshold(mut self, threshold: Duration) -> Self {
+        self.busy_threshold = Some(threshold);
+        self
+    }
+
+    /// Measure how long capturing each event takes, reported by [`capture_overhead_stats`]
+    /// and, with the `metrics` feature, the `spanner_capture_seconds` histogram.
+    ///
+    /// Off by default since it reads the clock twice per event.
+    pub fn with_overhead_tracking(mut self) -> Self {
+        self.track_overhead = true;
+        self
+    }
+
+    /// Collect the events under each root span separately, retrievable with [`get_span_trace`].
+    ///
+    /// These collections are kept apart from the event buffer, so one request's events can be
+    /// dumped without sifting through everything else that happened at the same time. A trace
+    /// lives until its root span closes, then `retention` decides whether it is kept.
+    pub fn with_span_traces(mut self, retention: TraceRetention) -> Self {
+        self.span_traces = Some(retention);
+        self
+    }
+
+    /// Store [`Event::display_message`] as the message of events logged with only fields.
+    ///
+    /// Formatters already fall back to it for display; this makes the derived message part of
+    /// the captured event itself, so message queries and exports see it too.
+    pub fn with_derived_messages(mut self) -> Self {
+        self.derive_messages = true;
+        self
+    }
+
+    /// Take the event's message from the field `name` instead of `message`, e.g. for code that
+    /// logs the text as `msg = ..` and uses `message` for something else.
+    ///
+    /// A captured event's message is, in order of precedence: the value of this field (by
+    /// default `message`, where `tracing` macros put their format string); then, with
+    /// [`SpannerLayer::with_derived_messages`], [`Event::display_message`] synthesized from the
+    /// first field or the current span; otherwise empty. Any other field, including one
+    /// called `message` once a different name is set, is kept as a regular field.
+    pub fn with_message_field(mut self, name: impl Into<String>) -> Self {
+        self.message_field = name.into();
+        self
+    }
+
+    /// Store captured events and closed spans in `manager` rather than the global manager, e.g.
+    /// to give independent subsystems, or tests running in parallel, their own buffers.
+    ///
+    /// Events reach `manager`'s listeners directly, without [`set_staged_emit`](crate::set_staged_emit)
+    /// staging. Span traces from [`SpannerLayer::with_span_traces`] and panic events from
+    /// [`install_panic_capture`] stay global.
+    pub fn with_manager(mut self, manager: Arc<RwLock<EventManager>>) -> Self {
+        self.manager = Some(manager);
+        self
+    }
+
+    /// Record the name of the async task each event fires in as [`Event::task_name`].
+    ///
+    /// The innermost span naming a task wins (see [`SpanInfo::task_name`]). Events outside
+    /// any named task, which is the usual case without `tokio`'s task instrumentation, have
+    /// no task name.
+    pub fn with_task_names(mut self) -> Self {
+        self.capture_task_names = true;
+        self
+    }
+
+    /// Leave `thread_id` and `thread_name` unset on captured events, e.g. for single-threaded
+    /// programs where they are the same on every event
+    pub fn without_thread_info(mut self) -> Self {
+        self.capture_thread_info = false;
+        self
+    }
+
+    /// Leave `process_id` unset on captured events, saving space when dumps come from one process
+    pub fn without_process_id(mut self) -> Self {
+        self.capture_process_id = false;
+        self
+    }
+
+    /// Only capture events that declare the given fields, e.g. business events carrying a
+    /// `user_id` or `order_id`, ignoring purely technical logging.
+    ///
+    /// This applies on top of any level or target filtering done by the subscriber. Fields are
+    /// checked by name, so one declared but left empty still counts. An empty list captures
+    /// every event.
+    pub fn with_required_fields<I: IntoIterator<Item = T>, T: Into<String>>(
+        mut self,
+        fields: I,
+        matching: FieldMatch,
+    ) -> Self {
+        self.require_fields = fields.into_iter().map(Into::into).collect();
+        self.require_fields_match = matching;
+        self
+    }
+
+    /// Only capture events fired inside a span whose field `key` is `value`, e.g. requests
+    /// flagged with `debug = true`, so capture can be scoped to a few requests at low cost.
+    ///
+    /// The spans entered on the current thread are checked, innermost first, falling back to
+    /// the subscriber's current span. Values compare as recorded, without surrounding quotes,
+    /// so `debug = true` and `debug = "true"` both match `"true"`.
+    pub fn with_required_span_field(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.require_span_field = Some((key.into(), value.into()));
+        self
+    }
+
+    /// Whether an event fired inside a span satisfying [`SpannerLayer::require_span_field`]
+    fn in_required_span<S: Subscriber>(&self, ctx: &Context<'_, S>) -> bool {
+        let Some((key, value)) = &self.require_span_field else { return true };
+
+        let current = ctx.current_span().id().map(Id::into_u64);
+        self.spans.any_in_scope(current, |span| span.fields.get(key).is_some_and(|field| field.trim_matches('"') == value))
+    }
+
+    /// Whether an event's fields satisfy [`SpannerLayer::require_fields`]
+    fn has_required_fields(&self, metadata: &Metadata<'_>) -> bool {
+        if self.require_fields.is_empty() {
+            return true;
+        }
+
+        let fields = metadata.fields();
+        let mut present = self.require_fields.iter().map(|name| fields.field(name).is_some());
+        match self.require_fields_match {
+            FieldMatch::All => present.all(|present| present),
+            FieldMatch::Any => present.any(|present| present),
         }
+    }
 
-        let mut visitor = FieldVisitor { fields: &mut fields, message: &mut message };
+    fn capture_event<S: Subscriber>(&self, event: &tracing::Event<'_>, ctx: &Context<'_, S>) {
+        let mut fields = HashMap::new();
+        let mut message = String::new();
 
-        event.record(&mut visitor);
+        // Capture event fields using a visitor
+        event.record(&mut FieldVisitor { fields: &mut fields, message: Some((&self.message_field, &mut message)) });
 
         // Create event data
         let metadata = event.metadata();
-        let mut event_data = EventData::new(message, *metadata.level(), metadata.target().to_string());
+        let mut event_data = EventData::new(message, *metadata.level(), target_of(metadata));
 
         event_data.fields = fields;
         event_data.file = metadata.file().map(String::from);
         event_data.line = metadata.line();
-        event_data.module_path = metadata.module_path().map(String::from);
+        event_data.module_path = metadata.module_path().map(SharedStr::from);
+
+        if let Some(remapper) = LEVEL_REMAPPER.read().unwrap_or_else(|poisoned| poisoned.into_inner()).as_ref()
+            && let Some(level) = remapper(&event_data)
+        {
+            event_data.level = level.into();
+        }
 
         // Create the event with thread context
-        #[cfg(not(any(target_arch = "wasm32", target_arch = "wasm64")))]
-        let captured_event = Event::new(event_data)
-            .with_thread_info(format!("{:?}", std::thread::current().id()), std::thread::current().name().map(String::from))
-            .with_process_id(std::process::id())
-            .with_correlation_id(format!("corr-{}", generate_uuid_like_string()));
+        let mut captured_event =
+            with_thread_context(Event::new(event_data), self.capture_thread_info, self.capture_process_id);
 
-        #[cfg(any(target_arch = "wasm32", target_arch = "wasm64"))]
-        let captured_event = Event::new(event_data)
-            .with_thread_info("main".to_string(), Some("main".to_string()))
-            .with_process_id(0)
-            .with_correlation_id(format!("corr-{}", generate_uuid_like_string()));
+        // Attach the spans entered on this thread, or whatever the subscriber knows about
+        let mut spans = self.spans.record_event();
+        if spans.is_empty() {
+            spans.extend(ambient_span(ctx, &self.spans));
+        }
+        let root = spans.last().filter(|_| self.span_traces.is_some()).map(|current| self.spans.root_of(current));
+        if self.capture_task_names
+            && let Some(task_name) = spans.iter().rev().find_map(SpanInfo::task_name)
+        {
+            captured_event = captured_event.with_task_name(task_name.to_string());
+        }
+        if let Some(current) = spans.last().cloned() {
+            captured_event = captured_event.with_span_stack(spans).with_current_span(current);
+        }
+        if self.derive_messages && captured_event.event_data.message.is_empty() {
+            captured_event.event_data.message = captured_event.display_message();
+        }
 
-        emit(captured_event);
+        if let Some(root) = root
+            && let Ok(mut traces) = SPAN_TRACES.lock()
+        {
+            traces.live.entry(root).or_default().push(captured_event.clone());
+        }
+        self.store_event(captured_event);
     }
 
-    fn on_new_span(&self, _attrs: &tracing::span::Attributes<'_>, _id: &tracing::span::Id, _ctx: Context<'_, S>) {
-        // Could implement span tracking here for even richer context
+    fn store_event(&self, event: Event) {
+        match &self.manager {
+            Some(manager) => emit_into(manager, event),
+            None => emit(event),
+        };
     }
 
-    fn on_enter(&self, _id: &tracing::span::Id, _ctx: Context<'_, S>) {
-        // Could track span entry
+    fn check_busy_time(&self, id: &Id, busy: Duration) {
+        if self.busy_threshold.is_none_or(|threshold| busy <= threshold) {
+            return;
+        }
+        let Some(span) = self.spans.spans.read().ok().and_then(|spans| spans.get(&id.into_u64()).cloned()) else { return };
+
+        let busy_ms = busy.as_millis();
+        let mut event_data = EventData::new(
+            format!("span {} held the executor for {}ms", span.display_name(), busy_ms),
+            Level::WARN,
+            span.target.to_string(),
+        );
+        event_data.add_field("span".to_string(), span.display_name().to_string());
+        event_data.add_field("busy_ms".to_string(), busy_ms.to_string());
+
+        let stack = self.spans.snapshot();
+        let event = with_thread_context(Event::new(event_data), self.capture_thread_info, self.capture_process_id);
+        self.store_event(event.with_kind(EventKind::SpanSummary).with_span_stack(stack).with_current_span(span));
     }
+}
+
+static OVERHEAD: Mutex<OverheadHistogram> =
+    Mutex::new(OverheadHistogram { count: 0, total: Duration::ZERO, buckets: [0; 64] });
 
-    fn on_exit(&self, _id: &tracing::span::Id, _ctx: Context<'_, S>) {
-        // Could track span exit
+/// Time spent capturing events, from layers built with [`SpannerLayer::with_overhead_tracking`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OverheadStats {
+    pub count: u64,
+    pub total: Duration,
+    /// Median capture time, rounded up to a power of two nanoseconds
+    pub p50: Duration,
+    /// 99th percentile capture time, rounded up to a power of two nanoseconds
+    pub p99: Duration,
+}
+
+/// Capture time statistics across every overhead-tracking layer in the process
+pub fn capture_overhead_stats() -> OverheadStats {
+    let Ok(overhead) = OVERHEAD.lock() else { return OverheadStats::default() };
+    OverheadStats {
+        count: overhead.count,
+        total: overhead.total,
+        p50: overhead.percentile(0.5),
+        p99: overhead.percentile(0.99),
     }
 }
 
-/// Helper function to generate a simple UUID-like string
-fn generate_uuid_like_string() -> String {
-    use chrono::Utc;
-    let now = Utc::now();
-    format!("{:x}-{:x}", now.timestamp(), now.timestamp_subsec_nanos())
+/// Capture times bucketed by power of two nanoseconds
+struct OverheadHistogram {
+    count: u64,
+    total: Duration,
+    buckets: [u64; 64],
+}
+
+impl OverheadHistogram {
+    fn record(&mut self, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.buckets[63 - nanos.max(1).leading_zeros() as usize] += 1;
+        self.count += 1;
+        self.total += elapsed;
+    }
+
+    /// Upper bound of the bucket holding the given quantile
+    fn percentile(&self, quantile: f64) -> Duration {
+        let rank = ((quantile * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(u64::MAX >> (63 - bucket));
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+/// Events collected per root span by layers built with [`SpannerLayer::with_span_traces`]
+static SPAN_TRACES: Mutex<SpanTraces> = Mutex::new(SpanTraces { live: BTreeMap::new(), retained: VecDeque::new() });
+
+const MAX_RETAINED_TRACES: usize = 64;
+
+struct SpanTraces {
+    live: BTreeMap<u64, Vec<Event>>,
+    retained: VecDeque<(u64, Vec<Event>)>,
+}
+
+/// Get the events captured under a root span, oldest first.
+///
+/// Returns the events so far while the span is open, and afterwards only if its trace was
+/// retained (see [`TraceRetention`]). `root_span_id` is the root's [`SpanInfo::id`], as found
+/// on captured events, not the id `tracing` gave it.
+pub fn get_span_trace(root_span_id: u64) -> Vec<Event> {
+    let Ok(traces) = SPAN_TRACES.lock() else { return Vec::new() };
+    traces
+        .live
+        .get(&root_span_id)
+        .or_else(|| traces.retained.iter().rev().find(|(id, _)| *id == root_span_id).map(|(_, events)| events))
+        .cloned()
+        .unwrap_or_default()
+}
+
+#[derive(Default)]
+/// Live spans, plus the stack of entered span ids for each thread
+struct SpanTracker {
+    spans: RwLock<HashMap<u64, SpanInfo>>,
+    stacks: Mutex<HashMap<ThreadId, Vec<u64>>>,
+    /// When each span was last entered on each thread
+    entered_at: Mutex<HashMap<(ThreadId, u64), DateTime<Utc>>>,
+    /// Spans that have been entered at least once, to count resumes
+    entered_before: Mutex<HashSet<u64>>,
+    /// Unique id of each span's root, by tracing id, for layers collecting span traces
+    roots: Mutex<HashMap<u64, u64>>,
+    /// Tracing id of each span's parent
+    parents: Mutex<HashMap<u64, u64>>,
+    /// Closed children of live spans, by the parent's tracing id, attached when it closes
+    closed_children: Mutex<HashMap<u64, ClosedChildren>>,
+}
+
+/// Closed children waiting for their parent to close, with their descendants
+#[derive(Default)]
+struct ClosedChildren {
+    spans: Vec<SpanInfo>,
+    /// Spans in `spans` and all their subtrees
+    nodes: usize,
+}
+
+/// Most closed descendants kept in a span's tree, so each closed record holds a bounded
+/// copy of its subtree; spans beyond it are truncated there but still recorded on their own
+const MAX_CLOSED_DESCENDANTS: usize = 256;
+
+impl SpanTracker {
+    /// Count an event against the innermost span entered on the current thread, then
+    /// snapshot the entered spans, outermost first
+    fn record_event(&self) -> Vec<SpanInfo> {
+        let ids =
+            self.stacks.lock().ok().and_then(|stacks| stacks.get(&thread::current().id()).cloned()).unwrap_or_default();
+        let Ok(mut spans) = self.spans.write() else { return Vec::new() };
+        if let Some(current) = ids.last().and_then(|id| spans.get_mut(id)) {
+            current.event_count += 1;
+        }
+        ids.iter().filter_map(|id| spans.get(id).cloned()).collect()
+    }
+
+    /// The spans entered on the current thread, outermost first
+    fn snapshot(&self) -> Vec<SpanInfo> {
+        let ids =
+            self.stacks.lock().ok().and_then(|stacks| stacks.get(&thread::current().id()).cloned()).unwrap_or_default();
+        let Ok(spans) = self.spans.read() else { return Vec::new() };
+        ids.iter().filter_map(|id| spans.get(id).cloned()).collect()
+    }
+
+    /// Whether a span entered on the current thread satisfies `matches`, checked in place.
+    /// With none entered, checks the `current` span and its ancestors instead.
+    fn any_in_scope(&self, current: Option<u64>, matches: impl Fn(&SpanInfo) -> bool) -> bool {
+        let mut ids =
+            self.stacks.lock().ok().and_then(|stacks| stacks.get(&thread::current().id()).cloned()).unwrap_or_default();
+        if ids.is_empty()
+            && let Ok(parents) = self.parents.lock()
+        {
+            ids.extend(std::iter::successors(current, |id| parents.get(id).copied()));
+        }
+        let Ok(spans) = self.spans.read() else { return false };
+        ids.iter().filter_map(|id| spans.get(id)).any(matches)
+    }
+
+    /// Unique id of the outermost ancestor of a span, or of the span itself if it has no known parent
+    fn root_of(&self, span: &SpanInfo) -> u64 {
+        self.roots.lock().ok().and_then(|roots| roots.get(&span.tracing_id).copied()).unwrap_or(span.id)
+    }
+
+    /// A live span, by the id `tracing` gave it
+    fn get(&self, tracing_id: u64) -> Option<SpanInfo> {
+        self.spans.read().ok().and_then(|spans| spans.get(&tracing_id).cloned())
+    }
+
+    fn update(&self, id: &Id, f: impl FnOnce(&mut SpanInfo)) {
+        if let Some(span) = self.spans.write().ok().as_mut().and_then(|spans| spans.get_mut(&id.into_u64())) {
+            f(span);
+        }
+    }
+}
+
+/// Collects fields as `Debug` strings, splitting out the named message field when a slot is
+/// given for it
+struct FieldVisitor<'a> {
+    fields: &'a mut HashMap<String, String>,
+    message: Option<(&'a str, &'a mut String)>,
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let value_str = format!("{:?}", value);
+        match &mut self.message {
+            Some((name, message)) if field.name() == *name => **message = value_str.trim_matches('"').to_string(),
+            _ => {
+                self.fields.insert(field.name().to_string(), value_str);
+            }
+        }
+    }
+}
+
+impl<S> Layer<S> for SpannerLayer
+where
+    S: Subscriber,
+{
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        if event.metadata().target() == REPLAY_TARGET
+            || !self.has_required_fields(event.metadata())
+            || !self.in_required_span(&ctx)
+        {
+            return;
+        }
+        if !self.track_overhead {
+            return self.capture_event(event, &ctx);
+        }
+
+        let started = Utc::now();
+        self.capture_event(event, &ctx);
+        let Ok(elapsed) = (Utc::now() - started).to_std() else { return };
+        #[cfg(feature = "metrics")]
+        metrics::histogram!("spanner_capture_seconds").record(elapsed.as_secs_f64());
+        if let Ok(mut overhead) = OVERHEAD.lock() {
+            overhead.record(elapsed);
+        }
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _ctx: Context<'_, S>) {
+        let metadata = attrs.metadata();
+        if metadata.target() == INTERNAL_SPAN_TARGET {
+            return;
+        }
+
+        let mut span = SpanInfo::new(next_span_id(), metadata.name().to_string(), target_of(metadata), *metadata.level());
+        span.tracing_id = id.into_u64();
+        span.file = metadata.file().map(String::from);
+        span.line = metadata.line();
+        span.module_path = metadata.module_path().map(SharedStr::from);
+
+        let mut fields = HashMap::new();
+        attrs.record(&mut FieldVisitor { fields: &mut fields, message: None });
+        fields.into_iter().for_each(|(key, value)| span.add_field(key, value));
+
+        if let Ok(mut spans) = self.spans.spans.write() {
+            spans.insert(id.into_u64(), span);
+        }
+
+        let parent = if attrs.is_contextual() {
+            self.spans.snapshot().pop()
+        } else {
+            attrs.parent().and_then(|parent| self.spans.get(parent.into_u64()))
+        };
+        let Some(parent) = parent else { return };
+        if let Ok(mut parents) = self.spans.parents.lock() {
+            parents.insert(id.into_u64(), parent.tracing_id);
+        }
+        if self.span_traces.is_some() {
+            let root = self.spans.root_of(&parent);
+            if let Ok(mut roots) = self.spans.roots.lock() {
+                roots.insert(id.into_u64(), root);
+            }
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        let mut fields = HashMap::new();
+        values.record(&mut FieldVisitor { fields: &mut fields, message: None });
+        self.spans.update(id, |span| fields.into_iter().for_each(|(key, value)| span.add_field(key, value)));
+    }
+
+    fn on_enter(&self, id: &Id, _ctx: Context<'_, S>) {
+        let thread_id = thread::current().id();
+        if let Ok(mut stacks) = self.spans.stacks.lock() {
+            stacks.entry(thread_id).or_default().push(id.into_u64());
+        }
+        if let Ok(mut entered) = self.spans.entered_at.lock() {
+            entered.insert((thread_id, id.into_u64()), Utc::now());
+        }
+
+        // Re-entering the same span (e.g. each poll of an instrumented future) is a resume
+        let resumed = self.spans.entered_before.lock().is_ok_and(|mut entered| !entered.insert(id.into_u64()));
+        if resumed {
+            self.spans.update(id, |span| span.resume_count += 1);
+        }
+    }
+
+    fn on_exit(&self, id: &Id, _ctx: Context<'_, S>) {
+        let entered = self
+            .spans
+            .entered_at
+            .lock()
+            .ok()
+            .and_then(|mut entered| entered.remove(&(thread::current().id(), id.into_u64())));
+        if let Some(busy) = entered.and_then(|at| (Utc::now() - at).to_std().ok()) {
+            self.spans.update(id, |span| span.busy_time += busy);
+            self.check_busy_time(id, busy);
+        }
+
+        let Ok(mut stacks) = self.spans.stacks.lock() else { return };
+        let thread_id = thread::current().id();
+        if let Some(stack) = stacks.get_mut(&thread_id) {
+            if let Some(position) = stack.iter().rposition(|entered| *entered == id.into_u64()) {
+                stack.remove(position);
+            }
+            if stack.is_empty() {
+                stacks.remove(&thread_id);
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, _ctx: Context<'_, S>) {
+        if let Ok(mut entered) = self.spans.entered_before.lock() {
+            entered.remove(&id.into_u64());
+        }
+        let closed = self.spans.spans.write().ok().and_then(|mut spans| spans.remove(&id.into_u64()));
+        let Some(mut span) = closed else { return };
+        let unique_id = span.id;
+        span.exit();
+
+        // Nest the span under its parent's eventual record, if the parent is still open
+        let children = self.spans.closed_children.lock().ok().and_then(|mut c| c.remove(&id.into_u64()));
+        span.children = children.map(|children| children.spans).unwrap_or_default();
+        let parent = self.spans.parents.lock().ok().and_then(|mut parents| parents.remove(&id.into_u64()));
+        let parent_open = |parent: &u64| self.spans.spans.read().is_ok_and(|spans| spans.contains_key(parent));
+        if let Some(parent) = parent.filter(parent_open)
+            && let Ok(mut closed_children) = self.spans.closed_children.lock()
+        {
+            let siblings = closed_children.entry(parent).or_default();
+            let mut budget = MAX_CLOSED_DESCENDANTS.saturating_sub(siblings.nodes);
+            if budget > 0 {
+                let mut child = span.clone();
+                child.truncate_tree(&mut budget);
+                siblings.nodes += child.node_count();
+                siblings.spans.push(child);
+            }
+        }
+        match &self.manager {
+            Some(manager) => record_closed_span_into(manager, span),
+            None => record_closed_span(span),
+        };
+
+        if let Some(retention) = self.span_traces {
+            if let Ok(mut roots) = self.spans.roots.lock() {
+                roots.remove(&id.into_u64());
+            }
+            let Ok(mut traces) = SPAN_TRACES.lock() else { return };
+            if let Some(trace) = traces.live.remove(&unique_id)
+                && retention == TraceRetention::KeepOnError
+                && trace.iter().any(|event| event.event_data.level() == Level::ERROR)
+            {
+                if traces.retained.len() == MAX_RETAINED_TRACES {
+                    traces.retained.pop_front();
+                }
+                traces.retained.push_back((unique_id, trace));
+            }
+        }
+    }
+}
+
+/// Target stored for events and spans whose callsite has an empty target and no module path
+pub const UNKNOWN_TARGET: &str = "<unknown>";
+
+/// The callsite's target, falling back to its module path, then [`UNKNOWN_TARGET`], when it
+/// is empty (as with some macro expansions and FFI bridges), so target grouping stays usable
+fn target_of(metadata: &Metadata<'_>) -> String {
+    match metadata.target() {
+        "" => metadata.module_path().filter(|path| !path.is_empty()).unwrap_or(UNKNOWN_TARGET).to_string(),
+        target => target.to_string(),
+    }
+}
+
+/// Describe the subscriber's current span, from the tracker if it knows the span and
+/// otherwise from its metadata alone, keeping the subscriber's id
+fn ambient_span<S: Subscriber>(ctx: &Context<'_, S>, tracker: &SpanTracker) -> Option<SpanInfo> {
+    let current = ctx.current_span();
+    let (id, metadata) = (current.id()?, current.metadata()?);
+    if let Some(span) = tracker.get(id.into_u64()) {
+        return Some(span);
+    }
+
+    let mut span = SpanInfo::new(id.into_u64(), metadata.name().to_string(), target_of(metadata), *metadata.level());
+    span.file = metadata.file().map(String::from);
+    span.line = metadata.line();
+    span.module_path = metadata.module_path().map(SharedStr::from);
+    Some(span)
+}
+
+/// Attach the current thread, process, correlation id and diagnostic context to a captured event.
+/// Outside [`with_captured_context`](crate::with_captured_context) each event gets a fresh id.
+pub(crate) fn with_thread_context(mut event: Event, thread_info: bool, process_id: bool) -> Event {
+    #[cfg(not(any(target_arch = "wasm32", target_arch = "wasm64")))]
+    {
+        if thread_info {
+            let thread = std::thread::current();
+            event = event.with_thread_info(format!("{:?}", thread.id()), thread.name().map(String::from));
+        }
+        if process_id {
+            event = event.with_process_id(std::process::id());
+        }
+    }
+
+    #[cfg(any(target_arch = "wasm32", target_arch = "wasm64"))]
+    {
+        if thread_info {
+            event = event.with_thread_info("main".to_string(), Some("main".to_string()));
+        }
+        if process_id {
+            event = event.with_process_id(0);
+        }
+    }
+
+    apply_context(&mut event);
+    match current_correlation_id() {
+        Some(correlation_id) => event.with_correlation_id(correlation_id),
+        None => event.with_generated_correlation_id(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            EventManager,
+            manager::{clear_global_events, get_global_event_count, get_global_events, global_test_guard},
+        },
+        tracing::{Dispatch, Instrument, dispatcher, info, info_span, subscriber},
+    };
+
+    fn captured(target: &str) -> Vec<Event> {
+        get_global_events().unwrap_or_default().into_iter().filter(|e| e.event_data.target == target).collect()
+    }
+
+    #[test]
+    fn test_span_name_override() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        subscriber::with_default(Registry::default().with(SpannerLayer::new()), || {
+            let outer = info_span!("handler", name = "GET /users");
+            let _outer = outer.enter();
+            let inner = info_span!("query", otel.name = "SELECT users", name = "ignored");
+            let _inner = inner.enter();
+            info!(target: "spanner_test::span_name", "inside");
+        });
+
+        let events = captured("spanner_test::span_name");
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.kind, EventKind::Log);
+        assert_eq!(event.span_stack.len(), 2);
+        assert_eq!(event.span_stack[0].name, "handler");
+        assert_eq!(event.span_stack[0].display_name(), "GET /users");
+
+        let current = event.current_span.as_ref().expect("event should carry its current span");
+        assert_eq!(current.name, "query");
+        assert_eq!(current.display_name(), "SELECT users");
+        assert!(event.get_span_tree().contains("SELECT users"));
+
+        let mut manager = EventManager::new(None);
+        manager.push(event.clone());
+        assert_eq!(manager.get_by_span("GET /users").len(), 1);
+        assert_eq!(manager.get_by_span("handler").len(), 1);
+        assert_eq!(manager.get_by_span("SELECT users").len(), 1);
+    }
+
+    #[test]
+    fn test_layer_events_are_stored() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        clear_global_events();
+
+        let received = Arc::new(Mutex::new(0));
+        let counter = received.clone();
+        let _subscription = crate::events().unwrap().on(move |event| {
+            if event.event_data.target == "spanner_test::stored" {
+                *counter.lock().unwrap() += 1;
+            }
+        });
+        subscriber::with_default(Registry::default().with(SpannerLayer::new()), || {
+            info!(target: "spanner_test::stored", "kept");
+            tracing::warn!(target: "spanner_test::stored", "kept too");
+        });
+
+        // Captured events are both stored and forwarded, and outlive the subscriber
+        assert!(get_global_event_count() > 0);
+        assert_eq!(captured("spanner_test::stored").len(), 2);
+        assert_eq!(*received.lock().unwrap(), 2);
+        clear_global_events();
+    }
+
+    #[test]
+    fn test_closed_span_trees_are_bounded() {
+        let manager = Arc::new(RwLock::new(EventManager::new(None)));
+        let layer = SpannerLayer::new().with_manager(manager.clone());
+        subscriber::with_default(Registry::default().with(layer), || {
+            info_span!("root").in_scope(|| {
+                for _ in 0..20 {
+                    info_span!("child").in_scope(|| (0..20).for_each(|_| drop(info_span!("leaf").entered())));
+                }
+            });
+        });
+
+        let manager = manager.read().unwrap();
+        let root = manager.closed_spans().find(|span| span.name == "root").unwrap();
+        assert!(root.node_count() <= 2 * MAX_CLOSED_DESCENDANTS, "{} spans kept", root.node_count());
+        assert_eq!(root.children[0].children.len(), 20, "small subtrees are kept whole");
+        assert_eq!(manager.closed_spans().filter(|span| span.name == "child").count(), 20);
+    }
+
+    #[test]
+    fn test_layers_with_own_managers() {
+        let target = "spanner_test::own_manager";
+        let run = |name: &'static str| {
+            let manager = Arc::new(RwLock::new(EventManager::new(None)));
+            let layer = SpannerLayer::new().with_manager(manager.clone());
+            subscriber::with_default(Registry::default().with(layer), || {
+                info_span!("subsystem", name).in_scope(|| info!(target: "spanner_test::own_manager", name, "working"));
+            });
+            manager
+        };
+
+        let handles = ["billing", "search"].map(|name| thread::spawn(move || run(name)));
+        for (handle, name) in handles.into_iter().zip(["billing", "search"]) {
+            let manager = handle.join().unwrap();
+            let manager = manager.read().unwrap();
+            let events: Vec<&Event> = manager.iter().collect();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].event_data.fields.get("name").map(String::as_str), Some(format!("\"{name}\"").as_str()));
+            assert_eq!(manager.closed_spans().count(), 1);
+        }
+        assert!(captured(target).is_empty(), "bound layers must not write to the global manager");
+    }
+
+    #[test]
+    fn test_span_event_count() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        subscriber::with_default(Registry::default().with(SpannerLayer::new()), || {
+            let outer = info_span!("retry_loop");
+            let _outer = outer.enter();
+            for attempt in 0..4 {
+                info!(target: "spanner_test::event_count", attempt, "retrying");
+            }
+            let inner = info_span!("backoff");
+            inner.in_scope(|| info!(target: "spanner_test::event_count", "sleeping"));
+        });
+
+        let events = captured("spanner_test::event_count");
+        assert_eq!(events.len(), 5);
+
+        // Newest first: the last event fired in `backoff`, the one before it in `retry_loop`
+        assert_eq!(events[0].current_span.as_ref().unwrap().event_count, 1);
+        assert_eq!(events[0].span_stack[0].event_count, 4, "events in child spans are not counted against the parent");
+        let retry_loop = events[1].current_span.as_ref().unwrap();
+        assert_eq!(retry_loop.event_count, 4);
+        assert!(events[1].get_span_tree().contains("events=4"));
+    }
+
+    #[test]
+    fn test_busy_threshold_warns_on_long_held_span() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        let layer = SpannerLayer::new().with_busy_threshold(Duration::from_millis(20));
+        subscriber::with_default(Registry::default().with(layer), || {
+            info_span!(target: "spanner_test::busy", "quick").in_scope(|| {});
+            info_span!(target: "spanner_test::busy", "blocking").in_scope(|| thread::sleep(Duration::from_millis(40)));
+        });
+
+        let events = captured("spanner_test::busy");
+        assert_eq!(events.len(), 1, "only the long-held span is reported");
+        let warning = &events[0];
+        assert_eq!(warning.event_data.level(), Level::WARN);
+        assert_eq!(warning.kind, EventKind::SpanSummary);
+        assert!(warning.event_data.message.starts_with("span blocking held the executor for "));
+        assert!(warning.event_data.fields["busy_ms"].parse::<u64>().unwrap() >= 40);
+        assert_eq!(warning.current_span.as_ref().unwrap().name, "blocking");
+    }
+
+    /// Minimal non-`Registry` subscriber that only tracks the current span per subscriber
+    #[derive(Default)]
+    struct StackSubscriber {
+        next_id: std::sync::atomic::AtomicU64,
+        spans: Mutex<HashMap<u64, &'static tracing::Metadata<'static>>>,
+        stack: Mutex<Vec<u64>>,
+    }
+
+    impl Subscriber for StackSubscriber {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool { true }
+
+        fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+            let id = self.next_id.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            self.spans.lock().unwrap().insert(id, attrs.metadata());
+            Id::from_u64(id)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, id: &Id) { self.stack.lock().unwrap().push(id.into_u64()); }
+
+        fn exit(&self, _: &Id) { self.stack.lock().unwrap().pop(); }
+
+        fn current_span(&self) -> tracing_core::span::Current {
+            let stack = self.stack.lock().unwrap();
+            match stack.last() {
+                Some(id) => tracing_core::span::Current::new(Id::from_u64(*id), self.spans.lock().unwrap()[id]),
+                None => tracing_core::span::Current::none(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_ambient_span_fallback_without_registry() {
+        use tracing_subscriber::reload;
+
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        // The layer tracks spans itself on a plain subscriber
+        subscriber::with_default(StackSubscriber::default().with(SpannerLayer::new()), || {
+            info_span!("tracked", id = 7).in_scope(|| info!(target: "spanner_test::no_registry", "tracked"));
+        });
+
+        // A span created before the layer was installed is only known through `current_span`
+        let (layer, handle) = reload::Layer::new(None::<SpannerLayer>);
+        subscriber::with_default(StackSubscriber::default().with(layer), || {
+            let span = info_span!("preexisting", id = 8);
+            let _entered = span.enter();
+            handle.reload(Some(SpannerLayer::new())).unwrap();
+            info!(target: "spanner_test::no_registry", "ambient");
+        });
+
+        let events = captured("spanner_test::no_registry");
+        assert_eq!(events.len(), 2);
+
+        let ambient = events[0].current_span.as_ref().expect("fallback should capture the current span");
+        assert_eq!(ambient.name, "preexisting");
+        assert!(ambient.fields.is_empty(), "only metadata is available for spans the layer never saw");
+
+        let tracked = events[1].current_span.as_ref().unwrap();
+        assert_eq!(tracked.name, "tracked");
+        assert_eq!(tracked.fields["id"], "7");
+    }
+
+    #[test]
+    fn test_closed_spans_are_recorded() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        subscriber::with_default(Registry::default().with(SpannerLayer::new()), || {
+            for _ in 0..3 {
+                info_span!("spanner_test_closed").in_scope(|| {
+                    info!(target: "spanner_test::closed", "working");
+                    thread::sleep(Duration::from_millis(2));
+                });
+            }
+        });
+
+        let percentiles = crate::get_span_duration_percentiles("spanner_test_closed", &[50.0]);
+        assert_eq!(percentiles.len(), 1);
+        assert!(percentiles[0].1 >= Duration::from_millis(2));
+        assert!(crate::get_span_duration_percentiles("emit", &[50.0]).is_empty(), "internal spans are not recorded");
+    }
+
+    #[test]
+    fn test_spans_without_events_are_recorded() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        subscriber::with_default(Registry::default().with(SpannerLayer::new()), || {
+            info!(target: "spanner_test::third_party", "before");
+            // A dependency's span that never encloses any of our events
+            tracing::debug_span!(target: "sqlx::query", "spanner_test_query", rows = 1500, db.statement = "SELECT 1")
+                .in_scope(|| thread::sleep(Duration::from_millis(2)));
+            info!(target: "spanner_test::third_party", "after");
+        });
+
+        let span = crate::get_all_spans()
+            .into_iter()
+            .find(|span| span.name == "spanner_test_query")
+            .expect("the closed span was recorded");
+        assert_eq!(span.target, "sqlx::query");
+        assert_eq!(span.event_count, 0);
+        assert_eq!(span.field_as_f64("rows"), Some(1500.0));
+        assert_eq!(span.fields.get("db.statement").map(String::as_str), Some("\"SELECT 1\""));
+        assert!(span.duration.is_some_and(|duration| duration >= Duration::from_millis(2)));
+    }
+
+    #[test]
+    fn test_resumed_span_is_coalesced() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        subscriber::with_default(Registry::default().with(SpannerLayer::new()), || {
+            let task = info_span!("poll_loop");
+            for _ in 0..4 {
+                task.in_scope(|| thread::sleep(Duration::from_millis(2)));
+            }
+            task.in_scope(|| info!(target: "spanner_test::resume", "done"));
+        });
+
+        let events = captured("spanner_test::resume");
+        let span = events[0].current_span.as_ref().unwrap();
+        assert_eq!(span.resume_count, 4, "five entries of the same span make one SpanInfo with four resumes");
+        assert!(span.busy_time >= Duration::from_millis(8));
+        assert!(events[0].get_span_tree().contains("resumes=4"));
+    }
+
+    /// Hands out the same span id every time, as a subscriber may once a span has closed
+    struct ReusedIds;
+
+    impl Subscriber for ReusedIds {
+        fn enabled(&self, _: &Metadata<'_>) -> bool { true }
+
+        fn new_span(&self, _: &Attributes<'_>) -> Id { Id::from_u64(1) }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+
+        fn try_close(&self, _: Id) -> bool { true }
+    }
+
+    #[test]
+    fn test_reused_span_ids_stay_distinct() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        let target = "spanner_test::reused_ids";
+
+        subscriber::with_default(ReusedIds.with(SpannerLayer::new()), || {
+            info_span!("first").in_scope(|| info!(target: "spanner_test::reused_ids", "one"));
+            info_span!("second").in_scope(|| info!(target: "spanner_test::reused_ids", "two"));
+        });
+        let events = captured(target);
+        let [second, first] = events.as_slice() else { panic!("expected two events") };
+        let (first, second) = (first.current_span.as_ref().unwrap(), second.current_span.as_ref().unwrap());
+
+        assert_eq!((first.tracing_id, second.tracing_id), (1, 1), "the subscriber reused the id");
+        assert_ne!(first.id, second.id);
+        assert_eq!((first.name.as_ref(), second.name.as_ref()), ("first", "second"));
+        let closed: Vec<u64> = crate::get_all_spans().iter().take(2).map(|span| span.id).collect();
+        assert_eq!(closed, [second.id, first.id]);
+    }
+
+    #[test]
+    fn test_required_span_field() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        let target = "spanner_test::span_field";
+
+        let layer = SpannerLayer::new().with_required_span_field("debug", "true");
+        subscriber::with_default(Registry::default().with(layer), || {
+            info_span!("request", debug = true).in_scope(|| {
+                info_span!("query").in_scope(|| info!(target: "spanner_test::span_field", "flagged"));
+            });
+            info_span!("request", debug = false).in_scope(|| info!(target: "spanner_test::span_field", "unflagged"));
+            info_span!("request").in_scope(|| info!(target: "spanner_test::span_field", "no flag"));
+            info!(target: "spanner_test::span_field", "outside");
+        });
+
+        let messages: Vec<_> = captured(target).into_iter().map(|e| e.event_data.message).collect();
+        assert_eq!(messages, ["flagged"]);
+    }
+
+    #[tracing::instrument]
+    fn handle_order(order_id: u64) { load_items(order_id) }
+
+    #[tracing::instrument]
+    fn load_items(order_id: u64) { info!(target: "spanner_test::instrument", "loading items") }
+
+    #[test]
+    fn test_instrumented_span_hierarchy() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        subscriber::with_default(Registry::default().with(SpannerLayer::new()), || handle_order(7));
+        let event = &captured("spanner_test::instrument")[0];
+        let names: Vec<&str> = event.span_stack.iter().map(|span| span.name.as_str()).collect();
+        assert_eq!(names, ["handle_order", "load_items"]);
+        assert_eq!(event.current_span.as_ref().map(|span| span.name.as_str()), Some("load_items"));
+        let tree = event.get_span_tree();
+        assert!(tree.contains("├─ handle_order (INFO)") && tree.contains("\n  ├─ load_items (INFO)"), "{tree}");
+
+        let closed = crate::get_all_spans();
+        let order = closed.iter().find(|span| span.name == "handle_order").unwrap();
+        assert!(order.duration.is_some());
+        let children: Vec<&str> = order.children.iter().map(|span| span.name.as_str()).collect();
+        assert_eq!(children, ["load_items"]);
+        assert_eq!(order.children[0].fields.get("order_id").map(String::as_str), Some("7"));
+    }
+
+    #[test]
+    fn test_span_traces_per_root() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        let dispatch =
+            Dispatch::new(Registry::default().with(SpannerLayer::new().with_span_traces(TraceRetention::KeepOnError)));
+
+        let requests: Vec<_> = ["ok", "failed"]
+            .into_iter()
+            .map(|outcome| {
+                let dispatch = dispatch.clone();
+                std::thread::spawn(move || {
+                    dispatcher::with_default(&dispatch, || {
+                        let request = info_span!("request", outcome);
+                        request.in_scope(|| {
+                            info!(target: "spanner_test::trace", outcome, "start");
+                            let id = captured("spanner_test::trace")
+                                .into_iter()
+                                .find(|e| e.event_data.fields.get("outcome").is_some_and(|v| v.contains(outcome)))
+                                .map(|e| e.span_stack[0].id)
+                                .unwrap();
+                            info_span!("query").in_scope(|| info!(target: "spanner_test::trace", outcome, "in query"));
+                            if outcome == "failed" {
+                                tracing::error!(target: "spanner_test::trace", outcome, "gave up");
+                            }
+                            assert_eq!(get_span_trace(id).len(), 2 + usize::from(outcome == "failed"));
+                            id
+                        })
+                    })
+                })
+            })
+            .collect();
+        let ids: Vec<_> = requests.into_iter().map(|handle| handle.join().unwrap()).collect();
+
+        // Only the request with an error was kept once its root span closed
+        assert!(get_span_trace(ids[0]).is_empty());
+        let failed = get_span_trace(ids[1]);
+        let messages: Vec<_> = failed.iter().map(|e| e.event_data.message.as_str()).collect();
+        assert_eq!(messages, ["start", "in query", "gave up"]);
+        assert!(failed.iter().all(|e| e.event_data.fields.get("outcome").map(String::as_str) == Some("\"failed\"")));
+    }
+
+    #[test]
+    fn test_empty_message_fallback() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        let target = "spanner_test::empty_message";
+
+        subscriber::with_default(Registry::default().with(SpannerLayer::new()), || {
+            info!(target: "spanner_test::empty_message", user_id = 5, action = "login");
+            info_span!("handler").in_scope(|| info!(target: "spanner_test::empty_message", ""));
+        });
+        let events = captured(target);
+        let [in_span, with_fields] = events.as_slice() else { panic!("expected two events") };
+        assert_eq!(with_fields.event_data.message, "", "the captured message is left alone by default");
+        assert!(with_fields.get_full_context().starts_with("Event: action=login (INFO)\n"));
+        assert!(with_fields.notification_summary(200).ends_with(": action=login"));
+        assert_eq!(in_span.display_message(), "in span handler");
+
+        clear_global_events();
+        subscriber::with_default(Registry::default().with(SpannerLayer::new().with_derived_messages()), || {
+            info!(target: "spanner_test::empty_message", user_id = 5);
+        });
+        assert_eq!(captured(target)[0].event_data.message, "user_id=5");
+    }
+
+    #[test]
+    fn test_custom_message_field() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        let target = "spanner_test::message_field";
+
+        subscriber::with_default(Registry::default().with(SpannerLayer::new().with_message_field("msg")), || {
+            info!(target: "spanner_test::message_field", msg = "checkout failed", message = "raw payload");
+            info!(target: "spanner_test::message_field", order_id = 7);
+        });
+        let events = captured(target);
+        let [without, with] = events.as_slice() else { panic!("expected two events") };
+
+        assert_eq!(with.event_data.message, "checkout failed");
+        assert_eq!(with.event_data.fields.get("message").map(String::as_str), Some("\"raw payload\""));
+        assert!(!with.event_data.fields.contains_key("msg"));
+        assert_eq!(without.event_data.message, "", "no message field and no derived messages");
+        assert_eq!(without.display_message(), "order_id=7");
+    }
+
+    #[test]
+    fn test_task_names() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        let target = "spanner_test::task";
+
+        subscriber::with_default(Registry::default().with(SpannerLayer::new().with_task_names()), || {
+            let task = async {
+                info_span!("handle").in_scope(|| info!(target: "spanner_test::task", "in task"));
+            };
+            futures::executor::block_on(task.instrument(info_span!("runtime.spawn", task.name = "flush_cache")));
+            info!(target: "spanner_test::task", "outside");
+        });
+
+        let mut manager = EventManager::new(None);
+        captured(target).into_iter().for_each(|event| manager.push(event));
+        let in_task: Vec<_> =
+            manager.get_by_task_name("flush_cache").iter().map(|e| e.event_data.message.as_str()).collect();
+        assert_eq!(in_task, ["in task"]);
+        assert_eq!(manager.get_by_target(target).iter().filter(|e| e.task_name.is_none()).count(), 1);
+    }
+
+    #[test]
+    fn test_empty_target_is_normalized() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        subscriber::with_default(Registry::default().with(SpannerLayer::new()), || {
+            tracing::event!(target: "", Level::INFO, marker = "spanner_test_empty_target", "no target");
+        });
+
+        let stored: Vec<_> = get_global_events()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|e| e.event_data.fields.contains_key("marker"))
+            .collect();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].event_data.target, module_path!());
+
+        let mut manager = EventManager::new(None);
+        manager.push(stored[0].clone());
+        assert_eq!(manager.get_by_target(module_path!()).len(), 1);
+
+        // Without a module path either, only the sentinel is left
+        struct Bridged;
+        impl tracing_core::Callsite for Bridged {
+            fn set_interest(&self, _: tracing_core::Interest) {}
+
+            fn metadata(&self) -> &Metadata<'_> { unreachable!() }
+        }
+        static BRIDGED: Bridged = Bridged;
+        let unknown = tracing_core::Metadata::new(
+            "bridged",
+            "",
+            Level::INFO,
+            None,
+            None,
+            None,
+            tracing_core::field::FieldSet::new(&[], tracing_core::identify_callsite!(&BRIDGED)),
+            tracing_core::metadata::Kind::EVENT,
+        );
+        assert_eq!(target_of(&unknown), UNKNOWN_TARGET);
+    }
+
+    #[test]
+    fn test_required_fields() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        let emit_all = || {
+            info!(target: "spanner_test::required", user_id = 1, order_id = 7, "checkout");
+            info!(target: "spanner_test::required", user_id = 1, "login");
+            info!(target: "spanner_test::required", cache_hits = 3, "cache stats");
+        };
+        let messages = |layer: SpannerLayer| {
+            clear_global_events();
+            subscriber::with_default(Registry::default().with(layer), emit_all);
+            let mut messages: Vec<_> =
+                captured("spanner_test::required").into_iter().map(|e| e.event_data.message).collect();
+            messages.sort();
+            messages
+        };
+
+        assert_eq!(messages(SpannerLayer::new().with_required_fields(["user_id"], FieldMatch::All)), ["checkout", "login"]);
+        assert_eq!(messages(SpannerLayer::new().with_required_fields(["user_id", "order_id"], FieldMatch::All)), [
+            "checkout"
+        ]);
+        assert_eq!(messages(SpannerLayer::new().with_required_fields(["order_id", "cache_hits"], FieldMatch::Any)), [
+            "cache stats",
+            "checkout"
+        ]);
+        assert_eq!(messages(SpannerLayer::new()).len(), 3);
+    }
+
+    #[test]
+    fn test_thread_and_process_info_can_be_disabled() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        let layer = SpannerLayer::new();
+        assert!(layer.capture_thread_info && layer.capture_process_id, "captured by default");
+        subscriber::with_default(Registry::default().with(layer), || info!(target: "spanner_test::full_info", "full"));
+        subscriber::with_default(
+            Registry::default().with(SpannerLayer::new().without_thread_info()),
+            || info!(target: "spanner_test::no_thread", "no thread"),
+        );
+        subscriber::with_default(
+            Registry::default().with(SpannerLayer::new().without_process_id()),
+            || info!(target: "spanner_test::no_process", "no process"),
+        );
+
+        let full = &captured("spanner_test::full_info")[0];
+        assert!(full.thread_id.is_some() && full.process_id.is_some());
+        let no_thread = &captured("spanner_test::no_thread")[0];
+        assert_eq!((&no_thread.thread_id, &no_thread.thread_name), (&None, &None));
+        assert!(no_thread.process_id.is_some());
+        let no_process = &captured("spanner_test::no_process")[0];
+        assert!(no_process.thread_id.is_some());
+        assert_eq!(no_process.process_id, None);
+    }
+
+    #[test]
+    fn test_panic_capture() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        install_panic_capture();
+
+        let result = std::thread::Builder::new()
+            .name("doomed".to_string())
+            .spawn(|| panic!("spanner_test panic {}", 42))
+            .unwrap()
+            .join();
+        assert!(result.is_err());
+
+        let event = captured("panic")
+            .into_iter()
+            .find(|e| e.event_data.message == "spanner_test panic 42")
+            .expect("panic was captured");
+        assert_eq!(event.event_data.level(), Level::ERROR);
+        assert_eq!(event.kind, EventKind::Panic);
+        assert_eq!(event.event_data.file.as_deref(), Some(file!()));
+        assert!(event.event_data.line.is_some());
+        assert_eq!(event.thread_name.as_deref(), Some("doomed"));
+    }
+
+    #[test]
+    fn test_overhead_tracking() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+
+        let before = capture_overhead_stats();
+        subscriber::with_default(Registry::default().with(SpannerLayer::new()), || info!("untracked"));
+        assert_eq!(capture_overhead_stats().count, before.count, "tracking is off by default");
+
+        subscriber::with_default(Registry::default().with(SpannerLayer::new().with_overhead_tracking()), || {
+            for index in 0..10 {
+                info!(target: "spanner_test::overhead", index, "tracked");
+            }
+        });
+
+        let after = capture_overhead_stats();
+        assert_eq!(after.count, before.count + 10);
+        assert!(after.total > before.total);
+        assert!(after.p50 > Duration::ZERO && after.p50 <= after.p99);
+    }
+
+    #[test]
+    fn test_level_remapper() {
+        let _guard = global_test_guard();
+        init_global_event_manager();
+        set_level_remapper(|data| {
+            (data.target == "spanner_test::remap" && data.level() == Level::INFO).then_some(Level::WARN)
+        });
+
+        subscriber::with_default(Registry::default().with(SpannerLayer::new()), || {
+            info!(target: "spanner_test::remap", "really a warning");
+            tracing::debug!(target: "spanner_test::remap", "left alone");
+        });
+        set_level_remapper(|_| None);
+
+        let levels: Vec<_> = captured("spanner_test::remap").iter().map(|e| e.event_data.level()).collect();
+        assert_eq!(levels, [Level::DEBUG, Level::WARN], "newest first");
+    }
 }